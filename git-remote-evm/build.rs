@@ -43,6 +43,20 @@ fn build_on_chain(project_root: &Path) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+// Lets the helper tell which ABI it was built against
+fn hash_contract_abi(project_root: &Path) -> Result<String, Box<dyn Error>> {
+    use sha2::{Digest, Sha256};
+
+    let artifact_path =
+        project_root.join("on-chain/artifacts/contracts/GitRepository.sol/GitRepository.json");
+    let artifact: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(artifact_path)?)?;
+    let abi = artifact.get("abi").ok_or("artifact has no abi")?;
+
+    let hash = Sha256::digest(serde_json::to_string(abi)?.as_bytes());
+    Ok(hash.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
 fn exit_with_error(message: &str, error: Box<dyn Error>) -> ! {
     eprintln!("{}: {}", message, error);
     std::process::exit(1);
@@ -64,4 +78,9 @@ fn main() {
         Ok(_) => println!("on-chain contract(s) built"),
         Err(e) => exit_with_error("failed to build on-chain contract(s)", e),
     }
+
+    match hash_contract_abi(project_root) {
+        Ok(hash) => println!("cargo:rustc-env=CONTRACT_ABI_HASH={}", hash),
+        Err(e) => exit_with_error("failed to hash contract abi", e),
+    }
 }
@@ -1,3 +1,4 @@
+use crate::core::address::verify_checksum;
 use regex::Regex;
 use std::error::Error;
 use std::fmt;
@@ -11,6 +12,11 @@ const INVALID_REF_NAME_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r"(^\.)|(^/)|(\.\.)|([:?\[\\^~\s*])|(\.lock$)|(/$)|(@\{)|([\x00-\x1f])")
         .expect("failed to create invalid ref name regex")
 });
+// Full ref paths are also checked per component, see git check-ref-format
+static INVALID_REF_PATH_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(/\.)|(//)|(\.lock/)|(\.$)|(^@$)|(\x7f)")
+        .expect("failed to create invalid ref path regex")
+});
 
 const EXECUTABLE_PREFIX: &str = "git-remote-";
 
@@ -67,6 +73,10 @@ impl Args {
             }
             3 => {
                 let address_str = address_from_arg(&args[2], &protocol)?;
+                verify_checksum(address_str).map_err(|_| ArgsError {
+                    what: "address checksum".to_string(),
+                    value: address_str.to_string(),
+                })?;
                 let address_str = address_str.strip_prefix("0x").ok_or(ArgsError {
                     what: "address".to_string(),
                     value: address_str.to_string(),
@@ -258,6 +268,47 @@ fn test_validate_remote_name() {
     }
 }
 
+// For full names such as refs/heads/main, which end up on-chain when pushed
+pub fn validate_ref_name(name: &str) -> bool {
+    validate_remote_name(name) && !INVALID_REF_PATH_REGEX.is_match(name)
+}
+
+#[test]
+fn test_validate_ref_name() {
+    let invalid_names = vec![
+        "",                     // Invalid (empty)
+        "refs/heads/my branch", // Invalid (space)
+        "refs/heads/a..b",      // Invalid (contains ..)
+        "refs/heads/.hidden",   // Invalid (component starts with '.')
+        "refs//heads/main",     // Invalid (empty component)
+        "refs/heads.lock/main", // Invalid (component ends with .lock)
+        "refs/heads/main.",     // Invalid (ends with '.')
+        "refs/heads/main/",     // Invalid (ends with '/')
+        "refs/heads/a:b",       // Invalid (colon)
+        "refs/heads/a\x7fb",    // Invalid (control character DEL)
+        "@",                    // Invalid (only '@')
+    ];
+    for name in invalid_names {
+        if validate_ref_name(name) {
+            panic!("expected invalid ref name: {}", name);
+        }
+    }
+
+    let valid_names = vec![
+        "HEAD",
+        "refs/heads/main",
+        "refs/heads/feature/a.b",
+        "refs/tags/v1.0",
+        "refs/notes/commits",
+        "refs/heads/@",
+    ];
+    for name in valid_names {
+        if !validate_ref_name(name) {
+            panic!("expected valid ref name: {}", name);
+        }
+    }
+}
+
 fn validate_address(address: &str) -> bool {
     EVM_ADDRESS_REGEX.is_match(address)
 }
@@ -351,7 +402,22 @@ fn test_parse() {
         address_no_prefix.to_lowercase()
     );
 
-    // Case 4: argc < 2
+    // Case 4: mixed-case address with an invalid checksum
+    let cmd_args = vec![
+        executable.to_string(),
+        remote_name.to_string(),
+        "eth://0xc0ffee254729296a45a3885639AC7E10F9d5497A".to_string(),
+    ];
+    let err = Args::parse(&cmd_args, git_dir.clone()).expect_err("expected error");
+    assert_eq!(
+        err,
+        ArgsError {
+            what: "address checksum".to_string(),
+            value: "0xc0ffee254729296a45a3885639AC7E10F9d5497A".to_string(),
+        }
+    );
+
+    // Case 5: argc < 2
     let cmd_args = vec![executable.to_string()];
     let err = Args::parse(&cmd_args, git_dir.clone()).expect_err("expected error");
     assert_eq!(
@@ -1,5 +1,8 @@
 use log::{debug, info};
 #[cfg(test)]
+use mockall::predicate::eq;
+use std::collections::HashMap;
+#[cfg(test)]
 use std::io::BufReader;
 #[cfg(test)]
 use std::io::Cursor;
@@ -8,15 +11,32 @@ use std::str::FromStr;
 
 mod error;
 
-use crate::core::reference::Fetch;
-#[cfg(test)]
-use crate::core::reference::Keys;
+use crate::args::validate_ref_name;
+use crate::core::fast_export::ExportStream;
+use crate::core::reference::{Fetch, Keys, Reference};
 #[cfg(test)]
 use crate::core::remote_helper::MockRemoteHelper;
 use crate::core::remote_helper::RemoteHelper;
+#[cfg(test)]
+use crate::core::remote_helper::error::RemoteHelperError;
+#[cfg(test)]
+use crate::core::remote_helper::{
+    evm::{Evm, Settings},
+    executor::{Fetched, MockExecutor},
+};
+#[cfg(test)]
+use crate::core::{
+    git::Git,
+    git::memory::{InMemoryGit, commit_objects},
+};
 use crate::core::{hash::Hash, reference::Push};
+use crate::macros;
 use error::CLIError;
+#[cfg(test)]
+use std::rc::Rc;
 
+// git never mixes fetches and pushes in one batch, so a line that would is fatal:
+// the streams are out of sync and any reply could be taken as the batch's answer
 #[derive(Default, PartialEq)]
 enum State {
     #[default]
@@ -32,6 +52,10 @@ pub struct CLI<'a> {
     stdout: &'a mut dyn Write,
 
     state: State,
+    // Set by `option cas` for --force-with-lease, consumed by the push of the same reference
+    leases: HashMap<String, Hash>,
+    // Set by `option push-option` for --push-option, handed over with the next push batch
+    push_options: Vec<String>,
 }
 
 impl<'a> CLI<'a> {
@@ -45,6 +69,8 @@ impl<'a> CLI<'a> {
             stdin,
             stdout,
             state: State::None,
+            leases: HashMap::new(),
+            push_options: Vec::new(),
         }
     }
 
@@ -60,6 +86,10 @@ impl<'a> CLI<'a> {
     fn do_push(&mut self, pushes: Vec<Push>) -> Result<(), CLIError> {
         info!("push: {:?}", pushes);
 
+        if !self.push_options.is_empty() {
+            self.remote_helper
+                .set_push_options(std::mem::take(&mut self.push_options));
+        }
         let result = self.remote_helper.push(pushes.clone());
         for reference in pushes {
             match &result {
@@ -87,8 +117,25 @@ impl<'a> CLI<'a> {
         };
     }
 
+    fn do_export(&mut self, stream: ExportStream) -> Result<(), CLIError> {
+        let names = stream.references();
+        info!("export: {:?}", names);
+
+        let result = self.remote_helper.export(stream);
+        for name in names {
+            match &result {
+                Ok(_) => writeln!(self.stdout, "ok {}", name)?,
+                Err(e) => writeln!(self.stdout, "error {} {:?}", name, e.to_string())?,
+            }
+        }
+        writeln!(self.stdout)?;
+
+        result.map_err(|e| e.into())
+    }
+
     fn handle_line(&mut self, line: String) -> Result<(), CLIError> {
-        if line == "\n" {
+        // git may terminate lines with \r\n on some platforms
+        if line.trim_end_matches(['\r', '\n']).is_empty() {
             match std::mem::take(&mut self.state) {
                 State::None => return Err(CLIError::EndOfInput),
                 State::ListingFetches(fetches) => return self.do_fetch(fetches),
@@ -114,17 +161,20 @@ impl<'a> CLI<'a> {
 
                 response = format!("{}\n", self.remote_helper.capabilities().join("\n"));
             }
+            // A trailing glob is not part of the protocol, it filters the refs when run by hand
             "list" => {
-                let is_for_push = match args.len() {
-                    0 => false,
-                    1 => match args[0] {
-                        "for-push" => true,
-                        _ => return Err(CLIError::InvalidArgument(args[0].to_string())),
-                    },
+                let (is_for_push, pattern) = match args.as_slice() {
+                    [] => (false, None),
+                    ["for-push"] => (true, None),
+                    ["for-push", pattern] => (true, Some(*pattern)),
+                    [pattern] => (false, Some(*pattern)),
                     _ => return Err(CLIError::MalformedLine(line)),
                 };
 
                 for reference in self.remote_helper.list(is_for_push)? {
+                    if pattern.is_some_and(|pattern| !matches_pattern(&reference, pattern)) {
+                        continue;
+                    }
                     response.push_str(&format!("{}\n", reference));
                 }
             }
@@ -169,7 +219,12 @@ impl<'a> CLI<'a> {
 
                 let local = parts[0].to_string();
                 let remote = parts[1].to_string();
-                let reference = Push::new(local, remote, is_force);
+                // Stored on-chain as is, a malformed name would break every later list
+                if !validate_ref_name(&remote) {
+                    return Err(CLIError::InvalidArgument(remote));
+                }
+                let mut reference = Push::new(local, remote, is_force);
+                reference.expected_remote = self.leases.remove(&reference.remote);
 
                 match &mut self.state {
                     State::None => {
@@ -183,6 +238,76 @@ impl<'a> CLI<'a> {
                     State::ListingFetches(_) => return Err(CLIError::IllegalState(line)),
                 }
             }
+            "export" => {
+                if !args.is_empty() {
+                    return Err(CLIError::MalformedLine(line));
+                }
+                if self.state != State::None {
+                    return Err(CLIError::IllegalState(line));
+                }
+
+                let stream = ExportStream::read(self.stdin)?;
+                return self.do_export(stream);
+            }
+            "option" => {
+                // Push options are free text, so the value is the rest of the line
+                let (name, value) = match line
+                    .trim_end_matches(['\r', '\n'])
+                    .splitn(3, ' ')
+                    .collect::<Vec<_>>()
+                    .as_slice()
+                {
+                    [_, name, value] => (name.to_string(), value.to_string()),
+                    _ => return Err(CLIError::MalformedLine(line)),
+                };
+
+                response = match name.as_str() {
+                    "verbosity" => {
+                        let verbosity: u32 = value
+                            .parse()
+                            .map_err(|_| CLIError::InvalidArgument(value.clone()))?;
+                        macros::set_quiet(verbosity == 0);
+                        "ok".to_string()
+                    }
+                    "cas" => {
+                        let (name, expected) = parse_lease(&value)?;
+                        debug!("lease on {}: {}", name, expected);
+                        self.leases.insert(name, expected);
+                        "ok".to_string()
+                    }
+                    "push-option" => {
+                        debug!("push option: {:?}", value);
+                        self.push_options.push(value);
+                        "ok".to_string()
+                    }
+                    _ => match self.remote_helper.set_option(&name, &value) {
+                        Ok(true) => "ok".to_string(),
+                        Ok(false) => "unsupported".to_string(),
+                        Err(e) => format!("error {}", e),
+                    },
+                };
+            }
+            // Only sent when advertised, which it isn't, a remote can't speak git's wire protocol.
+            // Falling back makes git use the commands above instead of aborting.
+            "connect" | "stateless-connect" => {
+                if args.len() != 1 {
+                    return Err(CLIError::MalformedLine(line));
+                }
+                if self.state != State::None {
+                    return Err(CLIError::IllegalState(line));
+                }
+
+                debug!("no connection to {}, falling back", args[0]);
+                response = "fallback".to_string();
+            }
+            // Not part of the remote helper protocol, used to validate the remote's setup
+            "check" => {
+                if !args.is_empty() {
+                    return Err(CLIError::MalformedLine(line));
+                }
+
+                self.remote_helper.check()?;
+            }
             _ => return Err(CLIError::UnknownCommand(line)),
         }
 
@@ -215,6 +340,43 @@ impl<'a> CLI<'a> {
     }
 }
 
+// Attributes like the object format apply to the whole listing, so they're always kept
+fn matches_pattern(reference: &Reference, pattern: &str) -> bool {
+    match reference {
+        Reference::Normal { name, .. }
+        | Reference::Peeled { name, .. }
+        | Reference::Symbolic { name, .. } => glob_match(pattern.as_bytes(), name.as_bytes()),
+        Reference::KeyValue {
+            key: Keys::Unborn, ..
+        } => glob_match(pattern.as_bytes(), b"HEAD"),
+        Reference::KeyValue { .. } => true,
+    }
+}
+
+// Supports `*` (any run of characters, including `/`) and `?` (a single character)
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            glob_match(&pattern[1..], name) || (!name.is_empty() && glob_match(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => glob_match(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => glob_match(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+// <refname>:<expected hash>, quoted if git thought it was necessary
+fn parse_lease(value: &str) -> Result<(String, Hash), CLIError> {
+    let unquoted = value.trim_matches('"');
+    let (name, expected) = unquoted
+        .split_once(':')
+        .ok_or(CLIError::InvalidArgument(value.to_string()))?;
+    let expected =
+        Hash::from_str(expected).map_err(|_| CLIError::InvalidArgument(value.to_string()))?;
+    Ok((name.to_string(), expected))
+}
+
 #[test]
 fn test_capabilities() {
     let mut stdin = BufReader::new(Cursor::new(b"capabilities\n\n".to_vec()));
@@ -230,6 +392,121 @@ fn test_capabilities() {
     assert_eq!(stdout, b"*fetch\n*push\n\n");
 }
 
+#[test]
+fn test_capabilities_crlf() {
+    let mut stdin = BufReader::new(Cursor::new(b"capabilities\r\n\r\n".to_vec()));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper
+        .expect_capabilities()
+        .times(1)
+        .returning(|| vec!["*fetch", "*push"]);
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    cli.run().expect("failed to run cli");
+    assert_eq!(stdout, b"*fetch\n*push\n\n");
+}
+
+#[test]
+fn test_push_crlf() {
+    let mut stdin = BufReader::new(Cursor::new(
+        b"push refs/heads/main:refs/heads/main\r\n\r\n".to_vec(),
+    ));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper
+        .expect_push()
+        .with(eq(vec![Push::new(
+            "refs/heads/main".to_string(),
+            "refs/heads/main".to_string(),
+            false,
+        )]))
+        .times(1)
+        .returning(|_| Ok(()));
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    cli.run().expect("failed to run cli");
+    assert_eq!(stdout, b"ok refs/heads/main\n\n");
+}
+
+#[test]
+fn test_push_ref_update_failure() {
+    let mut stdin = BufReader::new(Cursor::new(b"push refs/heads/main:HEAD\n\n".to_vec()));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper.expect_push().returning(|_| {
+        Err(RemoteHelperError::RefUpdateFailed {
+            name: "HEAD".to_string(),
+            details: "reverted".to_string(),
+        })
+    });
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    cli.run().expect_err("should fail");
+    assert_eq!(
+        String::from_utf8(stdout).expect("should be utf-8"),
+        "error HEAD \"objects stored, ref update failed for HEAD: reverted\"\n\n"
+    );
+}
+
+#[test]
+fn test_push_lease() {
+    let hash = Hash::from_data(b"expected", true).expect("should be set");
+    let input = format!(
+        "option cas refs/heads/main:{}\npush +refs/heads/main:refs/heads/main\npush refs/heads/dev:refs/heads/dev\n\n",
+        hash
+    );
+    let mut stdin = BufReader::new(Cursor::new(input.into_bytes()));
+    let mut stdout = Vec::new();
+
+    let mut main = Push::new(
+        "refs/heads/main".to_string(),
+        "refs/heads/main".to_string(),
+        true,
+    );
+    main.expected_remote = Some(hash);
+    let dev = Push::new(
+        "refs/heads/dev".to_string(),
+        "refs/heads/dev".to_string(),
+        false,
+    );
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper
+        .expect_push()
+        .with(eq(vec![main, dev]))
+        .times(1)
+        .returning(|_| Ok(()));
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    cli.run().expect("failed to run cli");
+    assert_eq!(stdout, b"ok\nok refs/heads/main\nok refs/heads/dev\n\n");
+
+    let mut stdin = BufReader::new(Cursor::new(b"option cas refs/heads/main\n\n".to_vec()));
+    let mut stdout = Vec::new();
+    let mut cli = CLI::new(Box::new(MockRemoteHelper::new()), &mut stdin, &mut stdout);
+    cli.run()
+        .expect_err("should fail because of a lease without a hash");
+}
+
+#[test]
+fn test_push_invalid_ref_name() {
+    let mut stdin = BufReader::new(Cursor::new(
+        b"push refs/heads/main:refs/heads/a..b\n\n".to_vec(),
+    ));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper.expect_push().never();
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    let err = cli.run().expect_err("should fail because of the ref name");
+    assert!(matches!(err, CLIError::InvalidArgument(name) if name == "refs/heads/a..b"));
+    assert!(stdout.is_empty());
+}
+
 #[test]
 fn test_list() {
     // Case 1: No refs
@@ -248,7 +525,6 @@ fn test_list() {
     let mut stdin = BufReader::new(Cursor::new(b"list\n\n".to_vec()));
     let mut stdout = Vec::new();
 
-    use crate::core::reference::Reference;
     let refs = vec![
         Reference::Normal {
             name: "refs/heads/main".to_string(),
@@ -277,3 +553,497 @@ fn test_list() {
         format!("{}\n{}\n{}\n\n", refs[0], refs[1], refs[2]).as_bytes()
     );
 }
+
+#[test]
+fn test_check() {
+    let mut stdin = BufReader::new(Cursor::new(b"check\n\n".to_vec()));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper.expect_check().times(1).returning(|| Ok(()));
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    cli.run().expect("failed to run cli");
+    assert_eq!(stdout, b"\n");
+}
+
+#[test]
+fn test_stateless_connect() {
+    for command in ["stateless-connect", "connect"] {
+        let input = format!("{} git-upload-pack\ncapabilities\n\n", command);
+        let mut stdin = BufReader::new(Cursor::new(input.into_bytes()));
+        let mut stdout = Vec::new();
+
+        let mut remote_helper = MockRemoteHelper::new();
+        remote_helper
+            .expect_capabilities()
+            .returning(|| vec!["fetch"]);
+        let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+        cli.run().expect("failed to run cli");
+        assert_eq!(stdout, b"fallback\nfetch\n\n");
+    }
+
+    let mut stdin = BufReader::new(Cursor::new(b"stateless-connect\n".to_vec()));
+    let mut stdout = Vec::new();
+    let mut cli = CLI::new(Box::new(MockRemoteHelper::new()), &mut stdin, &mut stdout);
+    cli.run().expect_err("should fail without a service");
+}
+
+#[test]
+fn test_export() {
+    let mut stdin = BufReader::new(Cursor::new(
+        b"export\nfeature done\nreset refs/heads/main\nfrom :1\n\nreset refs/tags/v1\nfrom :1\n\ndone\n\n"
+            .to_vec(),
+    ));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper
+        .expect_export()
+        .withf(|stream| {
+            stream.references() == vec!["refs/heads/main".to_string(), "refs/tags/v1".to_string()]
+        })
+        .times(1)
+        .returning(|_| Ok(()));
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    cli.run().expect("failed to run cli");
+    assert_eq!(stdout, b"ok refs/heads/main\nok refs/tags/v1\n\n");
+}
+
+#[test]
+fn test_export_failure() {
+    let mut stdin = BufReader::new(Cursor::new(
+        b"export\nreset refs/heads/main\nfrom :1\n\ndone\n\n".to_vec(),
+    ));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper.expect_export().returning(|_| {
+        Err(RemoteHelperError::Missing {
+            what: "test".to_string(),
+        })
+    });
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    cli.run().expect_err("should fail");
+    assert!(stdout.starts_with(b"error refs/heads/main "));
+}
+
+#[test]
+fn test_option() {
+    let mut stdin = BufReader::new(Cursor::new(
+        b"option verbosity 0\noption progress false\noption verbosity 1\n\n".to_vec(),
+    ));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper
+        .expect_set_option()
+        .with(eq("progress"), eq("false"))
+        .returning(|_, _| Ok(false));
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    cli.run().expect("failed to run cli");
+    assert_eq!(stdout, b"ok\nunsupported\nok\n");
+    assert!(!macros::is_quiet());
+
+    let mut stdin = BufReader::new(Cursor::new(b"option verbosity loud\n\n".to_vec()));
+    let mut stdout = Vec::new();
+    let mut cli = CLI::new(Box::new(MockRemoteHelper::new()), &mut stdin, &mut stdout);
+    cli.run()
+        .expect_err("should fail because of invalid verbosity");
+}
+
+#[test]
+fn test_option_delegated() {
+    let mut stdin = BufReader::new(Cursor::new(
+        b"option dry-run true\noption dry-run maybe\n\n".to_vec(),
+    ));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper
+        .expect_set_option()
+        .with(eq("dry-run"), eq("true"))
+        .returning(|_, _| Ok(true));
+    remote_helper
+        .expect_set_option()
+        .with(eq("dry-run"), eq("maybe"))
+        .returning(|_, value| {
+            Err(RemoteHelperError::Invalid {
+                what: "dry-run".to_string(),
+                value: value.to_string(),
+            })
+        });
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    cli.run().expect("failed to run cli");
+    assert_eq!(stdout, b"ok\nerror invalid dry-run: maybe\n");
+}
+
+#[test]
+fn test_push_options() {
+    let mut stdin = BufReader::new(Cursor::new(
+        b"option push-option ci.skip\noption push-option note=two words\r\npush refs/heads/main:refs/heads/main\n\n"
+            .to_vec(),
+    ));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper
+        .expect_set_push_options()
+        .with(eq(vec![
+            "ci.skip".to_string(),
+            "note=two words".to_string(),
+        ]))
+        .times(1)
+        .return_const(());
+    remote_helper.expect_push().times(1).returning(|_| Ok(()));
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    cli.run().expect("failed to run cli");
+    assert_eq!(stdout, b"ok\nok\nok refs/heads/main\n\n");
+}
+
+#[test]
+fn test_list_pattern() {
+    let refs = vec![
+        Reference::Normal {
+            name: "refs/heads/main".to_string(),
+            hash: Hash::from_str("4e1243bd22c66e76c2ba9eddc1f91394e57f9f83")
+                .expect("failed to create hash"),
+        },
+        Reference::Normal {
+            name: "refs/tags/v1.0".to_string(),
+            hash: Hash::from_str("4e1243bd22c66e76c2ba9eddc1f91394e57f9f83")
+                .expect("failed to create hash"),
+        },
+        Reference::Symbolic {
+            name: "HEAD".to_string(),
+            target: "refs/heads/main".to_string(),
+        },
+        Reference::KeyValue {
+            key: Keys::ObjectFormat,
+            value: "sha1".to_string(),
+        },
+    ];
+
+    // Case 1: Only matching refs and attributes are listed
+    let mut stdin = BufReader::new(Cursor::new(b"list refs/heads/*\n\n".to_vec()));
+    let mut stdout = Vec::new();
+
+    let refs_clone = refs.clone();
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper
+        .expect_list()
+        .with(eq(false))
+        .returning(move |_is_for_push| Ok(refs_clone.clone()));
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+    cli.run().expect("failed to run cli");
+    assert_eq!(stdout, format!("{}\n{}\n\n", refs[0], refs[3]).as_bytes());
+
+    // Case 2: Pattern after for-push
+    let mut stdin = BufReader::new(Cursor::new(b"list for-push refs/tags/v?.?\n\n".to_vec()));
+    let mut stdout = Vec::new();
+
+    let refs_clone = refs.clone();
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper
+        .expect_list()
+        .with(eq(true))
+        .returning(move |_is_for_push| Ok(refs_clone.clone()));
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+    cli.run().expect("failed to run cli");
+    assert_eq!(stdout, format!("{}\n{}\n\n", refs[1], refs[3]).as_bytes());
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match(b"refs/heads/*", b"refs/heads/main"));
+    assert!(glob_match(b"refs/heads/*", b"refs/heads/feature/x"));
+    assert!(glob_match(b"*", b""));
+    assert!(glob_match(b"refs/*/main", b"refs/heads/main"));
+    assert!(glob_match(b"HEA?", b"HEAD"));
+    assert!(!glob_match(b"HEA?", b"HEA"));
+    assert!(!glob_match(b"refs/tags/*", b"refs/heads/main"));
+    assert!(!glob_match(b"refs/heads/main", b"refs/heads/main2"));
+}
+
+#[test]
+fn test_fetch_during_push() {
+    let hash = Hash::from_data(b"hash", true).expect("should be set");
+    let input = format!(
+        "push refs/heads/main:refs/heads/main\nfetch {} refs/heads/main\n\n",
+        hash
+    );
+    let mut stdin = BufReader::new(Cursor::new(input.into_bytes()));
+    let mut stdout = Vec::new();
+
+    // Nothing is pushed, the batch is abandoned as a whole
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper.expect_push().never();
+    remote_helper.expect_fetch().never();
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    let err = cli
+        .run()
+        .expect_err("should fail because of a fetch in a push batch");
+    assert!(matches!(err, CLIError::IllegalState(line) if line.starts_with("fetch ")));
+    assert!(stdout.is_empty());
+}
+
+#[test]
+fn test_push_during_fetch() {
+    let hash = Hash::from_data(b"hash", true).expect("should be set");
+    let input = format!(
+        "fetch {} refs/heads/main\npush refs/heads/main:refs/heads/main\n\n",
+        hash
+    );
+    let mut stdin = BufReader::new(Cursor::new(input.into_bytes()));
+    let mut stdout = Vec::new();
+
+    let mut remote_helper = MockRemoteHelper::new();
+    remote_helper.expect_push().never();
+    remote_helper.expect_fetch().never();
+    let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
+
+    let err = cli
+        .run()
+        .expect_err("should fail because of a push in a fetch batch");
+    assert!(matches!(err, CLIError::IllegalState(line) if line.starts_with("push ")));
+    assert!(stdout.is_empty());
+}
+
+// The remote end of a dialogue, serves its references and objects like the contract would
+#[cfg(test)]
+fn seeded_executor(remote: InMemoryGit, references: Vec<Reference>) -> MockExecutor {
+    let mut executor = MockExecutor::new();
+    executor
+        .expect_list()
+        .returning(move || Ok(references.clone()));
+    executor
+        .expect_fetch()
+        .returning(move |hash| remote.get_object(hash).map(Fetched::Object));
+    executor
+}
+
+// Feeds the input to a CLI driving a real Evm, returns everything written to git
+#[cfg(test)]
+fn run_dialogue(executor: MockExecutor, git: Rc<InMemoryGit>, input: &str) -> String {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+    let evm =
+        Evm::new(runtime, Box::new(executor), git, Settings::default()).expect("should be set");
+
+    let mut stdin = BufReader::new(Cursor::new(input.as_bytes().to_vec()));
+    let mut stdout = Vec::new();
+    let mut cli = CLI::new(Box::new(evm), &mut stdin, &mut stdout);
+    cli.run().expect("failed to run cli");
+    String::from_utf8(stdout).expect("should be utf-8")
+}
+
+#[test]
+fn test_dialogue_clone() {
+    let main = commit_objects(None, &[b"shared"]);
+    let dev = commit_objects(Some(main[0].get_hash()), &[b"shared", b"dev"]);
+    let (main_hash, dev_hash) = (main[0].get_hash().clone(), dev[0].get_hash().clone());
+    let remote =
+        InMemoryGit::new(true).with_objects(main.iter().chain(dev.iter()).cloned().collect());
+    let references = vec![
+        Reference::Normal {
+            name: "refs/heads/dev".to_string(),
+            hash: dev_hash.clone(),
+        },
+        Reference::Normal {
+            name: "refs/heads/main".to_string(),
+            hash: main_hash.clone(),
+        },
+        Reference::Symbolic {
+            name: "HEAD".to_string(),
+            target: "refs/heads/main".to_string(),
+        },
+        Reference::KeyValue {
+            key: Keys::ObjectFormat,
+            value: "sha256".to_string(),
+        },
+    ];
+    let git = Rc::new(InMemoryGit::new(true));
+
+    let input = format!(
+        "capabilities\noption progress false\noption object-format true\nlist\nfetch {} refs/heads/dev\nfetch {} refs/heads/main\n\n\n",
+        dev_hash, main_hash
+    );
+    let stdout = run_dialogue(seeded_executor(remote, references), git.clone(), &input);
+    let expected = format!(
+        "*fetch\n*push\n{}\n{}\n{}\n{}\noption\nobject-format\ndelete-refs\n\nunsupported\nok\n:object-format sha256\n{} refs/heads/dev\n{} refs/heads/main\n@refs/heads/main HEAD\n\n\n",
+        "refspec refs/heads/*:refs/heads/*",
+        "refspec refs/tags/*:refs/tags/*",
+        "refspec refs/notes/*:refs/notes/*",
+        "refspec refs/replace/*:refs/replace/*",
+        dev_hash,
+        main_hash
+    );
+    assert_eq!(stdout, expected);
+
+    // Both branches share a commit, every object has to be there exactly once
+    let hashes = git
+        .list_objects(dev_hash)
+        .expect("graph should be complete");
+    assert_eq!(hashes.len(), 6);
+}
+
+#[test]
+fn test_dialogue_fetch_batches() {
+    let main = commit_objects(None, &[b"main"]);
+    let tag = commit_objects(None, &[b"tag"]);
+    let (main_hash, tag_hash) = (main[0].get_hash().clone(), tag[0].get_hash().clone());
+    let remote =
+        InMemoryGit::new(true).with_objects(main.iter().chain(tag.iter()).cloned().collect());
+    let references = vec![Reference::KeyValue {
+        key: Keys::ObjectFormat,
+        value: "sha256".to_string(),
+    }];
+    let git = Rc::new(InMemoryGit::new(true));
+
+    // Every batch is answered with a blank line, the state has to be reset in between
+    let input = format!(
+        "fetch {} refs/heads/main\n\nfetch {} refs/tags/v1\n\n\n",
+        main_hash, tag_hash
+    );
+    let stdout = run_dialogue(seeded_executor(remote, references), git.clone(), &input);
+    assert_eq!(stdout, "\n\n");
+    assert!(git.contains(&main_hash));
+    assert!(git.contains(&tag_hash));
+}
+
+#[test]
+fn test_dialogue_delete() {
+    use crate::core::remote_helper::executor::PushReceipt;
+    use alloy::primitives::U256;
+    use mockall::predicate::always;
+
+    let dev_hash = Hash::from_data(b"dev", true).expect("should be set");
+    let references = vec![Reference::KeyValue {
+        key: Keys::ObjectFormat,
+        value: "sha256".to_string(),
+    }];
+    let mut executor = seeded_executor(InMemoryGit::new(true), references);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    let remote_hash = dev_hash.clone();
+    executor
+        .expect_resolve_references()
+        .returning(move |_| Ok(vec![Some(remote_hash.clone())]));
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor
+        .expect_estimate_push_cost()
+        .returning(|_, _, _| Ok(U256::from(1)));
+    executor.expect_balance().returning(|| Ok(U256::from(1)));
+    // Nothing is uploaded, the reference is set to the zero hash
+    executor
+        .expect_push()
+        .with(
+            eq(vec![]),
+            eq(vec![]),
+            eq(vec![Reference::Normal {
+                name: "refs/heads/dev".to_string(),
+                hash: Hash::zero(true),
+            }]),
+            always(),
+        )
+        .times(1)
+        .returning(|_, _, _, _| Ok(PushReceipt::default()));
+
+    let stdout = run_dialogue(
+        executor,
+        Rc::new(InMemoryGit::new(true)),
+        "capabilities\npush :refs/heads/dev\n\n\n",
+    );
+    assert!(stdout.contains("\ndelete-refs\n"));
+    assert!(stdout.ends_with("\n\nok refs/heads/dev\n\n"));
+}
+
+// git works out a mirror push itself, refs listed for push but missing locally are sent as deletions
+#[test]
+fn test_dialogue_mirror() {
+    use crate::core::remote_helper::executor::PushReceipt;
+    use alloy::primitives::U256;
+
+    let old = commit_objects(None, &[b"old"]);
+    let new = commit_objects(Some(old[0].get_hash()), &[b"new"]);
+    let (old_hash, new_hash) = (old[0].get_hash().clone(), new[0].get_hash().clone());
+    let references = vec![
+        Reference::Normal {
+            name: "refs/heads/main".to_string(),
+            hash: old_hash.clone(),
+        },
+        Reference::Normal {
+            name: "refs/heads/gone".to_string(),
+            hash: old_hash.clone(),
+        },
+        Reference::Normal {
+            name: "refs/tags/gone".to_string(),
+            hash: old_hash.clone(),
+        },
+        Reference::Symbolic {
+            name: "HEAD".to_string(),
+            target: "refs/heads/main".to_string(),
+        },
+        Reference::KeyValue {
+            key: Keys::ObjectFormat,
+            value: "sha256".to_string(),
+        },
+    ];
+    let mut executor = seeded_executor(InMemoryGit::new(true), references);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    let remote_hash = old_hash.clone();
+    executor
+        .expect_resolve_references()
+        .returning(move |names| Ok(vec![Some(remote_hash.clone()); names.len()]));
+    let old_hashes: Vec<Hash> = old.iter().map(|o| o.get_hash().clone()).collect();
+    executor
+        .expect_list_all_objects()
+        .returning(move || Ok(old_hashes.clone()));
+    executor
+        .expect_estimate_push_cost()
+        .returning(|_, _, _| Ok(U256::from(1)));
+    executor.expect_balance().returning(|| Ok(U256::from(1)));
+    let expected_refs = vec![
+        Reference::Normal {
+            name: "refs/heads/main".to_string(),
+            hash: new_hash.clone(),
+        },
+        Reference::Normal {
+            name: "refs/heads/gone".to_string(),
+            hash: Hash::zero(true),
+        },
+        Reference::Normal {
+            name: "refs/tags/gone".to_string(),
+            hash: Hash::zero(true),
+        },
+    ];
+    executor
+        .expect_push()
+        .withf(move |objects, _, refs, _| objects.len() == 3 && refs == &expected_refs)
+        .times(1)
+        .returning(|_, _, _, _| Ok(PushReceipt::default()));
+
+    let git = Rc::new(
+        InMemoryGit::new(true)
+            .with_objects(old.iter().chain(new.iter()).cloned().collect())
+            .with_reference("refs/heads/main", new_hash.clone()),
+    );
+    let stdout = run_dialogue(
+        executor,
+        git,
+        "capabilities\nlist for-push\npush +refs/heads/main:refs/heads/main\npush :refs/heads/gone\npush :refs/tags/gone\n\n\n",
+    );
+    // Every remote ref has to be listed, otherwise git can't tell which ones to delete
+    for name in ["refs/heads/main", "refs/heads/gone", "refs/tags/gone"] {
+        assert!(stdout.contains(&format!("{} {}\n", old_hash, name)));
+    }
+    assert!(stdout.ends_with("\n\nok refs/heads/main\nok refs/heads/gone\nok refs/tags/gone\n\n"));
+}
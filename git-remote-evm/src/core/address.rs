@@ -0,0 +1,39 @@
+use super::remote_helper::error::RemoteHelperError;
+use alloy::primitives::Address;
+
+// EIP-55, only mixed-case addresses carry a checksum so all-lowercase or all-uppercase ones are accepted as-is
+pub fn verify_checksum(address: &str) -> Result<(), RemoteHelperError> {
+    let digits = address.strip_prefix("0x").unwrap_or(address);
+    let has_lowercase = digits.chars().any(|c| c.is_ascii_lowercase());
+    let has_uppercase = digits.chars().any(|c| c.is_ascii_uppercase());
+    if !has_lowercase || !has_uppercase {
+        return Ok(());
+    }
+
+    let err = RemoteHelperError::Invalid {
+        what: "address checksum".to_string(),
+        value: address.to_string(),
+    };
+    let bytes = hex::decode(digits).map_err(|_| err.clone())?;
+    if bytes.len() != 20 {
+        return Err(err);
+    }
+    let checksummed = Address::from_slice(&bytes).to_checksum(None);
+    if checksummed.strip_prefix("0x") != Some(digits) {
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_verify_checksum() {
+    verify_checksum("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed").expect("valid checksum");
+    verify_checksum("fB6916095ca1df60bB79Ce92cE3Ea74c37c5d359").expect("valid checksum");
+    verify_checksum("0x5aaeb6053f3e94c9b9a09f33669435e7ef1beaed").expect("lowercase is accepted");
+    verify_checksum("0x5AAEB6053F3E94C9B9A09F33669435E7EF1BEAED").expect("uppercase is accepted");
+
+    verify_checksum("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD")
+        .expect_err("should fail because of invalid checksum");
+    verify_checksum("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA")
+        .expect_err("should fail because of invalid length");
+}
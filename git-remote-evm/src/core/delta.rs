@@ -0,0 +1,316 @@
+use super::hash::Hash;
+use super::object::Object;
+#[cfg(test)]
+use super::object::ObjectKind;
+use crate::core::remote_helper::error::RemoteHelperError;
+use std::collections::HashMap;
+
+// Matches are looked up by blocks of this size, shorter ones are inserted as is
+const BLOCK_SIZE: usize = 16;
+// Limits of a single copy or insert instruction in git's delta format
+const MAX_COPY_SIZE: usize = 0xff_ffff;
+const MAX_INSERT_SIZE: usize = 0x7f;
+// A delta is only worth storing when it's at most this fraction of the object
+const MAX_DELTA_RATIO: usize = 2;
+
+// An object stored as the instructions to rebuild it from another one already on the remote,
+// in the same format git uses for deltified objects in packs
+#[derive(Debug, PartialEq, Clone, Eq)]
+pub struct DeltaObject {
+    hash: Hash,
+    base: Hash,
+    delta: Vec<u8>,
+}
+
+impl DeltaObject {
+    pub fn new(hash: Hash, base: Hash, delta: Vec<u8>) -> Self {
+        Self { hash, base, delta }
+    }
+
+    // None when the kinds differ or the delta wouldn't be much smaller than the object
+    pub fn compute(base: &Object, object: &Object) -> Option<Self> {
+        if base.get_kind() != object.get_kind() {
+            return None;
+        }
+        let delta = encode(base.get_data(), object.get_data());
+        if delta.len() * MAX_DELTA_RATIO > object.get_data().len() {
+            return None;
+        }
+        Some(Self::new(
+            object.get_hash().clone(),
+            base.get_hash().clone(),
+            delta,
+        ))
+    }
+
+    pub fn get_hash(&self) -> &Hash {
+        &self.hash
+    }
+
+    pub fn get_base(&self) -> &Hash {
+        &self.base
+    }
+
+    pub fn get_delta(&self) -> &Vec<u8> {
+        &self.delta
+    }
+
+    // Nothing checked the delta before it was stored, so the result has to hash to the expected value
+    pub fn apply(&self, base: &Object) -> Result<Object, RemoteHelperError> {
+        if base.get_hash() != &self.base {
+            return Err(RemoteHelperError::Invalid {
+                what: "delta base".to_string(),
+                value: format!("{}, expected: {}", base.get_hash(), self.base),
+            });
+        }
+        let data = decode(base.get_data(), &self.delta)?;
+        let object = Object::new(base.get_kind().clone(), data, self.hash.is_sha256())?;
+        if object.get_hash() != &self.hash {
+            return Err(RemoteHelperError::Invalid {
+                what: "object hash".to_string(),
+                value: format!("{}, expected: {}", object.get_hash(), self.hash),
+            });
+        }
+        Ok(object)
+    }
+}
+
+fn write_size(out: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_size(delta: &[u8], position: &mut usize) -> Result<usize, RemoteHelperError> {
+    let mut size = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *delta.get(*position).ok_or(truncated_delta())?;
+        *position += 1;
+        size |= ((byte & 0x7f) as usize)
+            .checked_shl(shift)
+            .ok_or(truncated_delta())?;
+        if byte & 0x80 == 0 {
+            return Ok(size);
+        }
+        shift += 7;
+    }
+}
+
+fn truncated_delta() -> RemoteHelperError {
+    RemoteHelperError::Invalid {
+        what: "delta".to_string(),
+        value: "truncated".to_string(),
+    }
+}
+
+fn write_insert(out: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_INSERT_SIZE) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+// Only the non-zero bytes of the offset and size are written, flagged in the instruction byte
+fn write_copy(out: &mut Vec<u8>, offset: usize, size: usize) {
+    let mut instruction = 0x80u8;
+    let mut operands = vec![];
+    for (i, byte) in (offset as u32).to_le_bytes().into_iter().enumerate() {
+        if byte != 0 {
+            instruction |= 1 << i;
+            operands.push(byte);
+        }
+    }
+    for (i, byte) in (size as u32).to_le_bytes().into_iter().take(3).enumerate() {
+        if byte != 0 {
+            instruction |= 1 << (4 + i);
+            operands.push(byte);
+        }
+    }
+    out.push(instruction);
+    out.extend(operands);
+}
+
+fn encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    write_size(&mut out, base.len());
+    write_size(&mut out, target.len());
+
+    // The first occurrence of each aligned block, the match is extended in both directions anyway
+    let mut index: HashMap<&[u8], usize> = HashMap::new();
+    for (i, block) in base.chunks_exact(BLOCK_SIZE).enumerate() {
+        index.entry(block).or_insert(i * BLOCK_SIZE);
+    }
+
+    let mut pending = vec![];
+    let mut position = 0;
+    while position < target.len() {
+        let found = target
+            .get(position..position + BLOCK_SIZE)
+            .and_then(|block| index.get(block));
+        let Some(&offset) = found else {
+            pending.push(target[position]);
+            position += 1;
+            continue;
+        };
+
+        let mut size = BLOCK_SIZE;
+        while offset + size < base.len()
+            && position + size < target.len()
+            && size < MAX_COPY_SIZE
+            && base[offset + size] == target[position + size]
+        {
+            size += 1;
+        }
+        let end = position + size;
+        let (mut offset, mut size) = (offset, size);
+        while offset > 0 && size < MAX_COPY_SIZE && pending.last() == Some(&base[offset - 1]) {
+            pending.pop();
+            offset -= 1;
+            size += 1;
+        }
+
+        write_insert(&mut out, &pending);
+        pending.clear();
+        write_copy(&mut out, offset, size);
+        position = end;
+    }
+    write_insert(&mut out, &pending);
+    out
+}
+
+fn decode(base: &[u8], delta: &[u8]) -> Result<Vec<u8>, RemoteHelperError> {
+    let mut position = 0;
+    let base_size = read_size(delta, &mut position)?;
+    if base_size != base.len() {
+        return Err(RemoteHelperError::Invalid {
+            what: "delta base size".to_string(),
+            value: format!("{}, expected: {}", base.len(), base_size),
+        });
+    }
+    let target_size = read_size(delta, &mut position)?;
+
+    // The size comes from the remote, so it isn't trusted for the allocation
+    let mut out = vec![];
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+        if instruction & 0x80 != 0 {
+            let mut read_operand = |bits: std::ops::Range<u8>| -> Result<usize, RemoteHelperError> {
+                let mut value = 0usize;
+                for (i, bit) in bits.enumerate() {
+                    if instruction & (1 << bit) != 0 {
+                        let byte = *delta.get(position).ok_or(truncated_delta())?;
+                        position += 1;
+                        value |= (byte as usize) << (8 * i);
+                    }
+                }
+                Ok(value)
+            };
+            let offset = read_operand(0..4)?;
+            let size = match read_operand(4..7)? {
+                0 => 0x10000,
+                size => size,
+            };
+            let copied = base
+                .get(offset..offset + size)
+                .ok_or(RemoteHelperError::Invalid {
+                    what: "delta copy".to_string(),
+                    value: format!("{} bytes at {}, base has {}", size, offset, base.len()),
+                })?;
+            out.extend_from_slice(copied);
+        } else if instruction != 0 {
+            let size = instruction as usize;
+            let inserted = delta
+                .get(position..position + size)
+                .ok_or(truncated_delta())?;
+            out.extend_from_slice(inserted);
+            position += size;
+        } else {
+            return Err(RemoteHelperError::Invalid {
+                what: "delta instruction".to_string(),
+                value: "0".to_string(),
+            });
+        }
+    }
+
+    if out.len() != target_size {
+        return Err(RemoteHelperError::Invalid {
+            what: "delta result size".to_string(),
+            value: format!("{}, expected: {}", out.len(), target_size),
+        });
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_delta_roundtrip() {
+    let base: Vec<u8> = (0..4096u32).flat_map(|i| i.to_le_bytes()).collect();
+    let mut target = base.clone();
+    target.splice(100..100, b"inserted".iter().cloned());
+    target.drain(5000..5100);
+    target[9000] ^= 0xff;
+    target.extend_from_slice(b"appended");
+
+    for (base, target) in [
+        (&base, &target),
+        (&base, &base),
+        (&target, &base),
+        (&vec![], &target),
+        (&base, &vec![]),
+    ] {
+        let delta = encode(base, target);
+        assert_eq!(&decode(base, &delta).expect("failed to decode"), target);
+    }
+    assert!(encode(&base, &target).len() < 100);
+}
+
+#[test]
+fn test_delta_decode_invalid() {
+    let base = b"0123456789abcdef0123456789abcdef".to_vec();
+    let target = b"0123456789abcdef0123456789abcdef!".to_vec();
+    let delta = encode(&base, &target);
+
+    decode(&base[1..], &delta).expect_err("should fail with the wrong base");
+    decode(&base, &delta[..delta.len() - 1]).expect_err("should fail when truncated");
+    // Copies past the end of the base
+    decode(&base, &[32, 64, 0x91, 16, 32]).expect_err("should fail with an invalid copy");
+    decode(&base, &[32, 1, 0]).expect_err("should fail with the reserved instruction");
+}
+
+#[test]
+fn test_delta_object() {
+    let base_data: Vec<u8> = (0..200)
+        .flat_map(|i| format!("line {}\n", i).into_bytes())
+        .collect();
+    let mut data = base_data.clone();
+    data.extend_from_slice(b"line 200\n");
+    let base = Object::new(ObjectKind::Blob, base_data, true).expect("should be set");
+    let object = Object::new(ObjectKind::Blob, data, true).expect("should be set");
+
+    let delta = DeltaObject::compute(&base, &object).expect("should be worth a delta");
+    assert_eq!(delta.get_hash(), object.get_hash());
+    assert_eq!(delta.get_base(), base.get_hash());
+    assert_eq!(delta.apply(&base).expect("failed to apply"), object);
+    delta
+        .apply(&object)
+        .expect_err("should fail with another base");
+
+    let tampered = DeltaObject::new(
+        base.get_hash().clone(),
+        base.get_hash().clone(),
+        delta.get_delta().clone(),
+    );
+    tampered
+        .apply(&base)
+        .expect_err("should fail when the result doesn't match the hash");
+
+    let unrelated = Object::new(ObjectKind::Blob, vec![b'x'; 100], true).expect("should be set");
+    assert_eq!(DeltaObject::compute(&unrelated, &object), None);
+}
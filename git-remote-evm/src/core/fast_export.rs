@@ -0,0 +1,167 @@
+use super::remote_helper::error::RemoteHelperError;
+use std::io::BufRead;
+#[cfg(test)]
+use std::io::{BufReader, Cursor};
+
+const COMMAND_DONE: &str = "done";
+const COMMAND_DATA: &str = "data ";
+const REF_COMMANDS: [&str; 2] = ["commit ", "reset "];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Raw(Vec<u8>),
+    Reference { command: String, name: String },
+}
+
+// git-fast-import.adoc, only the subset emitted by git fast-export is supported
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExportStream {
+    parts: Vec<Part>,
+}
+
+impl ExportStream {
+    // Reads up to and including the `done` command, git passes --use-done-feature to fast-export
+    pub fn read(reader: &mut dyn BufRead) -> Result<Self, RemoteHelperError> {
+        let mut parts = Vec::new();
+        loop {
+            let mut line = Vec::new();
+            let read =
+                reader
+                    .read_until(b'\n', &mut line)
+                    .map_err(|e| RemoteHelperError::Failure {
+                        action: "reading export stream".to_string(),
+                        details: Some(e.to_string()),
+                    })?;
+            if read == 0 {
+                return Err(RemoteHelperError::Missing {
+                    what: "done command in export stream".to_string(),
+                });
+            }
+
+            let text = String::from_utf8_lossy(&line);
+            let command = text.trim_end_matches('\n');
+            if command == COMMAND_DONE {
+                parts.push(Part::Raw(line));
+                return Ok(Self { parts });
+            }
+
+            if let Some(length) = command.strip_prefix(COMMAND_DATA) {
+                let length: usize = length.parse().map_err(|_| RemoteHelperError::Invalid {
+                    what: "data length in export stream".to_string(),
+                    value: length.to_string(),
+                })?;
+                let mut data = vec![0; length];
+                reader
+                    .read_exact(&mut data)
+                    .map_err(|e| RemoteHelperError::Failure {
+                        action: "reading data in export stream".to_string(),
+                        details: Some(e.to_string()),
+                    })?;
+                line.extend(data);
+                parts.push(Part::Raw(line));
+                continue;
+            }
+
+            match REF_COMMANDS
+                .iter()
+                .find_map(|prefix| command.strip_prefix(prefix).map(|name| (prefix, name)))
+            {
+                Some((prefix, name)) => parts.push(Part::Reference {
+                    command: prefix.to_string(),
+                    name: name.to_string(),
+                }),
+                None => parts.push(Part::Raw(line)),
+            }
+        }
+    }
+
+    // Names of the references updated by the stream, in order of first appearance
+    pub fn references(&self) -> Vec<String> {
+        let mut references: Vec<String> = Vec::new();
+        for part in &self.parts {
+            if let Part::Reference { name, .. } = part
+                && !references.contains(name)
+            {
+                references.push(name.clone());
+            }
+        }
+        references
+    }
+
+    pub fn to_bytes(&self, rename: &dyn Fn(&str) -> String) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for part in &self.parts {
+            match part {
+                Part::Raw(data) => bytes.extend(data),
+                Part::Reference { command, name } => {
+                    bytes.extend(format!("{}{}\n", command, rename(name)).as_bytes())
+                }
+            }
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+const TEST_STREAM: &[u8] = b"feature done\n\
+blob\n\
+mark :1\n\
+data 13\n\
+done\ncommit x\n\
+commit refs/heads/main\n\
+mark :2\n\
+committer A <a@example.com> 0 +0000\n\
+data 8\n\
+message\n\
+M 100644 :1 file\n\
+\n\
+reset refs/tags/v1\n\
+from :2\n\
+\n\
+reset refs/heads/main\n\
+from :2\n\
+\n\
+done\n";
+
+#[test]
+fn test_read() {
+    let mut input = TEST_STREAM.to_vec();
+    input.extend(b"capabilities\n");
+    let mut reader = BufReader::new(Cursor::new(input));
+
+    let stream = ExportStream::read(&mut reader).expect("failed to read stream");
+    assert_eq!(
+        stream.references(),
+        vec!["refs/heads/main".to_string(), "refs/tags/v1".to_string()]
+    );
+    assert_eq!(stream.to_bytes(&|name| name.to_string()), TEST_STREAM);
+
+    let mut rest = String::new();
+    reader.read_line(&mut rest).expect("failed to read line");
+    assert_eq!(rest, "capabilities\n");
+}
+
+#[test]
+fn test_to_bytes_renamed() {
+    let mut reader = BufReader::new(Cursor::new(TEST_STREAM.to_vec()));
+    let stream = ExportStream::read(&mut reader).expect("failed to read stream");
+
+    let bytes = stream.to_bytes(&|name| format!("refs/private/{}", name));
+    let text = String::from_utf8(bytes).expect("should be utf8");
+    assert!(text.contains("commit refs/private/refs/heads/main\n"));
+    assert!(text.contains("reset refs/private/refs/tags/v1\n"));
+    // References inside of data aren't commands
+    assert!(text.contains("done\ncommit x\n"));
+}
+
+#[test]
+fn test_read_invalid() {
+    let mut reader = BufReader::new(Cursor::new(b"feature done\nblob\n".to_vec()));
+    ExportStream::read(&mut reader).expect_err("should fail without done");
+
+    let mut reader = BufReader::new(Cursor::new(b"data abc\n".to_vec()));
+    ExportStream::read(&mut reader).expect_err("should fail with invalid length");
+
+    let mut reader = BufReader::new(Cursor::new(b"data 100\nshort".to_vec()));
+    ExportStream::read(&mut reader).expect_err("should fail with truncated data");
+}
@@ -0,0 +1,401 @@
+use super::{Git, GitVersion, SystemGit, address_from_url, saved_hash_mismatch};
+use crate::core::hash::Hash;
+use crate::core::object::{Object, ObjectKind};
+use crate::core::remote_helper::error::RemoteHelperError;
+use git2::{ObjectType, Oid, Repository};
+use log::{debug, trace};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+fn failure(action: &str) -> impl Fn(git2::Error) -> RemoteHelperError {
+    move |e| RemoteHelperError::Failure {
+        action: action.to_string(),
+        details: Some(e.message().to_string()),
+    }
+}
+
+fn to_oid(hash: &Hash) -> Result<Oid, RemoteHelperError> {
+    Oid::from_str(&hash.to_string()).map_err(|_| RemoteHelperError::Invalid {
+        what: "object id".to_string(),
+        value: hash.to_string(),
+    })
+}
+
+fn to_hash(oid: Oid) -> Result<Hash, RemoteHelperError> {
+    Hash::from_str(&oid.to_string())
+}
+
+fn to_object_type(kind: &ObjectKind) -> ObjectType {
+    match kind {
+        ObjectKind::Blob => ObjectType::Blob,
+        ObjectKind::Tree => ObjectType::Tree,
+        ObjectKind::Commit => ObjectType::Commit,
+        ObjectKind::Tag => ObjectType::Tag,
+    }
+}
+
+// Avoids spawning git in the fetch and push loops. libgit2 only supports sha1 repositories,
+// and version and fast_import have no libgit2 equivalent, so those still need git installed:
+// the version is only used for a warning, fast_import only for pushes through export.
+pub struct Libgit2Git {
+    repo: Repository,
+    system: SystemGit,
+}
+
+impl Libgit2Git {
+    // None for repositories libgit2 can't read, e.g. sha256 ones, they're left to SystemGit
+    pub fn open(path: PathBuf) -> Result<Option<Self>, RemoteHelperError> {
+        // libgit2 refuses to open a repository with another format, so its config is read first
+        let git_dir = Repository::discover_path(&path, Vec::<PathBuf>::new())
+            .map_err(failure("finding repository"))?;
+        let object_format = git2::Config::open(&git_dir.join("config"))
+            .and_then(|config| config.get_string("extensions.objectformat"));
+        if let Ok(object_format) = object_format
+            && object_format != "sha1"
+        {
+            debug!(
+                "libgit2 doesn't support the {} object format",
+                object_format
+            );
+            return Ok(None);
+        }
+        let repo = Repository::open(&git_dir).map_err(failure("opening repository"))?;
+        debug!("opened repository with libgit2: {}", path.to_string_lossy());
+        Ok(Some(Self {
+            repo,
+            system: SystemGit::new(path),
+        }))
+    }
+
+    // Same objects as `git rev-list --objects`, annotated tags are listed and peeled
+    fn walk(&self, roots: Vec<Oid>) -> Result<Vec<Hash>, RemoteHelperError> {
+        let mut seen = HashSet::new();
+        let mut oids = vec![];
+        let mut revwalk = self.repo.revwalk().map_err(failure("walking commits"))?;
+        let mut trees = vec![];
+
+        for root in roots {
+            let mut oid = root;
+            loop {
+                let object = self
+                    .repo
+                    .find_object(oid, None)
+                    .map_err(failure("finding object"))?;
+                match object.kind() {
+                    Some(ObjectType::Tag) => {
+                        if seen.insert(oid) {
+                            oids.push(oid);
+                        }
+                        oid = object.as_tag().map(|tag| tag.target_id()).ok_or(
+                            RemoteHelperError::Failure {
+                                action: "peeling tag".to_string(),
+                                details: Some(oid.to_string()),
+                            },
+                        )?;
+                    }
+                    Some(ObjectType::Commit) => {
+                        revwalk.push(oid).map_err(failure("walking commits"))?;
+                        break;
+                    }
+                    Some(ObjectType::Tree) => {
+                        trees.push(oid);
+                        break;
+                    }
+                    _ => {
+                        if seen.insert(oid) {
+                            oids.push(oid);
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+
+        for commit in revwalk {
+            let oid = commit.map_err(failure("walking commits"))?;
+            let commit = self
+                .repo
+                .find_commit(oid)
+                .map_err(failure("finding commit"))?;
+            if seen.insert(oid) {
+                oids.push(oid);
+            }
+            trees.push(commit.tree_id());
+        }
+        for tree in trees {
+            self.walk_tree(tree, &mut seen, &mut oids)?;
+        }
+
+        oids.into_iter().map(to_hash).collect()
+    }
+
+    fn walk_tree(
+        &self,
+        oid: Oid,
+        seen: &mut HashSet<Oid>,
+        oids: &mut Vec<Oid>,
+    ) -> Result<(), RemoteHelperError> {
+        if !seen.insert(oid) {
+            return Ok(());
+        }
+        oids.push(oid);
+
+        let tree = self.repo.find_tree(oid).map_err(failure("finding tree"))?;
+        for entry in tree.iter() {
+            match entry.kind() {
+                Some(ObjectType::Tree) => self.walk_tree(entry.id(), seen, oids)?,
+                Some(ObjectType::Blob) if seen.insert(entry.id()) => oids.push(entry.id()),
+                // Submodule commits belong to another repository, seen blobs are skipped
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Git for Libgit2Git {
+    fn version(&self) -> Result<GitVersion, RemoteHelperError> {
+        self.system.version()
+    }
+
+    fn is_sha256(&self) -> Result<bool, RemoteHelperError> {
+        Ok(false)
+    }
+
+    fn get_address(
+        &self,
+        protocol: &str,
+        remote_name: &str,
+    ) -> Result<[u8; 20], RemoteHelperError> {
+        trace!("getting address: {}", remote_name);
+        let remote = self
+            .repo
+            .find_remote(remote_name)
+            .map_err(failure("getting remote url"))?;
+        let remote_url = remote.url().ok_or(RemoteHelperError::Invalid {
+            what: "remote url".to_string(),
+            value: String::from_utf8_lossy(remote.url_bytes()).to_string(),
+        })?;
+        address_from_url(protocol, remote_url)
+    }
+
+    fn resolve_reference(&self, name: &str) -> Result<Hash, RemoteHelperError> {
+        trace!("resolving reference: {}", name);
+        let object = self
+            .repo
+            .revparse_single(name)
+            .map_err(failure("resolving reference"))?;
+        let hash = to_hash(object.id())?;
+        debug!("resolved reference {}: {}", name, hash);
+        Ok(hash)
+    }
+
+    fn get_object(&self, hash: Hash) -> Result<Object, RemoteHelperError> {
+        trace!("getting object: {}", hash);
+        let odb = self
+            .repo
+            .odb()
+            .map_err(failure("opening object database"))?;
+        let raw = odb
+            .read(to_oid(&hash)?)
+            .map_err(failure("getting object"))?;
+        let kind = match raw.kind() {
+            ObjectType::Blob => ObjectKind::Blob,
+            ObjectType::Tree => ObjectKind::Tree,
+            ObjectType::Commit => ObjectKind::Commit,
+            ObjectType::Tag => ObjectKind::Tag,
+            kind => {
+                return Err(RemoteHelperError::Invalid {
+                    what: "object type".to_string(),
+                    value: kind.to_string(),
+                });
+            }
+        };
+
+        let object = Object::new(kind, raw.data().to_vec(), false)?;
+        if &hash != object.get_hash() {
+            return Err(RemoteHelperError::Failure {
+                action: "getting object".to_string(),
+                details: Some(format!(
+                    "object hash mismatch: {} != {}",
+                    hash,
+                    object.get_hash()
+                )),
+            });
+        }
+        debug!("got object {}: {}", hash, object.get_kind());
+        Ok(object)
+    }
+
+    fn has_object(&self, hash: &Hash) -> Result<bool, RemoteHelperError> {
+        let odb = self
+            .repo
+            .odb()
+            .map_err(failure("opening object database"))?;
+        Ok(odb.exists(to_oid(hash)?))
+    }
+
+    fn get_objects(&self, hashes: Vec<Hash>) -> Result<Vec<Object>, RemoteHelperError> {
+        hashes
+            .into_iter()
+            .map(|hash| self.get_object(hash))
+            .collect()
+    }
+
+    fn save_object(&self, object: Object) -> Result<(), RemoteHelperError> {
+        trace!("saving object: {}", object.get_kind());
+        let odb = self
+            .repo
+            .odb()
+            .map_err(failure("opening object database"))?;
+        let oid = odb
+            .write(to_object_type(object.get_kind()), object.get_data())
+            .map_err(failure("saving object"))?;
+
+        let hash = to_hash(oid)?;
+        let object_hash = object.get_hash();
+        if &hash != object_hash {
+            return Err(saved_hash_mismatch("saving object", &hash, object_hash));
+        }
+        debug!("saved object: {}", hash);
+        Ok(())
+    }
+
+    fn save_objects(&self, objects: Vec<Object>) -> Result<(), RemoteHelperError> {
+        objects
+            .into_iter()
+            .try_for_each(|object| self.save_object(object))
+    }
+
+    fn list_objects(&self, hash: Hash) -> Result<Vec<Hash>, RemoteHelperError> {
+        trace!("listing objects: {}", hash);
+        let hashes = self.walk(vec![to_oid(&hash)?])?;
+        debug!("got objects: {:?}", hashes);
+        Ok(hashes)
+    }
+
+    fn list_all_objects(&self) -> Result<Vec<Hash>, RemoteHelperError> {
+        trace!("listing all objects");
+        let mut roots = vec![];
+        // Unborn HEAD has no target, like `git rev-list --all` it's skipped
+        if let Ok(head) = self.repo.head() {
+            roots.extend(head.target());
+        }
+        for reference in self
+            .repo
+            .references()
+            .map_err(failure("listing references"))?
+        {
+            let reference = reference.map_err(failure("listing references"))?;
+            roots.extend(reference.resolve().ok().and_then(|r| r.target()));
+        }
+        let hashes = self.walk(roots)?;
+        debug!("got objects: {:?}", hashes);
+        Ok(hashes)
+    }
+
+    fn get_config(&self, key: &str) -> Result<Option<String>, RemoteHelperError> {
+        let config = self.repo.config().map_err(failure("reading config"))?;
+        match config.get_string(key) {
+            Ok(value) if !value.trim().is_empty() => Ok(Some(value.trim().to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    fn fast_import(&self, stream: Vec<u8>) -> Result<(), RemoteHelperError> {
+        self.system.fast_import(stream)
+    }
+
+    fn delete_reference(&self, name: &str) -> Result<(), RemoteHelperError> {
+        trace!("deleting reference: {}", name);
+        self.repo
+            .find_reference(name)
+            .and_then(|mut reference| reference.delete())
+            .map_err(failure("deleting reference"))?;
+        debug!("deleted reference: {}", name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use super::{commit_file, get_head_hash, setup_git_repo};
+
+#[test]
+fn test_libgit2_sha256_unsupported() {
+    let repo_dir = setup_git_repo(true);
+    let git = Libgit2Git::open(repo_dir.path().to_path_buf()).expect("should open");
+    assert!(
+        git.is_none(),
+        "sha256 repositories should be left to SystemGit"
+    );
+}
+
+#[test]
+fn test_libgit2_resolve_reference() {
+    let repo_dir = setup_git_repo(false);
+    commit_file(&repo_dir, "abc", b"example");
+
+    let git = Libgit2Git::open(repo_dir.path().to_path_buf())
+        .expect("failed to open")
+        .expect("should support sha1");
+    let hash = git
+        .resolve_reference("HEAD")
+        .expect("failed to resolve reference");
+    assert_eq!(hash, get_head_hash(&repo_dir));
+    git.resolve_reference("refs/heads/missing")
+        .expect_err("should fail for a missing reference");
+}
+
+#[test]
+fn test_libgit2_objects() {
+    let repo_dir = setup_git_repo(false);
+    commit_file(&repo_dir, "abc", b"example");
+    commit_file(&repo_dir, "def", b"example2");
+    let system = SystemGit::new(repo_dir.path().to_path_buf());
+    let git = Libgit2Git::open(repo_dir.path().to_path_buf())
+        .expect("failed to open")
+        .expect("should support sha1");
+
+    let head = get_head_hash(&repo_dir);
+    let hashes = git
+        .list_objects(head.clone())
+        .expect("failed to list objects");
+    let expected = system.list_objects(head).expect("failed to list objects");
+    assert_eq!(
+        hashes.iter().collect::<HashSet<_>>(),
+        expected.iter().collect::<HashSet<_>>()
+    );
+    assert_eq!(hashes.len(), expected.len());
+    assert_eq!(
+        git.list_all_objects()
+            .expect("failed to list all objects")
+            .len(),
+        expected.len()
+    );
+
+    let objects = git
+        .get_objects(hashes.clone())
+        .expect("failed to get objects");
+    assert_eq!(
+        objects,
+        system.get_objects(hashes).expect("failed to get objects")
+    );
+
+    let other_dir = setup_git_repo(false);
+    let other = Libgit2Git::open(other_dir.path().to_path_buf())
+        .expect("failed to open")
+        .expect("should support sha1");
+    other
+        .save_objects(objects.clone())
+        .expect("failed to save objects");
+    let other_system = SystemGit::new(other_dir.path().to_path_buf());
+    for object in objects {
+        assert_eq!(
+            other_system
+                .get_object(object.get_hash().clone())
+                .expect("failed to get saved object"),
+            object
+        );
+    }
+}
@@ -0,0 +1,238 @@
+use super::{Git, GitVersion};
+use crate::core::hash::Hash;
+use crate::core::object::{Object, ObjectKind};
+use crate::core::remote_helper::error::RemoteHelperError;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+// A whole repository in memory, so tests can set up an object graph once and let Evm walk it
+pub struct InMemoryGit {
+    is_sha256: bool,
+    objects: RefCell<HashMap<Hash, Object>>,
+    references: RefCell<HashMap<String, Hash>>,
+}
+
+impl InMemoryGit {
+    pub fn new(is_sha256: bool) -> Self {
+        Self {
+            is_sha256,
+            objects: RefCell::new(HashMap::new()),
+            references: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_objects(self, objects: Vec<Object>) -> Self {
+        self.objects.borrow_mut().extend(
+            objects
+                .into_iter()
+                .map(|object| (object.get_hash().clone(), object)),
+        );
+        self
+    }
+
+    pub fn with_reference(self, name: &str, hash: Hash) -> Self {
+        self.references.borrow_mut().insert(name.to_string(), hash);
+        self
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.objects.borrow().contains_key(hash)
+    }
+
+    // Same objects as `git rev-list --objects`, fails like it when the graph has holes
+    fn walk(&self, roots: Vec<Hash>) -> Result<Vec<Hash>, RemoteHelperError> {
+        let mut seen = HashSet::new();
+        let mut hashes = vec![];
+        let mut to_visit = roots;
+        to_visit.reverse();
+        while let Some(hash) = to_visit.pop() {
+            if !seen.insert(hash.clone()) {
+                continue;
+            }
+            let object = self.get_object(hash.clone())?;
+            to_visit.extend(object.get_related().iter().rev().cloned());
+            hashes.push(hash);
+        }
+        Ok(hashes)
+    }
+
+    fn unsupported(action: &str) -> RemoteHelperError {
+        RemoteHelperError::Failure {
+            action: action.to_string(),
+            details: Some("not supported by InMemoryGit".to_string()),
+        }
+    }
+}
+
+impl Git for InMemoryGit {
+    fn version(&self) -> Result<GitVersion, RemoteHelperError> {
+        Ok(GitVersion {
+            major: 2,
+            minor: 42,
+            patch: 0,
+        })
+    }
+
+    fn is_sha256(&self) -> Result<bool, RemoteHelperError> {
+        Ok(self.is_sha256)
+    }
+
+    fn resolve_reference(&self, name: &str) -> Result<Hash, RemoteHelperError> {
+        self.references
+            .borrow()
+            .get(name)
+            .cloned()
+            .ok_or(RemoteHelperError::Missing {
+                what: format!("reference {}", name),
+            })
+    }
+
+    fn get_object(&self, hash: Hash) -> Result<Object, RemoteHelperError> {
+        self.objects
+            .borrow()
+            .get(&hash)
+            .cloned()
+            .ok_or(RemoteHelperError::Missing {
+                what: format!("object {}", hash),
+            })
+    }
+
+    fn has_object(&self, hash: &Hash) -> Result<bool, RemoteHelperError> {
+        Ok(self.contains(hash))
+    }
+
+    fn get_objects(&self, hashes: Vec<Hash>) -> Result<Vec<Object>, RemoteHelperError> {
+        hashes
+            .into_iter()
+            .map(|hash| self.get_object(hash))
+            .collect()
+    }
+
+    fn save_object(&self, object: Object) -> Result<(), RemoteHelperError> {
+        self.objects
+            .borrow_mut()
+            .insert(object.get_hash().clone(), object);
+        Ok(())
+    }
+
+    fn save_objects(&self, objects: Vec<Object>) -> Result<(), RemoteHelperError> {
+        objects
+            .into_iter()
+            .try_for_each(|object| self.save_object(object))
+    }
+
+    fn list_objects(&self, hash: Hash) -> Result<Vec<Hash>, RemoteHelperError> {
+        self.walk(vec![hash])
+    }
+
+    fn list_all_objects(&self) -> Result<Vec<Hash>, RemoteHelperError> {
+        let mut roots: Vec<(String, Hash)> = self
+            .references
+            .borrow()
+            .iter()
+            .map(|(name, hash)| (name.clone(), hash.clone()))
+            .collect();
+        // HashMap order is random, tests need a stable one
+        roots.sort_by(|a, b| a.0.cmp(&b.0));
+        self.walk(roots.into_iter().map(|(_, hash)| hash).collect())
+    }
+
+    fn get_address(
+        &self,
+        _protocol: &str,
+        _remote_name: &str,
+    ) -> Result<[u8; 20], RemoteHelperError> {
+        Err(Self::unsupported("getting address"))
+    }
+
+    fn get_config(&self, _key: &str) -> Result<Option<String>, RemoteHelperError> {
+        Ok(None)
+    }
+
+    fn fast_import(&self, _stream: Vec<u8>) -> Result<(), RemoteHelperError> {
+        Err(Self::unsupported("running git fast-import"))
+    }
+
+    fn delete_reference(&self, name: &str) -> Result<(), RemoteHelperError> {
+        self.references
+            .borrow_mut()
+            .remove(name)
+            .map(|_| ())
+            .ok_or(RemoteHelperError::Missing {
+                what: format!("reference {}", name),
+            })
+    }
+}
+
+// A commit with one file per blob, the commit comes first and the blobs last
+pub fn commit_objects(parent: Option<&Hash>, files: &[&[u8]]) -> Vec<Object> {
+    let blobs: Vec<Object> = files
+        .iter()
+        .map(|data| Object::new(ObjectKind::Blob, data.to_vec(), true).expect("should be set"))
+        .collect();
+    let mut tree_data = vec![];
+    for (i, blob) in blobs.iter().enumerate() {
+        tree_data.extend(format!("100644 file{}\0", i).into_bytes());
+        tree_data.extend(hex::decode(blob.get_hash().to_string()).expect("should succeed"));
+    }
+    let tree = Object::new(ObjectKind::Tree, tree_data, true).expect("should be set");
+    let parent = parent.map_or(String::new(), |parent| format!("parent {}\n", parent));
+    let commit_data = format!(
+        "tree {}\n{}author A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nmessage\n",
+        tree.get_hash(),
+        parent
+    );
+    let commit =
+        Object::new(ObjectKind::Commit, commit_data.into_bytes(), true).expect("should be set");
+
+    let mut objects = vec![commit, tree];
+    objects.extend(blobs);
+    objects
+}
+
+#[test]
+fn test_in_memory_git() {
+    let blob = Object::new(ObjectKind::Blob, b"blob".to_vec(), true).expect("should be set");
+    let mut tree_data = b"100644 blob\0".to_vec();
+    tree_data.extend(hex::decode(blob.get_hash().to_string()).expect("should succeed"));
+    let tree = Object::new(ObjectKind::Tree, tree_data, true).expect("should be set");
+    let commit = Object::new(
+        ObjectKind::Commit,
+        format!(
+            "tree {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nmessage\n",
+            tree.get_hash()
+        )
+        .into_bytes(),
+        true,
+    )
+    .expect("should be set");
+
+    let git = InMemoryGit::new(true)
+        .with_objects(vec![commit.clone(), tree.clone()])
+        .with_reference("refs/heads/main", commit.get_hash().clone());
+    assert_eq!(
+        git.resolve_reference("refs/heads/main")
+            .expect("failed to resolve"),
+        commit.get_hash().clone()
+    );
+    git.list_all_objects()
+        .expect_err("should fail while the blob is missing");
+
+    git.save_object(blob.clone()).expect("failed to save");
+    let expected = vec![
+        commit.get_hash().clone(),
+        tree.get_hash().clone(),
+        blob.get_hash().clone(),
+    ];
+    assert_eq!(
+        git.list_objects(commit.get_hash().clone())
+            .expect("failed to list"),
+        expected
+    );
+    assert_eq!(git.list_all_objects().expect("failed to list"), expected);
+
+    git.delete_reference("refs/heads/main")
+        .expect("failed to delete");
+    assert!(git.list_all_objects().expect("failed to list").is_empty());
+    assert!(git.has_object(blob.get_hash()).expect("failed to check"));
+}
@@ -0,0 +1,156 @@
+use super::{Git, GitVersion};
+use crate::core::hash::Hash;
+use crate::core::object::Object;
+use crate::core::remote_helper::error::RemoteHelperError;
+use std::cell::RefCell;
+
+// Keeps saved objects in memory, so tests can assert on everything a fetch saved and in which order
+pub struct RecordingGit {
+    is_sha256: bool,
+    existing: Vec<Hash>,
+    saved: RefCell<Vec<Object>>,
+}
+
+impl RecordingGit {
+    pub fn new(is_sha256: bool) -> Self {
+        Self {
+            is_sha256,
+            existing: vec![],
+            saved: RefCell::new(vec![]),
+        }
+    }
+
+    // Objects reported by list_all_objects as already present
+    pub fn with_existing(mut self, existing: Vec<Hash>) -> Self {
+        self.existing = existing;
+        self
+    }
+
+    pub fn saved_objects(&self) -> Vec<Object> {
+        self.saved.borrow().clone()
+    }
+
+    fn find(&self, hash: &Hash) -> Result<Object, RemoteHelperError> {
+        self.saved
+            .borrow()
+            .iter()
+            .find(|object| object.get_hash() == hash)
+            .cloned()
+            .ok_or(RemoteHelperError::Missing {
+                what: format!("object {}", hash),
+            })
+    }
+
+    fn unsupported(action: &str) -> RemoteHelperError {
+        RemoteHelperError::Failure {
+            action: action.to_string(),
+            details: Some("not supported by RecordingGit".to_string()),
+        }
+    }
+}
+
+impl Git for RecordingGit {
+    fn version(&self) -> Result<GitVersion, RemoteHelperError> {
+        Ok(GitVersion {
+            major: 2,
+            minor: 42,
+            patch: 0,
+        })
+    }
+
+    fn is_sha256(&self) -> Result<bool, RemoteHelperError> {
+        Ok(self.is_sha256)
+    }
+
+    fn resolve_reference(&self, _name: &str) -> Result<Hash, RemoteHelperError> {
+        Err(Self::unsupported("resolving reference"))
+    }
+
+    fn get_object(&self, hash: Hash) -> Result<Object, RemoteHelperError> {
+        self.find(&hash)
+    }
+
+    fn has_object(&self, hash: &Hash) -> Result<bool, RemoteHelperError> {
+        Ok(self.existing.contains(hash) || self.find(hash).is_ok())
+    }
+
+    fn get_objects(&self, hashes: Vec<Hash>) -> Result<Vec<Object>, RemoteHelperError> {
+        hashes.iter().map(|hash| self.find(hash)).collect()
+    }
+
+    fn save_object(&self, object: Object) -> Result<(), RemoteHelperError> {
+        self.saved.borrow_mut().push(object);
+        Ok(())
+    }
+
+    fn save_objects(&self, objects: Vec<Object>) -> Result<(), RemoteHelperError> {
+        self.saved.borrow_mut().extend(objects);
+        Ok(())
+    }
+
+    fn list_objects(&self, _hash: Hash) -> Result<Vec<Hash>, RemoteHelperError> {
+        Err(Self::unsupported("listing objects"))
+    }
+
+    fn list_all_objects(&self) -> Result<Vec<Hash>, RemoteHelperError> {
+        let mut hashes = self.existing.clone();
+        hashes.extend(
+            self.saved
+                .borrow()
+                .iter()
+                .map(|object| object.get_hash().clone()),
+        );
+        Ok(hashes)
+    }
+
+    fn get_address(
+        &self,
+        _protocol: &str,
+        _remote_name: &str,
+    ) -> Result<[u8; 20], RemoteHelperError> {
+        Err(Self::unsupported("getting address"))
+    }
+
+    fn get_config(&self, _key: &str) -> Result<Option<String>, RemoteHelperError> {
+        Ok(None)
+    }
+
+    fn fast_import(&self, _stream: Vec<u8>) -> Result<(), RemoteHelperError> {
+        Err(Self::unsupported("running git fast-import"))
+    }
+
+    fn delete_reference(&self, _name: &str) -> Result<(), RemoteHelperError> {
+        Err(Self::unsupported("deleting reference"))
+    }
+}
+
+#[test]
+fn test_recording_git() {
+    use crate::core::object::ObjectKind;
+
+    let first = Object::new(ObjectKind::Blob, b"first".to_vec(), true).expect("should be set");
+    let second = Object::new(ObjectKind::Blob, b"second".to_vec(), true).expect("should be set");
+    let existing = Hash::from_data(b"existing", true).expect("should be set");
+
+    let git = RecordingGit::new(true).with_existing(vec![existing.clone()]);
+    git.save_object(first.clone()).expect("failed to save");
+    git.save_objects(vec![second.clone()])
+        .expect("failed to save");
+
+    assert_eq!(git.saved_objects(), vec![first.clone(), second.clone()]);
+    assert_eq!(
+        git.list_all_objects().expect("failed to list"),
+        vec![
+            existing.clone(),
+            first.get_hash().clone(),
+            second.get_hash().clone()
+        ]
+    );
+    assert_eq!(
+        git.get_object(second.get_hash().clone())
+            .expect("failed to get"),
+        second
+    );
+    git.get_object(existing)
+        .expect_err("existing objects have no data");
+}
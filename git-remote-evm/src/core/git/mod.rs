@@ -0,0 +1,1422 @@
+use super::address::verify_checksum;
+use super::kv_source::KeyValueSource;
+use super::remote_helper::error::RemoteHelperError;
+use crate::core::hash::Hash;
+use crate::core::object::{Object, ObjectKind};
+use log::{debug, trace, warn};
+use mockall::automock;
+use std::cell::OnceCell;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+#[cfg(feature = "libgit2")]
+pub mod libgit2;
+#[cfg(test)]
+pub mod memory;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GitVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl std::fmt::Display for GitVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+impl GitVersion {
+    // sha256 has been fully supported since git 2.42.0
+    pub fn supports_sha256(&self) -> bool {
+        self.major > 2 || (self.major == 2 && self.minor >= 42)
+    }
+}
+
+// Config is read through the repository, so any backend can be used as a KeyValueSource
+#[automock]
+pub trait Git: KeyValueSource {
+    fn version(&self) -> Result<GitVersion, RemoteHelperError>;
+    fn is_sha256(&self) -> Result<bool, RemoteHelperError>;
+    fn resolve_reference(&self, name: &str) -> Result<Hash, RemoteHelperError>;
+    fn get_object(&self, hash: Hash) -> Result<Object, RemoteHelperError>;
+    // Also true for objects no reference points at, unlike list_all_objects
+    fn has_object(&self, hash: &Hash) -> Result<bool, RemoteHelperError>;
+    fn get_objects(&self, hashes: Vec<Hash>) -> Result<Vec<Object>, RemoteHelperError>;
+    fn save_object(&self, object: Object) -> Result<(), RemoteHelperError>;
+    fn save_objects(&self, objects: Vec<Object>) -> Result<(), RemoteHelperError>;
+    fn list_objects(&self, hash: Hash) -> Result<Vec<Hash>, RemoteHelperError>;
+    fn list_all_objects(&self) -> Result<Vec<Hash>, RemoteHelperError>;
+    fn get_address(&self, protocol: &str, remote_name: &str)
+    -> Result<[u8; 20], RemoteHelperError>;
+    fn get_config(&self, key: &str) -> Result<Option<String>, RemoteHelperError>;
+    fn fast_import(&self, stream: Vec<u8>) -> Result<(), RemoteHelperError>;
+    fn delete_reference(&self, name: &str) -> Result<(), RemoteHelperError>;
+}
+
+impl<T: Git> KeyValueSource for T {
+    fn read(&self, key: &str) -> Result<Option<String>, RemoteHelperError> {
+        self.get_config(key)
+    }
+}
+
+// Expects <protocol>://0x<checksummed address>
+fn address_from_url(protocol: &str, remote_url: &str) -> Result<[u8; 20], RemoteHelperError> {
+    let (scheme, rest) = remote_url
+        .split_once("://")
+        .ok_or(RemoteHelperError::Failure {
+            action: "getting address".to_string(),
+            details: Some(format!("scheme not found in {}", remote_url)),
+        })?;
+    if scheme != protocol {
+        return Err(RemoteHelperError::Invalid {
+            what: "remote url scheme".to_string(),
+            value: format!("{}, but invoked as git-remote-{}", scheme, protocol),
+        });
+    }
+    let address_str = rest.strip_prefix("0x").ok_or(RemoteHelperError::Failure {
+        action: "getting address".to_string(),
+        details: Some(format!("address not found in {}", remote_url)),
+    })?;
+    verify_checksum(address_str)?;
+    let address = hex::decode(address_str).map_err(|e| RemoteHelperError::Failure {
+        action: "decoding address".to_string(),
+        details: Some(e.to_string()),
+    })?;
+    let address: &[u8; 20] = address.as_array().ok_or(RemoteHelperError::Failure {
+        action: "getting address".to_string(),
+        details: None,
+    })?;
+    debug!("got address: {}", address_str);
+    Ok(*address)
+}
+
+// Hashes of different lengths mean the object was hashed for the other object format,
+// which is worth saying instead of only showing two unrelated hashes
+fn saved_hash_mismatch(action: &str, saved: &Hash, expected: &Hash) -> RemoteHelperError {
+    let format = |hash: &Hash| if hash.is_sha256() { "sha256" } else { "sha1" };
+    let details = match saved.is_sha256() == expected.is_sha256() {
+        true => format!("object hash mismatch: {} != {}", saved, expected),
+        false => format!(
+            "object hash mismatch: {} != {}, the repository uses {} but the object was hashed with {}, \
+             the remote likely has a different object format (see extensions.objectFormat)",
+            saved,
+            expected,
+            format(saved),
+            format(expected)
+        ),
+    };
+    RemoteHelperError::Failure {
+        action: action.to_string(),
+        details: Some(details),
+    }
+}
+
+// Reads what `git cat-file --batch` prints for the hashes, in the same order
+fn read_cat_file_batch(
+    mut reader: impl BufRead,
+    hashes: Vec<Hash>,
+) -> Result<Vec<Object>, RemoteHelperError> {
+    let mut objects = Vec::with_capacity(hashes.len());
+    for hash in hashes {
+        let mut header = String::new();
+        reader
+            .read_line(&mut header)
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "reading stdout of git cat-file".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        let parts = header.split_whitespace().collect::<Vec<&str>>();
+        if parts.len() != 3 || parts[0] != hash.to_string() {
+            return Err(RemoteHelperError::Failure {
+                action: "getting object".to_string(),
+                details: Some(format!(
+                    "unexpected cat-file output for {}: {}",
+                    hash,
+                    header.trim()
+                )),
+            });
+        }
+        let kind = ObjectKind::from_str(parts[1])?;
+        let size: usize = parts[2].parse().map_err(|_| RemoteHelperError::Invalid {
+            what: "object size".to_string(),
+            value: parts[2].to_string(),
+        })?;
+
+        // The content is followed by a newline
+        let mut data = vec![0; size + 1];
+        reader
+            .read_exact(&mut data)
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "reading stdout of git cat-file".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        data.truncate(size);
+
+        let object = Object::new(kind, data, hash.is_sha256())?;
+        if &hash != object.get_hash() {
+            return Err(RemoteHelperError::Failure {
+                action: "getting object".to_string(),
+                details: Some(format!(
+                    "object hash mismatch: {} != {}",
+                    hash,
+                    object.get_hash()
+                )),
+            });
+        }
+        objects.push(object);
+    }
+    Ok(objects)
+}
+
+// Without a scope git merges system, global and local config, the last one set wins
+fn read_git_config(
+    path: &Path,
+    scope: Option<ConfigScope>,
+    key: &str,
+) -> Result<Option<String>, RemoteHelperError> {
+    let mut args = vec!["config"];
+    if let Some(scope) = scope {
+        args.push(scope.flag());
+    }
+    args.extend(["--get", key]);
+    let output = Command::new("git")
+        .current_dir(path)
+        .env_remove("GIT_DIR")
+        .args(&args)
+        .output()
+        .map_err(|e| RemoteHelperError::Failure {
+            action: "running git config".to_string(),
+            details: Some(e.to_string()),
+        })?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+
+    let stdout = String::from_utf8(output.stdout).map_err(|e| RemoteHelperError::Failure {
+        action: "reading stdout of git config".to_string(),
+        details: Some(e.to_string()),
+    })?;
+
+    let value = stdout.trim();
+    if value.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(value.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigScope {
+    Local,
+    Global,
+    System,
+}
+
+impl ConfigScope {
+    fn flag(&self) -> &'static str {
+        match self {
+            ConfigScope::Local => "--local",
+            ConfigScope::Global => "--global",
+            ConfigScope::System => "--system",
+        }
+    }
+}
+
+impl FromStr for ConfigScope {
+    type Err = RemoteHelperError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "local" => Ok(ConfigScope::Local),
+            "global" => Ok(ConfigScope::Global),
+            "system" => Ok(ConfigScope::System),
+            _ => Err(RemoteHelperError::Invalid {
+                what: "git config scope".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.flag().trim_start_matches('-'))
+    }
+}
+
+// Reads git config from a single scope, e.g. to find out whether a setting comes from
+// the repository or from a stray global one
+pub struct GitConfigSource {
+    path: PathBuf,
+    scope: ConfigScope,
+}
+
+impl GitConfigSource {
+    pub fn new_scoped(path: PathBuf, scope: ConfigScope) -> Self {
+        Self { path, scope }
+    }
+}
+
+impl KeyValueSource for GitConfigSource {
+    fn read(&self, key: &str) -> Result<Option<String>, RemoteHelperError> {
+        read_git_config(&self.path, Some(self.scope), key)
+    }
+}
+
+#[derive(Clone)]
+pub struct SystemGit {
+    path: PathBuf,
+    // The format is fixed when the repository is created, so it's only read once
+    is_sha256: OnceCell<bool>,
+    version: OnceCell<GitVersion>,
+}
+
+impl SystemGit {
+    pub fn new(path: PathBuf) -> Self {
+        debug!("git commands will run in: {}", path.to_string_lossy());
+        Self {
+            path,
+            is_sha256: OnceCell::new(),
+            version: OnceCell::new(),
+        }
+    }
+}
+
+impl SystemGit {
+    fn save_objects_from(
+        &self,
+        temp_dir: &std::path::Path,
+        objects: &[Object],
+    ) -> Result<(), RemoteHelperError> {
+        let kinds = [
+            ObjectKind::Blob,
+            ObjectKind::Tree,
+            ObjectKind::Commit,
+            ObjectKind::Tag,
+        ];
+        for kind in kinds {
+            let objects: Vec<&Object> = objects
+                .iter()
+                .filter(|object| object.get_kind() == &kind)
+                .collect();
+            if objects.is_empty() {
+                continue;
+            }
+
+            let mut paths = String::new();
+            for (i, object) in objects.iter().enumerate() {
+                let path = temp_dir.join(format!("{}-{}", kind, i));
+                std::fs::write(&path, object.get_data()).map_err(|e| {
+                    RemoteHelperError::Failure {
+                        action: "writing object to temporary file".to_string(),
+                        details: Some(e.to_string()),
+                    }
+                })?;
+                paths.push_str(&format!("{}\n", path.to_string_lossy()));
+            }
+
+            let mut cmd = Command::new("git")
+                .current_dir(self.path.as_path())
+                .env_remove("GIT_DIR")
+                .args([
+                    "hash-object",
+                    "-t",
+                    &kind.to_string(),
+                    "-w",
+                    "--no-filters",
+                    "--stdin-paths",
+                ])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| RemoteHelperError::Failure {
+                    action: "saving objects".to_string(),
+                    details: Some(e.to_string()),
+                })?;
+            cmd.stdin
+                .take()
+                .ok_or(RemoteHelperError::Failure {
+                    action: "saving objects".to_string(),
+                    details: Some("failed to get stdin".to_string()),
+                })?
+                .write_all(paths.as_bytes())
+                .map_err(|e| RemoteHelperError::Failure {
+                    action: "writing paths to stdin".to_string(),
+                    details: Some(e.to_string()),
+                })?;
+
+            let output = cmd
+                .wait_with_output()
+                .map_err(|e| RemoteHelperError::Failure {
+                    action: "getting object hashes".to_string(),
+                    details: Some(e.to_string()),
+                })?;
+            if !output.status.success() {
+                return Err(RemoteHelperError::Failure {
+                    action: "saving objects".to_string(),
+                    details: Some(String::from_utf8_lossy(&output.stderr).to_string()),
+                });
+            }
+
+            let stdout =
+                String::from_utf8(output.stdout).map_err(|e| RemoteHelperError::Failure {
+                    action: "reading stdout of git hash-object".to_string(),
+                    details: Some(e.to_string()),
+                })?;
+            let hashes = stdout
+                .lines()
+                .map(Hash::from_str)
+                .collect::<Result<Vec<_>, _>>()?;
+            if hashes.len() != objects.len() {
+                return Err(RemoteHelperError::Failure {
+                    action: "saving objects".to_string(),
+                    details: Some(format!(
+                        "expected {} hashes, got {}",
+                        objects.len(),
+                        hashes.len()
+                    )),
+                });
+            }
+            for (hash, object) in hashes.iter().zip(objects) {
+                let object_hash = object.get_hash();
+                if hash != object_hash {
+                    return Err(saved_hash_mismatch("saving objects", hash, object_hash));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rev_list(&self, name: &str) -> Result<Vec<Hash>, RemoteHelperError> {
+        let output = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(&["rev-list", "--objects", name])
+            .output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "running git rev-list".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        if !output.status.success() {
+            return Err(RemoteHelperError::Failure {
+                action: "running git rev-list".to_string(),
+                details: Some(String::from_utf8_lossy(&output.stderr).to_string()),
+            });
+        }
+
+        let stdout = String::from_utf8(output.stdout).map_err(|e| RemoteHelperError::Failure {
+            action: "reading stdout of git rev-list".to_string(),
+            details: Some(e.to_string()),
+        })?;
+
+        let mut hashes = vec![];
+        for line in stdout.lines() {
+            let hash_str = line
+                .split_whitespace()
+                .next()
+                .ok_or(RemoteHelperError::Failure {
+                    action: "getting hash from line".to_string(),
+                    details: Some(line.to_string()),
+                })?;
+            let hash = Hash::from_str(hash_str).map_err(|e| RemoteHelperError::Failure {
+                action: "parsing hash".to_string(),
+                details: Some(e.to_string()),
+            })?;
+            hashes.push(hash);
+        }
+        Ok(hashes)
+    }
+}
+
+impl Git for SystemGit {
+    fn version(&self) -> Result<GitVersion, RemoteHelperError> {
+        if let Some(version) = self.version.get() {
+            return Ok(*version);
+        }
+        let output = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(&["--version"])
+            .output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "getting git version".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        let stdout = String::from_utf8(output.stdout).map_err(|e| RemoteHelperError::Failure {
+            action: "reading stdout of git --version".to_string(),
+            details: Some(e.to_string()),
+        })?;
+        let version =
+            stdout
+                .trim()
+                .strip_prefix("git version ")
+                .ok_or(RemoteHelperError::Failure {
+                    action: "parsing git version".to_string(),
+                    details: Some(stdout.to_string()),
+                })?;
+
+        let parts: Vec<&str> = version.split('.').collect();
+        if parts.len() != 3 {
+            return Err(RemoteHelperError::Failure {
+                action: "parsing git version".to_string(),
+                details: Some(version.to_string()),
+            });
+        }
+
+        let major = parts[0]
+            .parse::<u32>()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "parsing git major version".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        let minor = parts[1]
+            .parse::<u32>()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "parsing git minor version".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        let patch = parts[2]
+            .parse::<u32>()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "parsing git patch version".to_string(),
+                details: Some(e.to_string()),
+            })?;
+
+        let version = GitVersion {
+            major,
+            minor,
+            patch,
+        };
+        trace!("retrieved git version: {}", version);
+        Ok(*self.version.get_or_init(|| version))
+    }
+
+    fn is_sha256(&self) -> Result<bool, RemoteHelperError> {
+        if let Some(is_sha256) = self.is_sha256.get() {
+            return Ok(*is_sha256);
+        }
+        let output = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(["rev-parse", "--show-object-format"])
+            .output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "getting object format".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        if !output.status.success() {
+            return Err(RemoteHelperError::Failure {
+                action: "getting object format".to_string(),
+                details: Some(String::from_utf8_lossy(&output.stderr).to_string()),
+            });
+        }
+        let stdout = String::from_utf8(output.stdout).map_err(|e| RemoteHelperError::Failure {
+            action: "reading stdout of git rev-parse".to_string(),
+            details: Some(e.to_string()),
+        })?;
+
+        let is_sha256 = match stdout.trim() {
+            "sha1" => false,
+            "sha256" => true,
+            format => {
+                return Err(RemoteHelperError::Invalid {
+                    what: "object format".to_string(),
+                    value: format.to_string(),
+                });
+            }
+        };
+        trace!("object format is sha256: {}", is_sha256);
+        Ok(*self.is_sha256.get_or_init(|| is_sha256))
+    }
+
+    fn get_address(
+        &self,
+        protocol: &str,
+        remote_name: &str,
+    ) -> Result<[u8; 20], RemoteHelperError> {
+        trace!(
+            "getting address: {} in {}",
+            remote_name,
+            self.path.to_string_lossy()
+        );
+        let output = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(&["remote", "get-url", remote_name])
+            .output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "getting remote url".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        if !output.status.success() {
+            return Err(RemoteHelperError::Failure {
+                action: "getting remote url".to_string(),
+                details: Some(String::from_utf8_lossy(&output.stderr).to_string()),
+            });
+        }
+
+        let remote_url =
+            String::from_utf8(output.stdout).map_err(|e| RemoteHelperError::Failure {
+                action: "reading stdout of git remote get-url".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        address_from_url(protocol, remote_url.trim())
+    }
+
+    fn resolve_reference(&self, name: &str) -> Result<Hash, RemoteHelperError> {
+        trace!(
+            "resolving reference: {} in {}",
+            name,
+            self.path.to_string_lossy()
+        );
+        let output = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(&["rev-parse", name])
+            .output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "resolving reference".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        if !output.status.success() {
+            return Err(RemoteHelperError::Failure {
+                action: "resolving reference".to_string(),
+                details: Some(String::from_utf8_lossy(&output.stderr).to_string()),
+            });
+        }
+        let stdout = String::from_utf8(output.stdout).map_err(|e| RemoteHelperError::Failure {
+            action: "reading stdout of git rev-parse".to_string(),
+            details: Some(e.to_string()),
+        })?;
+        let hash = Hash::from_str(stdout.trim()).map_err(|e| RemoteHelperError::Failure {
+            action: "parsing hash".to_string(),
+            details: Some(e.to_string()),
+        })?;
+        debug!("resolved reference {}: {}", name, hash);
+        Ok(hash)
+    }
+
+    fn get_object(&self, hash: Hash) -> Result<Object, RemoteHelperError> {
+        trace!(
+            "getting object: {} in {}",
+            hash,
+            self.path.to_string_lossy()
+        );
+        let output = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(&["cat-file", "-t", &hash.to_string()])
+            .output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "getting object type".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        if !output.status.success() {
+            return Err(RemoteHelperError::Failure {
+                action: "getting object type".to_string(),
+                details: Some(format!("git cat-file -t {} failed", hash)),
+            });
+        }
+        let stdout = String::from_utf8(output.stdout).map_err(|e| RemoteHelperError::Failure {
+            action: "reading stdout of git cat-file".to_string(),
+            details: Some(e.to_string()),
+        })?;
+        let kind = ObjectKind::from_str(stdout.trim()).map_err(|e| RemoteHelperError::Failure {
+            action: "parsing object type".to_string(),
+            details: Some(e.to_string()),
+        })?;
+
+        let output = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(&["cat-file", kind.to_string().as_str(), &hash.to_string()])
+            .output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "getting object type".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        let object = Object::new(kind, output.stdout, hash.is_sha256())?;
+        debug!("got object {}: {}", hash, object.get_kind());
+
+        if &hash != object.get_hash() {
+            return Err(RemoteHelperError::Failure {
+                action: "getting object".to_string(),
+                details: Some(format!(
+                    "object hash mismatch: {} != {}",
+                    hash,
+                    object.get_hash()
+                )),
+            });
+        }
+
+        Ok(object)
+    }
+
+    fn has_object(&self, hash: &Hash) -> Result<bool, RemoteHelperError> {
+        let output = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(["cat-file", "-e", &hash.to_string()])
+            .output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "checking if object exists".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        trace!("object {} exists: {}", hash, output.status.success());
+        Ok(output.status.success())
+    }
+
+    // Reads all objects through a single `git cat-file --batch` instead of spawning git per object
+    fn get_objects(&self, hashes: Vec<Hash>) -> Result<Vec<Object>, RemoteHelperError> {
+        trace!(
+            "getting {} objects in {}",
+            hashes.len(),
+            self.path.to_string_lossy()
+        );
+        let mut cmd = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "running git cat-file --batch".to_string(),
+                details: Some(e.to_string()),
+            })?;
+
+        let mut stdin = cmd.stdin.take().ok_or(RemoteHelperError::Failure {
+            action: "running git cat-file --batch".to_string(),
+            details: Some("failed to get stdin".to_string()),
+        })?;
+        let input: String = hashes.iter().map(|hash| format!("{}\n", hash)).collect();
+        // Written from another thread so a full stdout pipe can't block git while we're still writing
+        let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+        let objects = match cmd.stdout.take() {
+            Some(stdout) => read_cat_file_batch(BufReader::new(stdout), hashes),
+            None => Err(RemoteHelperError::Failure {
+                action: "running git cat-file --batch".to_string(),
+                details: Some("failed to get stdout".to_string()),
+            }),
+        };
+        // Dropping the child would leave a zombie, and the writer blocked on a pipe nobody reads
+        let objects = match objects {
+            Ok(objects) => objects,
+            Err(e) => {
+                if let Err(e) = cmd.kill().and_then(|_| cmd.wait()) {
+                    warn!("failed to stop git cat-file --batch: {}", e);
+                }
+                let _ = writer.join();
+                return Err(e);
+            }
+        };
+
+        writer
+            .join()
+            .map_err(|_| RemoteHelperError::Failure {
+                action: "writing hashes to stdin".to_string(),
+                details: None,
+            })?
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "writing hashes to stdin".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        let output = cmd
+            .wait_with_output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "running git cat-file --batch".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        if !output.status.success() {
+            return Err(RemoteHelperError::Failure {
+                action: "running git cat-file --batch".to_string(),
+                details: Some(String::from_utf8_lossy(&output.stderr).to_string()),
+            });
+        }
+        debug!("got {} objects", objects.len());
+
+        Ok(objects)
+    }
+
+    fn save_object(&self, object: Object) -> Result<(), RemoteHelperError> {
+        trace!(
+            "saving object: {} in {}",
+            object.get_kind(),
+            self.path.to_string_lossy()
+        );
+        let mut cmd = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(&[
+                "hash-object",
+                "-t",
+                &object.get_kind().to_string(),
+                "-w",
+                "--stdin",
+            ])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "saving object".to_string(),
+                details: Some(e.to_string()),
+            })?;
+
+        cmd.stdin
+            .take()
+            .ok_or(RemoteHelperError::Failure {
+                action: "saving object".to_string(),
+                details: Some("failed to get stdin".to_string()),
+            })?
+            .write_all(object.get_data())
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "writing object to stdin".to_string(),
+                details: Some(e.to_string()),
+            })?;
+
+        let output = cmd
+            .wait_with_output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "getting object hash".to_string(),
+                details: Some(e.to_string()),
+            })?;
+
+        if !output.status.success() {
+            let stderr =
+                String::from_utf8(output.stderr).map_err(|e| RemoteHelperError::Failure {
+                    action: "reading stderr of git hash-object".to_string(),
+                    details: Some(e.to_string()),
+                })?;
+            return Err(RemoteHelperError::Failure {
+                action: "saving object".to_string(),
+                details: Some(stderr),
+            });
+        }
+
+        let stdout = String::from_utf8(output.stdout).map_err(|e| RemoteHelperError::Failure {
+            action: "reading stdout of git hash-object".to_string(),
+            details: Some(e.to_string()),
+        })?;
+        let hash = Hash::from_str(stdout.trim()).map_err(|e| RemoteHelperError::Failure {
+            action: "parsing saved object's hash".to_string(),
+            details: Some(e.to_string()),
+        })?;
+
+        let object_hash = object.get_hash();
+        if &hash != object_hash {
+            return Err(saved_hash_mismatch("saving object", &hash, object_hash));
+        }
+        debug!("saved object: {}", hash);
+
+        Ok(())
+    }
+
+    // Writes the objects to temporary files and saves them with one `git hash-object --stdin-paths` per kind
+    fn save_objects(&self, objects: Vec<Object>) -> Result<(), RemoteHelperError> {
+        trace!(
+            "saving {} objects in {}",
+            objects.len(),
+            self.path.to_string_lossy()
+        );
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let temp_dir =
+            std::env::temp_dir().join(format!("gitdem-{}-{}", std::process::id(), nanos));
+        std::fs::create_dir_all(&temp_dir).map_err(|e| RemoteHelperError::Failure {
+            action: "creating temporary directory".to_string(),
+            details: Some(e.to_string()),
+        })?;
+
+        let result = self.save_objects_from(&temp_dir, &objects);
+        if let Err(e) = std::fs::remove_dir_all(&temp_dir) {
+            warn!("failed to remove {}: {}", temp_dir.to_string_lossy(), e);
+        }
+        result?;
+        debug!("saved {} objects", objects.len());
+
+        Ok(())
+    }
+
+    fn list_objects(&self, hash: Hash) -> Result<Vec<Hash>, RemoteHelperError> {
+        trace!(
+            "listing objects: {} in {}",
+            hash,
+            self.path.to_string_lossy()
+        );
+        let hashes = self.rev_list(&hash.to_string())?;
+        debug!("got objects: {:?}", hashes);
+        Ok(hashes)
+    }
+
+    fn list_all_objects(&self) -> Result<Vec<Hash>, RemoteHelperError> {
+        trace!("listing all objects in {}", self.path.to_string_lossy());
+        let hashes = self.rev_list("--all")?;
+        debug!("got objects: {:?}", hashes);
+        Ok(hashes)
+    }
+
+    fn get_config(&self, key: &str) -> Result<Option<String>, RemoteHelperError> {
+        read_git_config(&self.path, None, key)
+    }
+
+    fn fast_import(&self, stream: Vec<u8>) -> Result<(), RemoteHelperError> {
+        trace!("running fast-import in {}", self.path.to_string_lossy());
+        let mut cmd = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(["fast-import", "--quiet", "--force"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "running git fast-import".to_string(),
+                details: Some(e.to_string()),
+            })?;
+
+        cmd.stdin
+            .take()
+            .ok_or(RemoteHelperError::Failure {
+                action: "running git fast-import".to_string(),
+                details: Some("failed to get stdin".to_string()),
+            })?
+            .write_all(&stream)
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "writing stream to stdin".to_string(),
+                details: Some(e.to_string()),
+            })?;
+
+        let output = cmd
+            .wait_with_output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "running git fast-import".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        if !output.status.success() {
+            return Err(RemoteHelperError::Failure {
+                action: "running git fast-import".to_string(),
+                details: Some(String::from_utf8_lossy(&output.stderr).to_string()),
+            });
+        }
+        debug!("imported stream of {} bytes", stream.len());
+        Ok(())
+    }
+
+    fn delete_reference(&self, name: &str) -> Result<(), RemoteHelperError> {
+        trace!(
+            "deleting reference: {} in {}",
+            name,
+            self.path.to_string_lossy()
+        );
+        let output = Command::new("git")
+            .current_dir(self.path.as_path())
+            .env_remove("GIT_DIR")
+            .args(["update-ref", "-d", name])
+            .output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: "deleting reference".to_string(),
+                details: Some(e.to_string()),
+            })?;
+        if !output.status.success() {
+            return Err(RemoteHelperError::Failure {
+                action: "deleting reference".to_string(),
+                details: Some(String::from_utf8_lossy(&output.stderr).to_string()),
+            });
+        }
+        debug!("deleted reference: {}", name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn setup_git_repo(is_sha256: bool) -> tempfile::TempDir {
+    let temp_dir = tempfile::tempdir().expect("failed to create temp dir");
+
+    let object_format = if is_sha256 { "sha256" } else { "sha1" };
+
+    let output = Command::new("git")
+        .current_dir(temp_dir.path())
+        .args(&["init", &format!("--object-format={}", object_format)])
+        .output()
+        .expect("failed to run git init");
+    if !output.status.success() {
+        panic!(
+            "git init failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    temp_dir
+}
+
+#[test]
+fn test_resolve_reference() {
+    let repo_dir = setup_git_repo(true);
+
+    let mut file =
+        std::fs::File::create(repo_dir.path().join("abc")).expect("failed to create abc file");
+    file.write_all(b"example").expect("failed to write abc");
+
+    let cmd = Command::new("git")
+        .current_dir(repo_dir.path())
+        .args(&["add", "abc"])
+        .output()
+        .expect("failed to run git add");
+    if !cmd.status.success() {
+        panic!("git add failed: {}", String::from_utf8_lossy(&cmd.stderr));
+    }
+    let cmd = Command::new("git")
+        .current_dir(repo_dir.path())
+        .args(&["commit", "-m", "something"])
+        .output()
+        .expect("failed to run git hash-object");
+    if !cmd.status.success() {
+        panic!(
+            "git commit failed: {}",
+            String::from_utf8_lossy(&cmd.stderr)
+        );
+    }
+
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+    let hash = git
+        .resolve_reference("HEAD")
+        .expect("failed to resolve reference");
+    assert_eq!(hash, get_head_hash(&repo_dir));
+}
+
+#[test]
+fn test_save_object() {
+    let repo_dir = setup_git_repo(true);
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+
+    let data = b"test";
+    let object =
+        Object::new(ObjectKind::Blob, data.to_vec(), true).expect("failed to create object");
+    git.save_object(object).expect("failed to save object");
+}
+
+#[test]
+fn test_has_object() {
+    let repo_dir = setup_git_repo(true);
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+
+    let object =
+        Object::new(ObjectKind::Blob, b"test".to_vec(), true).expect("failed to create object");
+    let hash = object.get_hash().clone();
+    assert!(!git.has_object(&hash).expect("failed to check object"));
+    git.save_object(object).expect("failed to save object");
+    assert!(git.has_object(&hash).expect("failed to check object"));
+}
+
+#[cfg(test)]
+fn get_head_hash(repo_dir: &tempfile::TempDir) -> Hash {
+    let cmd = Command::new("git")
+        .current_dir(repo_dir.path())
+        .args(&["rev-parse", "HEAD"])
+        .output()
+        .expect("failed to run git rev-parse");
+    if !cmd.status.success() {
+        let stderr = String::from_utf8_lossy(&cmd.stderr);
+        panic!("git rev-parse failed: {}", stderr);
+    }
+    let stdout = String::from_utf8(cmd.stdout).expect("failed to convert stdout to string");
+    Hash::from_str(stdout.trim()).expect("failed to parse hash")
+}
+
+#[cfg(test)]
+fn commit_file(repo_dir: &tempfile::TempDir, file_name: &str, content: &[u8]) {
+    let mut file =
+        std::fs::File::create(repo_dir.path().join(file_name)).expect("failed to create abc file");
+    file.write_all(content).expect("failed to write abc");
+
+    let cmd = Command::new("git")
+        .current_dir(repo_dir.path())
+        .args(&["add", file_name])
+        .output()
+        .expect("failed to run git add");
+    if !cmd.status.success() {
+        panic!("git add failed: {}", String::from_utf8_lossy(&cmd.stderr));
+    }
+    let cmd = Command::new("git")
+        .current_dir(repo_dir.path())
+        .args(&["commit", "-m", "something"])
+        .output()
+        .expect("failed to run git hash-object");
+    if !cmd.status.success() {
+        panic!(
+            "git commit failed:\nstdout: {}\nstderr: {}",
+            String::from_utf8_lossy(&cmd.stdout),
+            String::from_utf8_lossy(&cmd.stderr)
+        );
+    }
+}
+
+#[test]
+fn test_get_object() {
+    let repo_dir = setup_git_repo(false);
+
+    let blob0_content = b"example";
+    let blob1_content = b"example2";
+    commit_file(&repo_dir, "abc", blob0_content);
+    commit_file(&repo_dir, "def", blob1_content);
+
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+    let object = git
+        .get_object(get_head_hash(&repo_dir))
+        .expect("failed to get object");
+    assert_eq!(object.get_kind(), &ObjectKind::Commit);
+    let related_objects = object.get_related();
+    assert_eq!(related_objects.len(), 2);
+
+    let object = git
+        .get_object(related_objects[0].clone())
+        .expect("failed to get tree object");
+    assert_eq!(object.get_kind(), &ObjectKind::Tree);
+    let related_objects = object.get_related();
+    assert_eq!(related_objects.len(), 2);
+
+    let blob0 = git
+        .get_object(related_objects[0].clone())
+        .expect("failed to get blob object");
+    assert_eq!(blob0.get_kind(), &ObjectKind::Blob);
+    assert_eq!(blob0.get_data(), blob0_content);
+
+    let blob1 = git
+        .get_object(related_objects[1].clone())
+        .expect("failed to get blob object");
+    assert_eq!(blob1.get_kind(), &ObjectKind::Blob);
+    assert_eq!(blob1.get_data(), blob1_content);
+}
+
+#[test]
+fn test_save_objects() {
+    let source_dir = setup_git_repo(false);
+    commit_file(&source_dir, "abc", b"example");
+    commit_file(&source_dir, "def", b"example2");
+    let source = SystemGit::new(source_dir.path().to_path_buf());
+    let hashes = source
+        .list_objects(get_head_hash(&source_dir))
+        .expect("failed to list objects");
+    let objects = source
+        .get_objects(hashes.clone())
+        .expect("failed to get objects");
+
+    let repo_dir = setup_git_repo(false);
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+    git.save_objects(objects.clone())
+        .expect("failed to save objects");
+    for object in objects {
+        assert_eq!(
+            git.get_object(object.get_hash().clone())
+                .expect("failed to get saved object"),
+            object
+        );
+    }
+
+    git.save_objects(vec![]).expect("failed to save no objects");
+}
+
+#[test]
+fn test_get_objects() {
+    let repo_dir = setup_git_repo(true);
+
+    commit_file(&repo_dir, "abc", b"example");
+    commit_file(&repo_dir, "def", b"example2");
+
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+    let hashes = git
+        .list_objects(get_head_hash(&repo_dir))
+        .expect("failed to list objects");
+    let objects = git
+        .get_objects(hashes.clone())
+        .expect("failed to get objects");
+    assert_eq!(objects.len(), hashes.len());
+    for (object, hash) in objects.iter().zip(hashes.iter()) {
+        assert_eq!(object.get_hash(), hash);
+        assert_eq!(
+            object,
+            &git.get_object(hash.clone()).expect("failed to get object")
+        );
+    }
+
+    assert!(
+        git.get_objects(vec![])
+            .expect("failed to get no objects")
+            .is_empty()
+    );
+    git.get_objects(vec![
+        Hash::from_data(b"missing", true).expect("should be set"),
+    ])
+    .expect_err("should fail for a missing object");
+
+    // git is stopped while it still has hashes to read, the call returns instead of hanging
+    let mut hashes: Vec<Hash> = hashes.iter().cycle().take(10_000).cloned().collect();
+    hashes.insert(0, Hash::from_data(b"missing", true).expect("should be set"));
+    git.get_objects(hashes)
+        .expect_err("should fail for a missing object");
+}
+
+#[test]
+fn test_get_address() {
+    let repo_dir = setup_git_repo(true);
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+
+    let add_remote = |remote_name: &str, url: &str| {
+        let cmd = Command::new("git")
+            .current_dir(repo_dir.path())
+            .args(&["remote", "add", remote_name, url])
+            .output()
+            .expect("failed to run git remote add");
+        if !cmd.status.success() {
+            panic!(
+                "git remote add failed: {}",
+                String::from_utf8_lossy(&cmd.stderr)
+            );
+        }
+    };
+
+    add_remote("origin", "eth://0x0000000000000000000000000000000000000000");
+    let address = git
+        .get_address("eth", "origin")
+        .expect("failed to get address");
+    assert_eq!(
+        hex::encode(address),
+        "0000000000000000000000000000000000000000"
+    );
+
+    add_remote(
+        "upstream",
+        "arb1://0xc6093fd9cc143f9f058938868b2df2daf9a91d28",
+    );
+    let address = git
+        .get_address("arb1", "upstream")
+        .expect("failed to get address");
+    assert_eq!(
+        hex::encode(address).to_lowercase(),
+        "c6093fd9cc143f9f058938868b2df2daf9a91d28"
+    );
+
+    let err = git
+        .get_address("arb1", "origin")
+        .expect_err("should fail because of scheme mismatch");
+    match err {
+        RemoteHelperError::Invalid { what, value } => {
+            assert_eq!(what, "remote url scheme");
+            assert!(value.contains("eth") && value.contains("arb1"));
+        }
+        _ => panic!("unexpected error: {:?}", err),
+    }
+
+    add_remote(
+        "checksummed",
+        "eth://0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+    );
+    git.get_address("eth", "checksummed")
+        .expect("failed to get checksummed address");
+    add_remote("typo", "eth://0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAeD");
+    git.get_address("eth", "typo")
+        .expect_err("should fail because of invalid checksum");
+
+    add_remote("noscheme", "0xc6093fd9cc143f9f058938868b2df2daf9a91d28");
+    git.get_address("eth", "noscheme")
+        .expect_err("should fail without a scheme");
+}
+
+#[test]
+fn test_get_version() {
+    let repo_dir = setup_git_repo(true);
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+    let version = git.version().expect("failed to get version");
+    assert!(version.major >= 1);
+
+    // Cached, git can't run in a directory that's gone
+    drop(repo_dir);
+    assert_eq!(git.version().expect("should be cached"), version);
+}
+
+#[test]
+fn test_supports_sha256() {
+    let cases = [
+        ((1, 99, 0), false),
+        ((2, 41, 9), false),
+        ((2, 42, 0), true),
+        ((2, 50, 1), true),
+        ((3, 0, 0), true),
+    ];
+    for ((major, minor, patch), expected) in cases {
+        let version = GitVersion {
+            major,
+            minor,
+            patch,
+        };
+        assert_eq!(version.supports_sha256(), expected, "{}", version);
+    }
+}
+
+#[test]
+fn test_is_sha256() {
+    let repo_dir = setup_git_repo(true);
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+    assert!(git.is_sha256().expect("failed to get object format"));
+
+    let repo_dir = setup_git_repo(false);
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+    assert!(!git.is_sha256().expect("failed to get object format"));
+
+    // Cached, git isn't asked again even though the repository is gone
+    drop(repo_dir);
+    assert!(!git.is_sha256().expect("should be cached"));
+}
+
+#[test]
+fn test_read_config() {
+    let repo_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let cmd = Command::new("git")
+        .arg("init")
+        .current_dir(repo_dir.path().to_path_buf())
+        .output()
+        .expect("failed to run git init");
+    if !cmd.status.success() {
+        panic!("git init failed: {}", String::from_utf8_lossy(&cmd.stderr));
+    }
+
+    let key = "some.key";
+    let value = "123456";
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+
+    let cmd = Command::new("git")
+        .arg("config")
+        .arg(key)
+        .arg(value)
+        .current_dir(repo_dir.path())
+        .output()
+        .expect("failed to run git config");
+    if !cmd.status.success() {
+        panic!(
+            "git config failed: {}",
+            String::from_utf8_lossy(&cmd.stderr)
+        );
+    }
+    let read_value = git
+        .get_config(key)
+        .expect("failed to read config")
+        .expect("doesn't have value");
+    assert_eq!(read_value, value.to_string());
+
+    let cmd = Command::new("git")
+        .arg("config")
+        .arg("--unset")
+        .arg(key)
+        .current_dir(repo_dir.path())
+        .output()
+        .expect("failed to run git config");
+    if !cmd.status.success() {
+        panic!(
+            "git config failed: {}",
+            String::from_utf8_lossy(&cmd.stderr)
+        );
+    }
+    let read_value = git.get_config(key).expect("failed to read config");
+    assert!(read_value.is_none());
+}
+
+#[test]
+fn test_fast_import() {
+    let repo_dir = setup_git_repo(false);
+    let git = SystemGit::new(repo_dir.path().to_path_buf());
+
+    let stream = b"feature done\n\
+blob\n\
+mark :1\n\
+data 7\n\
+example\n\
+commit refs/heads/imported\n\
+mark :2\n\
+committer A <a@example.com> 0 +0000\n\
+data 7\n\
+message\n\
+M 100644 :1 abc\n\
+\n\
+done\n";
+    git.fast_import(stream.to_vec())
+        .expect("failed to import stream");
+    let hash = git
+        .resolve_reference("refs/heads/imported")
+        .expect("failed to resolve reference");
+    assert_eq!(
+        git.list_objects(hash)
+            .expect("failed to list objects")
+            .len(),
+        3
+    );
+
+    git.delete_reference("refs/heads/imported")
+        .expect("failed to delete reference");
+    git.resolve_reference("refs/heads/imported")
+        .expect_err("reference should be deleted");
+
+    git.fast_import(b"data 100\n".to_vec())
+        .expect_err("should fail with invalid stream");
+}
+
+#[test]
+fn test_config_scope() {
+    let repo_dir = setup_git_repo(false);
+    let cmd = Command::new("git")
+        .args(["config", "--local", "some.scoped-key", "local"])
+        .current_dir(repo_dir.path())
+        .output()
+        .expect("failed to run git config");
+    assert!(cmd.status.success());
+
+    let local = GitConfigSource::new_scoped(repo_dir.path().to_path_buf(), ConfigScope::Local);
+    assert_eq!(
+        local.read("some.scoped-key").expect("failed to read"),
+        Some("local".to_string())
+    );
+    let system = GitConfigSource::new_scoped(repo_dir.path().to_path_buf(), ConfigScope::System);
+    assert_eq!(
+        system.read("some.scoped-key").expect("failed to read"),
+        None
+    );
+
+    assert_eq!(ConfigScope::from_str("global"), Ok(ConfigScope::Global));
+    assert_eq!(ConfigScope::Global.to_string(), "global");
+    ConfigScope::from_str("worktree").expect_err("should fail");
+}
+
+#[test]
+fn test_saved_hash_mismatch() {
+    let sha1 = Hash::from_data(b"saved", false).expect("should be set");
+    let other_sha1 = Hash::from_data(b"expected", false).expect("should be set");
+    let sha256 = Hash::from_data(b"expected", true).expect("should be set");
+
+    assert_eq!(
+        saved_hash_mismatch("saving object", &sha1, &other_sha1),
+        RemoteHelperError::Failure {
+            action: "saving object".to_string(),
+            details: Some(format!("object hash mismatch: {} != {}", sha1, other_sha1)),
+        }
+    );
+    let RemoteHelperError::Failure {
+        details: Some(details),
+        ..
+    } = saved_hash_mismatch("saving object", &sha1, &sha256)
+    else {
+        panic!("expected a failure with details");
+    };
+    assert!(details.contains("the repository uses sha1 but the object was hashed with sha256"));
+    assert!(details.contains("extensions.objectFormat"));
+}
@@ -1,43 +1,148 @@
 use alloy::primitives::FixedBytes;
-use regex::Regex;
-use std::{fmt, hash::Hash as StdHash, str::FromStr, sync::LazyLock};
+use std::{fmt, hash::Hash as StdHash, str::FromStr};
 
 use super::remote_helper::error::RemoteHelperError;
 
-static HASH_REGEX_SHA1: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"^[0-9a-f]{40}$").expect("failed to create sha1 regex"));
-static HASH_REGEX_SHA256: LazyLock<Regex> =
-    LazyLock::new(|| Regex::new(r"^[0-9a-f]{64}$").expect("failed to create sha256 regex"));
+// Hashes are stored in 32 byte slots on-chain, shorter ones are padded with trailing zeros
+const PADDED_LEN_HEX: usize = 64;
+
+// An object format git can use, implemented by marker types and dispatched through HashKind
+pub trait HashAlgo {
+    fn len_hex() -> usize;
+    // Hashes the parts as if they were concatenated
+    fn digest(parts: &[&[u8]]) -> String;
+}
+
+pub struct Sha1;
+
+impl HashAlgo for Sha1 {
+    fn len_hex() -> usize {
+        40
+    }
+
+    fn digest(parts: &[&[u8]]) -> String {
+        use sha1::Digest;
+        let mut hasher = sha1::Sha1::new();
+        parts.iter().for_each(|part| hasher.update(part));
+        hex::encode(hasher.finalize())
+    }
+}
+
+pub struct Sha256;
+
+impl HashAlgo for Sha256 {
+    fn len_hex() -> usize {
+        64
+    }
+
+    fn digest(parts: &[&[u8]]) -> String {
+        use sha2::Digest;
+        let mut hasher = sha2::Sha256::new();
+        parts.iter().for_each(|part| hasher.update(part));
+        hex::encode(hasher.finalize())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, StdHash)]
+pub enum HashKind {
+    Sha1,
+    Sha256,
+}
+
+impl HashKind {
+    // Order matters when parsing, a padded sha1 hash is also a valid sha256 one
+    const ALL: [HashKind; 2] = [HashKind::Sha1, HashKind::Sha256];
+
+    pub fn len_hex(self) -> usize {
+        match self {
+            HashKind::Sha1 => Sha1::len_hex(),
+            HashKind::Sha256 => Sha256::len_hex(),
+        }
+    }
+
+    // Length of the raw hash, as it appears in tree entries
+    pub fn len_bytes(self) -> usize {
+        self.len_hex() / 2
+    }
+
+    fn digest(self, parts: &[&[u8]]) -> String {
+        match self {
+            HashKind::Sha1 => Sha1::digest(parts),
+            HashKind::Sha256 => Sha256::digest(parts),
+        }
+    }
+
+    pub fn from_sha256(is_sha256: bool) -> Self {
+        match is_sha256 {
+            true => HashKind::Sha256,
+            false => HashKind::Sha1,
+        }
+    }
+
+    // Accepts the hash as git prints it or padded the way the contract stores it
+    fn parse(self, s: &str) -> Option<String> {
+        // Slicing below is by byte, a multi-byte character could straddle the boundary
+        if !s.is_ascii() {
+            return None;
+        }
+        let len = self.len_hex();
+        let hex = match s.len() {
+            n if n == len => s,
+            PADDED_LEN_HEX if s[len..].bytes().all(|c| c == b'0') => &s[..len],
+            _ => return None,
+        };
+        hex.bytes()
+            .all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+            .then(|| hex.to_string())
+    }
+}
 
 #[derive(Clone, Debug, PartialEq, Eq, StdHash)]
-pub enum Hash {
-    Sha1(String),
-    Sha256(String),
+pub struct Hash {
+    kind: HashKind,
+    hex: String,
 }
 
 impl Hash {
+    pub fn algo(&self) -> HashKind {
+        self.kind
+    }
+
     pub fn is_sha256(&self) -> bool {
-        matches!(self, Self::Sha256(_))
+        self.algo() == HashKind::Sha256
+    }
+
+    // git uses the null hash for references that don't exist
+    pub fn is_zero(&self) -> bool {
+        self.hex.bytes().all(|b| b == b'0')
+    }
+
+    // Stored for a reference to delete it, see is_zero
+    pub fn zero(is_sha256: bool) -> Self {
+        let kind = HashKind::from_sha256(is_sha256);
+        Self {
+            kind,
+            hex: "0".repeat(kind.len_hex()),
+        }
     }
 
     pub fn padded(&self) -> String {
         // pad with trailing zeros to make it 64 characters long
-        match self {
-            Self::Sha1(s) => s.clone() + &"0".repeat(64 - s.len()),
-            Self::Sha256(s) => s.clone(),
-        }
+        self.hex.clone() + &"0".repeat(PADDED_LEN_HEX - self.algo().len_hex())
     }
 
+    #[cfg(test)]
     pub fn from_data(data: &[u8], is_sha256: bool) -> Result<Self, RemoteHelperError> {
-        if is_sha256 {
-            use sha2::{Digest, Sha256};
-            let hash = Sha256::digest(data);
-            Ok(Self::Sha256(hex::encode(hash)))
-        } else {
-            use sha1::{Digest, Sha1};
-            let hash = Sha1::digest(data);
-            Ok(Self::Sha1(hex::encode(hash)))
-        }
+        Self::from_parts(&[data], is_sha256)
+    }
+
+    // Hashes the parts as if they were concatenated, without copying them into one buffer
+    pub fn from_parts(parts: &[&[u8]], is_sha256: bool) -> Result<Self, RemoteHelperError> {
+        let kind = HashKind::from_sha256(is_sha256);
+        Ok(Self {
+            kind,
+            hex: kind.digest(parts),
+        })
     }
 }
 
@@ -45,18 +150,13 @@ impl FromStr for Hash {
     type Err = RemoteHelperError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let no_padding = s.strip_suffix(&"0".repeat(24)).unwrap_or(s);
-
-        if HASH_REGEX_SHA1.is_match(no_padding) {
-            Ok(Self::Sha1(no_padding.to_string()))
-        } else if HASH_REGEX_SHA256.is_match(no_padding) {
-            Ok(Self::Sha256(no_padding.to_string()))
-        } else {
-            Err(RemoteHelperError::Failure {
+        HashKind::ALL
+            .into_iter()
+            .find_map(|kind| kind.parse(s).map(|hex| Self { kind, hex }))
+            .ok_or(RemoteHelperError::Failure {
                 action: "parsing hash".to_string(),
-                details: Some(format!("invalid hash: {:?}", no_padding)),
+                details: Some(format!("invalid hash: {:?}", s)),
             })
-        }
     }
 }
 
@@ -78,11 +178,7 @@ impl TryFrom<&[u8]> for Hash {
 
 impl fmt::Display for Hash {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let s = match self {
-            Self::Sha1(s) => s,
-            Self::Sha256(s) => s,
-        };
-        write!(f, "{}", s)
+        write!(f, "{}", self.hex)
     }
 }
 
@@ -90,15 +186,74 @@ impl fmt::Display for Hash {
 fn test_hash() {
     let hash_str = "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83";
     let hash = Hash::from_str(hash_str).expect("should succeed");
-    assert_eq!(hash, Hash::Sha1(hash_str.to_string()));
+    assert_eq!(hash.algo(), HashKind::Sha1);
+    assert_eq!(hash.to_string(), hash_str);
+    assert_eq!(
+        Hash::from_str(&hash.padded()).expect("should succeed"),
+        hash
+    );
 
     let hash_str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
     let hash = Hash::from_str(hash_str).expect("should succeed");
-    assert_eq!(hash, Hash::Sha256(hash_str.to_string()));
+    assert_eq!(hash.algo(), HashKind::Sha256);
+    assert_eq!(hash.to_string(), hash_str);
+    assert_eq!(hash.padded(), hash_str);
 
     let hash_str = "4e1243bd22c66e.6c2ba9eddc1f91394e57f9f83";
     Hash::from_str(hash_str).expect_err("should fail");
 
     let hash_str = "abc";
     Hash::from_str(hash_str).expect_err("should fail");
+
+    // 64 bytes with a character across the end of a sha1 hash
+    let hash_str = "€".repeat(21) + "a";
+    assert_eq!(hash_str.len(), 64);
+    Hash::from_str(&hash_str).expect_err("should fail");
+}
+
+#[test]
+fn test_hash_from_parts() {
+    for is_sha256 in [false, true] {
+        let expected = Hash::from_data(b"blob 4\0test", is_sha256).expect("should succeed");
+        let hash =
+            Hash::from_parts(&[b"blob 4\0", b"te", b"st"], is_sha256).expect("should succeed");
+        assert_eq!(hash, expected);
+    }
+}
+
+#[test]
+fn test_hash_algo() {
+    assert_eq!(Sha1::len_hex(), 40);
+    assert_eq!(
+        Sha1::digest(&[b""]),
+        "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+    );
+    assert_eq!(Sha256::len_hex(), 64);
+    assert_eq!(
+        Sha256::digest(&[b"te", b"st"]),
+        "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+    );
+    for kind in HashKind::ALL {
+        let hash = Hash::from_parts(&[b"test"], kind == HashKind::Sha256).expect("should succeed");
+        assert_eq!(hash.algo(), kind);
+        assert_eq!(hash.to_string().len(), kind.len_hex());
+    }
+}
+
+#[test]
+fn test_hash_padding() {
+    for kind in HashKind::ALL {
+        let zero = Hash::zero(kind == HashKind::Sha256);
+        assert_eq!(zero.algo(), kind);
+        assert!(zero.is_zero());
+        assert_eq!(zero.to_string().len(), kind.len_hex());
+        assert_eq!(zero.padded(), "0".repeat(PADDED_LEN_HEX));
+        assert_eq!(kind.len_bytes() * 2, kind.len_hex());
+
+        let hash = Hash::from_parts(&[b"test"], kind == HashKind::Sha256).expect("should succeed");
+        let padded = hash.padded();
+        assert_eq!(padded.len(), PADDED_LEN_HEX);
+        assert_eq!(&padded[..kind.len_hex()], hash.to_string());
+        assert_eq!(Hash::from_str(&padded).expect("should succeed"), hash);
+    }
 }
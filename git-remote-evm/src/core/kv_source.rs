@@ -7,21 +7,38 @@ pub trait KeyValueSource {
     fn read(&self, key: &str) -> Result<Option<String>, RemoteHelperError>;
 }
 
-pub struct EnvSource {}
+pub const DEFAULT_ENV_PREFIX: &str = "GITDEM_";
+
+// Maps config keys to prefixed environment variables, e.g. evm.eth.rpc -> GITDEM_EVM_ETH_RPC
+pub struct EnvSource {
+    prefix: String,
+}
 
 impl EnvSource {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn env_key(&self, key: &str) -> String {
+        format!(
+            "{}{}",
+            self.prefix,
+            key.to_uppercase().replace(['.', '-'], "_")
+        )
+    }
+}
+
+impl Default for EnvSource {
+    fn default() -> Self {
+        Self::new(DEFAULT_ENV_PREFIX)
     }
 }
 
 impl KeyValueSource for EnvSource {
     fn read(&self, key: &str) -> Result<Option<String>, RemoteHelperError> {
-        let key = key.to_uppercase().replace('.', "_");
-        let key = key.strip_prefix("EVM_").unwrap_or(&key);
-        let key = format!("GITDEM_{}", key);
-
-        let value = match std::env::var(key) {
+        let value = match std::env::var(self.env_key(key)) {
             Ok(value) => value.trim().to_string(),
             Err(VarError::NotPresent) => return Ok(None),
             Err(VarError::NotUnicode(_)) => {
@@ -40,25 +57,48 @@ impl KeyValueSource for EnvSource {
     }
 }
 
+#[test]
+fn test_env_key() {
+    let env_source = EnvSource::default();
+    assert_eq!(env_source.env_key("evm.eth.rpc"), "GITDEM_EVM_ETH_RPC");
+    assert_eq!(env_source.env_key("evm.wallet"), "GITDEM_EVM_WALLET");
+    assert_eq!(
+        env_source.env_key("evm.strict-balance-check"),
+        "GITDEM_EVM_STRICT_BALANCE_CHECK"
+    );
+
+    let env_source = EnvSource::new("OTHER_");
+    assert_eq!(env_source.env_key("evm.eth.rpc"), "OTHER_EVM_ETH_RPC");
+
+    let env_source = EnvSource::new("");
+    assert_eq!(env_source.env_key("some.key"), "SOME_KEY");
+}
+
 #[test]
 fn test_env_source() {
     let expected_value = "test_value";
     unsafe {
-        std::env::set_var("GITDEM_SOME_KEY", expected_value);
+        std::env::set_var("GITDEM_EVM_SOME_KEY", expected_value);
+        std::env::set_var("CUSTOM_EVM_SOME_KEY", "custom_value");
     }
 
-    let env_source = EnvSource::new();
+    let env_source = EnvSource::default();
 
     let value = env_source.read("evm.some.key").unwrap();
     assert_eq!(value, Some(expected_value.to_string()));
 
     let value = env_source.read("some.key").unwrap();
-    assert_eq!(value, Some(expected_value.to_string()));
+    assert_eq!(value, None);
 
     let value = env_source.read("another.key").unwrap();
     assert_eq!(value, None);
 
+    let env_source = EnvSource::new("CUSTOM_");
+    let value = env_source.read("evm.some.key").unwrap();
+    assert_eq!(value, Some("custom_value".to_string()));
+
     unsafe {
-        std::env::remove_var("GITDEM_SOME_KEY");
+        std::env::remove_var("GITDEM_EVM_SOME_KEY");
+        std::env::remove_var("CUSTOM_EVM_SOME_KEY");
     }
 }
@@ -1,3 +1,6 @@
+pub mod address;
+pub mod delta;
+pub mod fast_export;
 pub mod git;
 pub mod hash;
 pub mod kv_source;
@@ -1,9 +1,14 @@
-use super::hash::Hash;
+use super::hash::{Hash, HashKind};
 use crate::core::remote_helper::error::RemoteHelperError;
 use std::fmt::Debug;
 use std::hash::Hash as StdHash;
 use std::str::FromStr;
 
+// How much of a blob's content describe() shows
+const DESCRIBE_PREVIEW_LENGTH: usize = 32;
+// Length of "commit", the longest kind, the space after it is looked for no further
+const MAX_KIND_LENGTH: usize = 6;
+
 #[derive(Debug, PartialEq, Clone, Eq, StdHash)]
 pub enum ObjectKind {
     Blob,
@@ -42,6 +47,20 @@ impl FromStr for ObjectKind {
     }
 }
 
+impl ObjectKind {
+    // Corrupt headers can hold anything, so only a plain lowercase word is parsed and
+    // anything else is reported as hex, e.g. a kind with a NUL or control character in it
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RemoteHelperError> {
+        if bytes.is_empty() || !bytes.iter().all(u8::is_ascii_lowercase) {
+            return Err(RemoteHelperError::Invalid {
+                what: "object kind".to_string(),
+                value: format!("0x{}", hex::encode(bytes)),
+            });
+        }
+        Self::from_str(&String::from_utf8_lossy(bytes))
+    }
+}
+
 #[derive(PartialEq, Clone, Eq, StdHash)]
 pub struct Object {
     kind: ObjectKind,
@@ -67,18 +86,19 @@ impl Object {
         is_sha256: bool,
     ) -> Result<Self, RemoteHelperError> {
         let related_objects = Self::find_related(&kind, &data, is_sha256)?;
-        let mut object = Self {
+        // Hashed in parts so large objects aren't copied just to compute their hash
+        let hash = Hash::from_parts(&[&Self::header(&kind, data.len()), &data], is_sha256)
+            .expect("creating hash from a valid object should not fail");
+        Ok(Self {
             kind,
             data,
             related: related_objects,
-            hash: Hash::Sha256("placeholder; will be overwritten".to_string()),
-        };
+            hash,
+        })
+    }
 
-        let data = object.serialize();
-        let hash = Hash::from_data(&data, is_sha256)
-            .expect("creating hash from a valid object should not fail");
-        object.hash = hash;
-        Ok(object)
+    fn header(kind: &ObjectKind, size: usize) -> Vec<u8> {
+        format!("{} {}\0", kind, size).into_bytes()
     }
 
     pub fn get_kind(&self) -> &ObjectKind {
@@ -93,20 +113,91 @@ impl Object {
         &self.related
     }
 
+    // Names and hashes of a tree's entries, empty for other kinds
+    pub fn get_tree_entries(&self) -> Vec<(String, Hash)> {
+        if !matches!(self.kind, ObjectKind::Tree) {
+            return vec![];
+        }
+        let hash_length = self.hash.algo().len_bytes();
+        let mut entries = vec![];
+        let mut data = self.data.as_slice();
+        while let Some(null_index) = data.iter().position(|b| *b == b'\0') {
+            let Some(hash) = data.get(null_index + 1..null_index + 1 + hash_length) else {
+                break;
+            };
+            let entry = String::from_utf8_lossy(&data[..null_index]);
+            let name = entry
+                .split_once(' ')
+                .map_or(entry.as_ref(), |(_, name)| name);
+            if let Ok(hash) = Hash::try_from(hash) {
+                entries.push((name.to_string(), hash));
+            }
+            data = &data[null_index + 1 + hash_length..];
+        }
+        entries
+    }
+
     pub fn get_hash(&self) -> &Hash {
         &self.hash
     }
 
     pub fn serialize(&self) -> Vec<u8> {
-        let mut data: Vec<u8> = Vec::new();
-        data.extend_from_slice(self.kind.to_string().as_bytes());
-        data.push(b' ');
-        data.extend_from_slice(self.data.len().to_string().as_bytes());
-        data.push(b'\0');
+        let mut data = Self::header(&self.kind, self.data.len());
         data.extend_from_slice(&self.data);
         data
     }
 
+    // Same as serialize but reuses the object's buffer instead of holding a second copy of the data
+    pub fn into_serialized(self) -> Vec<u8> {
+        let header = Self::header(&self.kind, self.data.len());
+        let mut data = self.data;
+        data.splice(0..0, header);
+        data
+    }
+
+    pub fn serialized_size(&self) -> usize {
+        Self::header(&self.kind, self.data.len()).len() + self.data.len()
+    }
+
+    // Human readable summary for logs, unlike Debug it looks at the content
+    pub fn describe(&self) -> String {
+        let summary = match self.kind {
+            ObjectKind::Blob => {
+                let preview = &self.data[..self.data.len().min(DESCRIBE_PREVIEW_LENGTH)];
+                let preview = match std::str::from_utf8(preview) {
+                    Ok(text) => format!("{:?}", text),
+                    Err(_) => format!("0x{}", hex::encode(preview)),
+                };
+                let ellipsis = if self.data.len() > DESCRIBE_PREVIEW_LENGTH {
+                    "..."
+                } else {
+                    ""
+                };
+                format!("{} bytes, {}{}", self.data.len(), preview, ellipsis)
+            }
+            ObjectKind::Tree => {
+                let hash_length = self.hash.algo().len_bytes();
+                let mut names = vec![];
+                let mut data = self.data.as_slice();
+                while let Some(null_index) = data.iter().position(|b| *b == b'\0') {
+                    let entry = String::from_utf8_lossy(&data[..null_index]);
+                    let name = entry
+                        .split_once(' ')
+                        .map_or(entry.as_ref(), |(_, name)| name);
+                    names.push(name.to_string());
+                    data = data.get(null_index + 1 + hash_length..).unwrap_or_default();
+                }
+                format!("{} entries: {}", names.len(), names.join(", "))
+            }
+            ObjectKind::Commit | ObjectKind::Tag => {
+                let text = String::from_utf8_lossy(&self.data);
+                let message = text.split_once("\n\n").map_or("", |(_, message)| message);
+                format!("{:?}", message.lines().next().unwrap_or_default())
+            }
+        };
+        format!("{} {}: {}", self.kind, self.hash, summary)
+    }
+
     fn find_related(
         kind: &ObjectKind,
         data: &[u8],
@@ -115,7 +206,7 @@ impl Object {
         match kind {
             ObjectKind::Blob => Ok(vec![]),
             ObjectKind::Tree => {
-                let hash_length = if is_sha256 { 32 } else { 20 };
+                let hash_length = HashKind::from_sha256(is_sha256).len_bytes();
                 let mut related_objects = vec![];
                 let mut data = data;
                 while !data.is_empty() {
@@ -126,6 +217,12 @@ impl Object {
                         },
                     )?;
                     data = &data[null_index + 1..];
+                    if data.len() < hash_length {
+                        return Err(RemoteHelperError::Invalid {
+                            what: "truncated tree entry".to_string(),
+                            value: format!("{} bytes left, expected: {}", data.len(), hash_length),
+                        });
+                    }
 
                     let hash_bytes = &data[..hash_length];
                     let hash = Hash::try_from(hash_bytes)?;
@@ -143,21 +240,34 @@ impl Object {
                         value: e.to_string(),
                     })?;
 
+                let invalid_line = |line: &str| RemoteHelperError::Invalid {
+                    what: "object commit line".to_string(),
+                    value: line.to_string(),
+                };
+
+                // The headers start with the tree, followed by the parents, if any
                 let mut related_objects = vec![];
                 for line in data.lines() {
-                    let parts = line.split_whitespace().collect::<Vec<_>>();
-                    if parts.len() < 2 {
-                        return Err(RemoteHelperError::Invalid {
-                            what: "object commit line".to_string(),
-                            value: line.to_string(),
-                        });
-                    }
-
-                    let kind = parts[0];
+                    let (kind, value) = line.split_once(' ').ok_or(invalid_line(line))?;
                     match kind {
-                        "tree" | "parent" => related_objects.push(Hash::from_str(parts[1])?),
+                        "tree" | "parent" => {}
                         _ => break,
                     }
+                    if (kind == "tree") != related_objects.is_empty() {
+                        return Err(invalid_line(line));
+                    }
+
+                    let hash = Hash::from_str(value).map_err(|_| invalid_line(line))?;
+                    if hash.is_sha256() != is_sha256 {
+                        return Err(invalid_line(line));
+                    }
+                    related_objects.push(hash);
+                }
+                if related_objects.is_empty() {
+                    return Err(RemoteHelperError::Invalid {
+                        what: "object commit".to_string(),
+                        value: "missing tree".to_string(),
+                    });
                 }
                 Ok(related_objects)
             }
@@ -183,38 +293,71 @@ impl Object {
                         value: String::from_utf8_lossy(lines[0]).to_string(),
                     });
                 }
-                let object = Hash::try_from(parts[1])?;
+                // The target is written out in hex like in commits
+                let object = std::str::from_utf8(parts[1])
+                    .ok()
+                    .and_then(|hex| Hash::from_str(hex).ok())
+                    .filter(|hash| hash.is_sha256() == is_sha256)
+                    .ok_or(RemoteHelperError::Invalid {
+                        what: "object tag".to_string(),
+                        value: String::from_utf8_lossy(lines[0]).to_string(),
+                    })?;
                 Ok(vec![object])
             }
         }
     }
 
     pub fn deserialize(input: &[u8], is_sha256: bool) -> Result<Self, RemoteHelperError> {
-        let parts = input.splitn(2, |b| *b == b'\0').collect::<Vec<_>>();
-        if parts.len() != 2 {
-            return Err(RemoteHelperError::Invalid {
-                what: "object".to_string(),
-                value: String::from_utf8_lossy(input).to_string(),
-            });
+        let (kind, data) = Self::split_serialized(input)?;
+        Self::new(kind, data.to_vec(), is_sha256)
+    }
+
+    // Checks the header and hash without copying the data, so objects can be verified before they're stored
+    pub fn verify_serialized(input: &[u8], hash: &Hash) -> Result<(), RemoteHelperError> {
+        let invalid = |details: String| RemoteHelperError::Invalid {
+            what: "serialized object".to_string(),
+            value: format!("{} ({})", hash, details),
+        };
+        let (kind, data) = Self::split_serialized(input).map_err(|e| invalid(e.to_string()))?;
+        let computed =
+            Hash::from_parts(&[&Self::header(&kind, data.len()), data], hash.is_sha256())?;
+        if &computed != hash {
+            return Err(invalid(format!("hashes to {}", computed)));
         }
+        Ok(())
+    }
 
-        let header =
-            String::from_utf8(parts[0].to_vec()).map_err(|e| RemoteHelperError::Invalid {
+    // Returns the kind from the header and the data after it, once the size is known to match.
+    // The kind ends at the first space, so a NUL inside it can't be mistaken for the end of the header.
+    fn split_serialized(input: &[u8]) -> Result<(ObjectKind, &[u8]), RemoteHelperError> {
+        let Some(space_index) = input
+            .iter()
+            .take(MAX_KIND_LENGTH + 1)
+            .position(|b| *b == b' ')
+        else {
+            return Err(RemoteHelperError::Invalid {
                 what: "object header".to_string(),
-                value: e.to_string(),
-            })?;
-        let data = parts[1];
+                value: String::from_utf8_lossy(input).to_string(),
+            });
+        };
+        let kind = ObjectKind::from_bytes(&input[..space_index])?;
 
-        let parts = header.splitn(2, |b| b == ' ').collect::<Vec<_>>();
+        let parts = input[space_index + 1..]
+            .splitn(2, |b| *b == b'\0')
+            .collect::<Vec<_>>();
         if parts.len() != 2 {
             return Err(RemoteHelperError::Invalid {
-                what: "object header".to_string(),
-                value: header,
+                what: "object".to_string(),
+                value: String::from_utf8_lossy(input).to_string(),
             });
         }
+        let data = parts[1];
 
-        let kind = ObjectKind::from_str(&parts[0])?;
-        let size = parts[1]
+        let size = std::str::from_utf8(parts[0])
+            .map_err(|e| RemoteHelperError::Invalid {
+                what: "object header".to_string(),
+                value: e.to_string(),
+            })?
             .parse::<usize>()
             .map_err(|e| RemoteHelperError::Invalid {
                 what: "object size".to_string(),
@@ -228,7 +371,7 @@ impl Object {
             });
         }
 
-        Self::new(kind, data.to_vec(), is_sha256)
+        Ok((kind, data))
     }
 }
 
@@ -243,6 +386,51 @@ fn test_object_deserialize() {
     assert_eq!(object.data, b"test");
 }
 
+#[test]
+fn test_object_deserialize_invalid_kind() {
+    for (input, value) in [
+        (&b"bl\0ob 4\0test"[..], "0x626c006f62"),
+        (b"\0blob 4\0test", "0x00626c6f62"),
+        (b"blob\x01 4\0test", "0x626c6f6201"),
+        (b"BLOB 4\0test", "0x424c4f42"),
+        (b" 4\0test", "0x"),
+        (b"blub 4\0test", "blub"),
+    ] {
+        let err = Object::deserialize(input, true).expect_err("should fail");
+        assert_eq!(
+            err,
+            RemoteHelperError::Invalid {
+                what: "object kind".to_string(),
+                value: value.to_string(),
+            }
+        );
+    }
+
+    assert_eq!(
+        ObjectKind::from_bytes(b"commit").expect("should succeed"),
+        ObjectKind::Commit
+    );
+    Object::deserialize(b"blob", true).expect_err("should fail without a size");
+    Object::deserialize(b"blob\0te st", true)
+        .expect_err("should fail without a space in the header");
+    Object::deserialize(b"blob 4", true).expect_err("should fail without data");
+    Object::deserialize(b"blob x\0test", true).expect_err("should fail with an invalid size");
+}
+
+#[test]
+fn test_object_verify_serialized() {
+    let object =
+        Object::new(ObjectKind::Blob, b"test".to_vec(), true).expect("failed to create blob");
+    let hash = object.get_hash().clone();
+    Object::verify_serialized(&object.serialize(), &hash).expect("should be valid");
+
+    let err = Object::verify_serialized(b"blob 5\0test", &hash).expect_err("size mismatch");
+    assert!(matches!(err, RemoteHelperError::Invalid { what, value }
+        if what == "serialized object" && value.starts_with(&hash.to_string())));
+    Object::verify_serialized(b"blob 4\0tess", &hash).expect_err("hash mismatch");
+    Object::verify_serialized(b"test", &hash).expect_err("missing header");
+}
+
 #[test]
 fn test_object_serialize() {
     let object = Object::new(ObjectKind::Blob, vec![], true).expect("failed to create blob");
@@ -251,4 +439,152 @@ fn test_object_serialize() {
     let object =
         Object::new(ObjectKind::Blob, b"test".to_vec(), true).expect("failed to create blob");
     assert_eq!(object.serialize(), b"blob 4\0test");
+    assert_eq!(object.serialized_size(), b"blob 4\0test".len());
+    assert_eq!(object.into_serialized(), b"blob 4\0test");
+}
+
+#[test]
+fn test_object_describe() {
+    let blob =
+        Object::new(ObjectKind::Blob, b"test".to_vec(), true).expect("failed to create blob");
+    assert_eq!(
+        blob.describe(),
+        format!("blob {}: 4 bytes, \"test\"", blob.get_hash())
+    );
+
+    let blob = Object::new(ObjectKind::Blob, vec![0xff; 40], true).expect("failed to create blob");
+    assert_eq!(
+        blob.describe(),
+        format!(
+            "blob {}: 40 bytes, 0x{}...",
+            blob.get_hash(),
+            "ff".repeat(32)
+        )
+    );
+
+    let mut tree_data = b"100644 file\0".to_vec();
+    tree_data.extend(hex::decode(blob.get_hash().to_string()).expect("failed to decode"));
+    tree_data.extend(b"40000 dir\0");
+    tree_data.extend(hex::decode(blob.get_hash().to_string()).expect("failed to decode"));
+    let tree = Object::new(ObjectKind::Tree, tree_data, true).expect("failed to create tree");
+    assert_eq!(
+        tree.describe(),
+        format!("tree {}: 2 entries: file, dir", tree.get_hash())
+    );
+
+    let commit_data = format!(
+        "tree {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nsubject\n\nbody\n",
+        tree.get_hash()
+    );
+    let commit = Object::new(ObjectKind::Commit, commit_data.into_bytes(), true)
+        .expect("failed to create commit");
+    assert_eq!(
+        commit.describe(),
+        format!("commit {}: \"subject\"", commit.get_hash())
+    );
+}
+
+#[test]
+fn test_object_truncated_tree() {
+    let hash = Hash::from_data(b"test", true).expect("should be set");
+    let mut tree_data = b"100644 file\0".to_vec();
+    tree_data.extend(hex::decode(hash.to_string()).expect("failed to decode"));
+    tree_data.truncate(tree_data.len() - 1);
+
+    let err = Object::new(ObjectKind::Tree, tree_data, true).expect_err("should fail");
+    assert_eq!(
+        err,
+        RemoteHelperError::Invalid {
+            what: "truncated tree entry".to_string(),
+            value: "31 bytes left, expected: 32".to_string(),
+        }
+    );
+
+    Object::new(ObjectKind::Tree, b"100644 file\0".to_vec(), false)
+        .expect_err("should fail without a hash");
+}
+
+#[test]
+fn test_object_malformed_commit() {
+    let tree = Hash::from_data(b"tree", true).expect("should be set");
+    let parent = Hash::from_data(b"parent", true).expect("should be set");
+    let valid = format!(
+        "tree {}\nparent {}\nauthor A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nmessage\n",
+        tree, parent
+    );
+    let object = Object::new(ObjectKind::Commit, valid.clone().into_bytes(), true)
+        .expect("failed to create commit");
+    assert_eq!(object.get_related(), &vec![tree.clone(), parent.clone()]);
+
+    // Every truncation of a valid commit has to fail cleanly or parse, never panic
+    for length in 0..valid.len() {
+        let _ = Object::new(
+            ObjectKind::Commit,
+            valid.as_bytes()[..length].to_vec(),
+            true,
+        );
+    }
+
+    let sha1_tree = Hash::from_data(b"tree", false).expect("should be set");
+    let malformed = vec![
+        "".to_string(),
+        "tree".to_string(),
+        "tree \n".to_string(),
+        "parent\n".to_string(),
+        "tree abc\n".to_string(),
+        format!("tree {}x\n", tree),
+        format!("parent {}\ntree {}\n", parent, tree),
+        format!("tree {}\ntree {}\n", tree, tree),
+        format!("tree {}\n", sha1_tree),
+        format!("author A <a@example.com> 0 +0000\ntree {}\n", tree),
+    ];
+    for data in malformed {
+        let err =
+            Object::new(ObjectKind::Commit, data.clone().into_bytes(), true).expect_err(&data);
+        assert!(
+            matches!(err, RemoteHelperError::Invalid { .. }),
+            "unexpected error for {:?}: {:?}",
+            data,
+            err
+        );
+    }
+}
+
+#[test]
+fn test_object_tag() {
+    let target = Hash::from_data(b"commit", true).expect("should be set");
+    let data = format!(
+        "object {}\ntype commit\ntag v1\ntagger A <a@example.com> 0 +0000\n\nv1\n",
+        target
+    );
+    let object =
+        Object::new(ObjectKind::Tag, data.into_bytes(), true).expect("failed to create tag");
+    assert_eq!(object.get_related(), &vec![target]);
+
+    let sha1_target = Hash::from_data(b"commit", false).expect("should be set");
+    for data in [
+        "object abc\n".to_string(),
+        format!("object {}\n", sha1_target),
+    ] {
+        Object::new(ObjectKind::Tag, data.into_bytes(), true)
+            .expect_err("should fail with an invalid target");
+    }
+}
+
+#[test]
+fn test_object_tree_entries() {
+    let one = Hash::from_data(b"one", true).expect("should be set");
+    let two = Hash::from_data(b"two", true).expect("should be set");
+    let mut data = b"100644 one.txt\0".to_vec();
+    data.extend(hex::decode(one.to_string()).expect("should succeed"));
+    data.extend(b"40000 dir\0");
+    data.extend(hex::decode(two.to_string()).expect("should succeed"));
+    let tree = Object::new(ObjectKind::Tree, data, true).expect("failed to create tree");
+    assert_eq!(
+        tree.get_tree_entries(),
+        vec![("one.txt".to_string(), one), ("dir".to_string(), two)]
+    );
+
+    let blob = Object::new(ObjectKind::Blob, b"blob".to_vec(), true).expect("should be set");
+    assert!(blob.get_tree_entries().is_empty());
 }
@@ -5,12 +5,18 @@ use std::{fmt, str::FromStr};
 #[derive(Clone, Debug, PartialEq)]
 pub enum Keys {
     ObjectFormat,
+    // HEAD points at a branch without commits, the value is its target
+    Unborn,
+    // Storage format of the remote, git ignores keys it doesn't know
+    Version,
 }
 
 impl fmt::Display for Keys {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Keys::ObjectFormat => write!(f, "object-format"),
+            Keys::Unborn => write!(f, "unborn"),
+            Keys::Version => write!(f, "gitdem/version"),
         }
     }
 }
@@ -19,13 +25,14 @@ impl FromStr for Keys {
     type Err = RemoteHelperError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s == "object-format" {
-            Ok(Keys::ObjectFormat)
-        } else {
-            Err(RemoteHelperError::Failure {
-                action: "parsing keys".to_string(),
-                details: Some("invalid key".to_string()),
-            })
+        match s {
+            "object-format" => Ok(Keys::ObjectFormat),
+            "unborn" => Ok(Keys::Unborn),
+            "gitdem/version" => Ok(Keys::Version),
+            _ => Err(RemoteHelperError::Invalid {
+                what: "key".to_string(),
+                value: s.to_string(),
+            }),
         }
     }
 }
@@ -33,16 +40,39 @@ impl FromStr for Keys {
 // gitremote-helpers.adoc (line 264)
 #[derive(Clone, Debug, PartialEq)]
 pub enum Reference {
-    Normal { name: String, hash: Hash },
-    Symbolic { name: String, target: String },
-    KeyValue { key: Keys, value: String },
+    Normal {
+        name: String,
+        hash: Hash,
+    },
+    // An annotated tag along with the object it resolves to, listed as `<object> <name>^{}`
+    Peeled {
+        name: String,
+        tag: Hash,
+        object: Hash,
+    },
+    Symbolic {
+        name: String,
+        target: String,
+    },
+    KeyValue {
+        key: Keys,
+        value: String,
+    },
 }
 
 impl fmt::Display for Reference {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Reference::Normal { name, hash } => write!(f, "{} {}", hash, name),
+            Reference::Peeled { name, tag, object } => {
+                write!(f, "{} {}\n{} {}^{{}}", tag, name, object, name)
+            }
             Reference::Symbolic { name, target } => write!(f, "@{} {}", target, name),
+            // Unborn is an attribute of the symbolic HEAD line, git ignores attributes it doesn't know
+            Reference::KeyValue {
+                key: Keys::Unborn,
+                value,
+            } => write!(f, "@{} HEAD {}", value, Keys::Unborn),
             Reference::KeyValue { key, value } => write!(f, ":{} {}", key, value),
         }
     }
@@ -54,6 +84,8 @@ pub struct Push {
     pub local: String,
     pub remote: String,
     pub is_force: bool,
+    // Set by --force-with-lease, the push is rejected unless the remote is still at this hash
+    pub expected_remote: Option<Hash>,
 }
 
 impl fmt::Display for Push {
@@ -72,6 +104,7 @@ impl Push {
             local,
             remote,
             is_force,
+            expected_remote: None,
         }
     }
 }
@@ -99,3 +132,63 @@ impl FromStr for Fetch {
     }
 }
 
+#[test]
+fn test_keys() {
+    assert_eq!(
+        Keys::from_str("object-format").expect("failed to parse"),
+        Keys::ObjectFormat
+    );
+    assert_eq!(
+        Keys::from_str("unborn").expect("failed to parse"),
+        Keys::Unborn
+    );
+    assert_eq!(
+        Keys::from_str("gitdem/version").expect("failed to parse"),
+        Keys::Version
+    );
+    assert_eq!(
+        Keys::from_str("unknown").expect_err("expected error"),
+        RemoteHelperError::Invalid {
+            what: "key".to_string(),
+            value: "unknown".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_reference_display() {
+    let object_format = Reference::KeyValue {
+        key: Keys::ObjectFormat,
+        value: "sha256".to_string(),
+    };
+    assert_eq!(object_format.to_string(), ":object-format sha256");
+
+    let unborn = Reference::KeyValue {
+        key: Keys::Unborn,
+        value: "refs/heads/main".to_string(),
+    };
+    assert_eq!(unborn.to_string(), "@refs/heads/main HEAD unborn");
+
+    let version = Reference::KeyValue {
+        key: Keys::Version,
+        value: "1".to_string(),
+    };
+    assert_eq!(version.to_string(), ":gitdem/version 1");
+
+    let symbolic = Reference::Symbolic {
+        name: "HEAD".to_string(),
+        target: "refs/heads/main".to_string(),
+    };
+    assert_eq!(symbolic.to_string(), "@refs/heads/main HEAD");
+
+    let peeled = Reference::Peeled {
+        name: "refs/tags/v1".to_string(),
+        tag: Hash::from_str("4e1243bd22c66e76c2ba9eddc1f91394e57f9f83").expect("should be set"),
+        object: Hash::from_str("9f2c4a1b7e3d5f60718293a4b5c6d7e8f9012345").expect("should be set"),
+    };
+    assert_eq!(
+        peeled.to_string(),
+        "4e1243bd22c66e76c2ba9eddc1f91394e57f9f83 refs/tags/v1\n\
+         9f2c4a1b7e3d5f60718293a4b5c6d7e8f9012345 refs/tags/v1^{}"
+    );
+}
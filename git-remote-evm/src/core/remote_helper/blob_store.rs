@@ -0,0 +1,164 @@
+use crate::core::remote_helper::error::RemoteHelperError;
+use log::{debug, trace};
+use mockall::automock;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+// Large enough that small blobs, trees and commits stay on-chain
+pub const DEFAULT_OFFLOAD_THRESHOLD: usize = 16 * 1024;
+
+// Stores object data off-chain, only the returned CID is saved in the contract
+#[automock]
+pub trait BlobStore: Send + Sync {
+    // Computes the CID without storing anything, used for estimates
+    fn cid(&self, data: &[u8]) -> Result<String, RemoteHelperError>;
+    fn put(&self, data: &[u8]) -> Result<String, RemoteHelperError>;
+    fn get(&self, cid: &str) -> Result<Vec<u8>, RemoteHelperError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobStoreKind {
+    Ipfs,
+}
+
+impl FromStr for BlobStoreKind {
+    type Err = RemoteHelperError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ipfs" => Ok(BlobStoreKind::Ipfs),
+            _ => Err(RemoteHelperError::Invalid {
+                what: "blob store".to_string(),
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+// Objects whose serialized size is above the threshold are put in the store
+pub struct Offload {
+    pub store: Box<dyn BlobStore>,
+    pub threshold: usize,
+}
+
+impl Offload {
+    pub fn new(kind: BlobStoreKind, threshold: usize) -> Self {
+        let store: Box<dyn BlobStore> = match kind {
+            BlobStoreKind::Ipfs => Box::new(Ipfs),
+        };
+        Self { store, threshold }
+    }
+}
+
+// Uses the ipfs cli (kubo), so its daemon and config decide where the data is pinned
+pub struct Ipfs;
+
+impl Ipfs {
+    fn run(&self, args: &[&str], input: Option<&[u8]>) -> Result<Vec<u8>, RemoteHelperError> {
+        let action = format!("running ipfs {}", args[0]);
+        trace!("{}", action);
+        let mut cmd = Command::new("ipfs")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: action.clone(),
+                details: Some(e.to_string()),
+            })?;
+
+        let mut stdin = cmd.stdin.take().ok_or(RemoteHelperError::Failure {
+            action: action.clone(),
+            details: Some("failed to get stdin".to_string()),
+        })?;
+        if let Some(input) = input {
+            stdin
+                .write_all(input)
+                .map_err(|e| RemoteHelperError::Failure {
+                    action: "writing data to stdin".to_string(),
+                    details: Some(e.to_string()),
+                })?;
+        }
+        drop(stdin);
+
+        let output = cmd
+            .wait_with_output()
+            .map_err(|e| RemoteHelperError::Failure {
+                action: action.clone(),
+                details: Some(e.to_string()),
+            })?;
+        if !output.status.success() {
+            return Err(RemoteHelperError::Failure {
+                action,
+                details: Some(String::from_utf8_lossy(&output.stderr).to_string()),
+            });
+        }
+        Ok(output.stdout)
+    }
+
+    fn add(&self, data: &[u8], only_hash: bool) -> Result<String, RemoteHelperError> {
+        let mut args = vec!["add", "--quiet", "--cid-version=1"];
+        if only_hash {
+            args.push("--only-hash");
+        }
+        let output = self.run(&args, Some(data))?;
+        parse_cid(&output)
+    }
+}
+
+fn parse_cid(output: &[u8]) -> Result<String, RemoteHelperError> {
+    let cid = String::from_utf8_lossy(output).trim().to_string();
+    if cid.is_empty() || cid.contains(char::is_whitespace) {
+        return Err(RemoteHelperError::Invalid {
+            what: "cid".to_string(),
+            value: cid,
+        });
+    }
+    Ok(cid)
+}
+
+impl BlobStore for Ipfs {
+    fn cid(&self, data: &[u8]) -> Result<String, RemoteHelperError> {
+        self.add(data, true)
+    }
+
+    fn put(&self, data: &[u8]) -> Result<String, RemoteHelperError> {
+        let cid = self.add(data, false)?;
+        debug!("added {} bytes to ipfs: {}", data.len(), cid);
+        Ok(cid)
+    }
+
+    fn get(&self, cid: &str) -> Result<Vec<u8>, RemoteHelperError> {
+        let data = self.run(&["cat", cid], None)?;
+        debug!("got {} bytes from ipfs: {}", data.len(), cid);
+        Ok(data)
+    }
+}
+
+#[test]
+fn test_blob_store_kind() {
+    assert_eq!(
+        BlobStoreKind::from_str("ipfs").expect("failed to parse"),
+        BlobStoreKind::Ipfs
+    );
+    assert_eq!(
+        BlobStoreKind::from_str("s3").expect_err("expected error"),
+        RemoteHelperError::Invalid {
+            what: "blob store".to_string(),
+            value: "s3".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_parse_cid() {
+    let cid = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+    assert_eq!(
+        parse_cid(format!("{}\n", cid).as_bytes()).expect("failed to parse"),
+        cid
+    );
+    parse_cid(b"\n").expect_err("should fail with empty output");
+    parse_cid(b"a b\n").expect_err("should fail with multiple words");
+}
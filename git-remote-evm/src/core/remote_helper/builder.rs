@@ -0,0 +1,163 @@
+use crate::core::git::Git;
+use crate::core::remote_helper::blob_store::Offload;
+use crate::core::remote_helper::config::{RedactedRpc, Wallet, get_default_rpc};
+use crate::core::remote_helper::error::RemoteHelperError;
+use crate::core::remote_helper::evm::{Evm, Settings};
+use crate::core::remote_helper::executor::{Background, FeeBump, RpcOptions};
+use log::debug;
+use std::rc::Rc;
+
+// Builds an Evm without going through process args or git config, e.g. to embed it in tests
+pub struct EvmBuilder {
+    protocol: String,
+    address: [u8; 20],
+    wallet: Option<Wallet>,
+    sponsor: Option<Wallet>,
+    git: Rc<dyn Git>,
+    rpc: Option<String>,
+    rpc_options: RpcOptions,
+    offload: Option<Offload>,
+    fee_bump: Option<FeeBump>,
+    worker_threads: Option<usize>,
+    settings: Settings,
+}
+
+impl EvmBuilder {
+    pub fn new(protocol: &str, address: [u8; 20], git: Rc<dyn Git>) -> Self {
+        Self {
+            protocol: protocol.to_string(),
+            address,
+            wallet: None,
+            sponsor: None,
+            git,
+            rpc: None,
+            rpc_options: RpcOptions::default(),
+            offload: None,
+            fee_bump: None,
+            worker_threads: None,
+            settings: Settings::default(),
+        }
+    }
+
+    // Without one the executor can only list and fetch
+    pub fn wallet(mut self, wallet: Wallet) -> Self {
+        self.wallet = Some(wallet);
+        self
+    }
+
+    // Pays the gas of pushes signed by the wallet, which has to own the contract
+    pub fn sponsor(mut self, sponsor: Option<Wallet>) -> Self {
+        self.sponsor = sponsor;
+        self
+    }
+
+    // Defaults to the protocol's public rpc when not set
+    pub fn rpc(mut self, rpc: &str) -> Self {
+        self.rpc = Some(rpc.to_string());
+        self
+    }
+
+    // Added to every http request, e.g. an Authorization header the rpc provider requires
+    pub fn rpc_headers(mut self, rpc_headers: Vec<(String, String)>) -> Self {
+        self.rpc_options.headers = rpc_headers;
+        self
+    }
+
+    // Http requests go through it instead of a proxy from the environment
+    pub fn rpc_proxy(mut self, rpc_proxy: Option<String>) -> Self {
+        self.rpc_options.proxy = rpc_proxy;
+        self
+    }
+
+    pub fn offload(mut self, offload: Option<Offload>) -> Self {
+        self.offload = offload;
+        self
+    }
+
+    pub fn fee_bump(mut self, fee_bump: Option<FeeBump>) -> Self {
+        self.fee_bump = fee_bump;
+        self
+    }
+
+    // Runs the executor on a multi-threaded runtime, the default current-thread one is
+    // cheaper when nothing runs concurrently
+    pub fn worker_threads(mut self, worker_threads: Option<usize>) -> Self {
+        self.worker_threads = worker_threads;
+        self
+    }
+
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn build(self) -> Result<Evm, RemoteHelperError> {
+        let rpc = match self.rpc {
+            Some(rpc) => rpc,
+            None => get_default_rpc(&self.protocol)
+                .ok_or(RemoteHelperError::Missing {
+                    what: "rpc".to_string(),
+                })?
+                .to_string(),
+        };
+        debug!(
+            "building {} remote helper for 0x{} with rpc: {}",
+            self.protocol,
+            hex::encode(self.address),
+            RedactedRpc(&rpc)
+        );
+
+        let runtime = match self.worker_threads {
+            Some(threads) => {
+                debug!("using a runtime with {} worker threads", threads);
+                tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(threads)
+                    .enable_all()
+                    .build()
+            }
+            None => tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build(),
+        }
+        .map_err(|e| RemoteHelperError::Failure {
+            action: "creating runtime".to_string(),
+            details: Some(e.to_string()),
+        })?;
+        let executor = match (self.settings.read_only, self.wallet) {
+            (false, Some(wallet)) => runtime.block_on(Background::new(
+                wallet,
+                self.sponsor,
+                &rpc,
+                self.rpc_options,
+                self.address,
+                self.offload,
+                self.fee_bump,
+            ))?,
+            _ => runtime.block_on(Background::new_readonly(
+                &rpc,
+                self.rpc_options,
+                self.address,
+                self.offload,
+            ))?,
+        };
+
+        Evm::new(runtime, Box::new(executor), self.git, self.settings)
+    }
+}
+
+#[test]
+fn test_build_unknown_protocol() {
+    use crate::core::git::MockGit;
+
+    let err = EvmBuilder::new("unknown", [0u8; 20], Rc::new(MockGit::new()))
+        .wallet(Wallet::Environment)
+        .build()
+        .err()
+        .expect("should fail without an rpc");
+    assert_eq!(
+        err,
+        RemoteHelperError::Missing {
+            what: "rpc".to_string(),
+        }
+    );
+}
@@ -13,6 +13,19 @@ pub enum RemoteHelperError {
         action: String,
         details: Option<String>,
     },
+    // The remote reference moved since the pusher last saw it
+    NonFastForward {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    // An earlier transaction of the push stored the objects, so a retry only updates the ref
+    RefUpdateFailed {
+        name: String,
+        details: String,
+    },
+    // Ctrl-c stopped the command before it finished
+    Interrupted,
 }
 
 impl Error for RemoteHelperError {}
@@ -30,6 +43,21 @@ impl std::fmt::Display for RemoteHelperError {
                     .clone()
                     .unwrap_or("details not provided".to_string())
             ),
+            Self::NonFastForward {
+                name,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "stale info, {} is at {} but {} was expected",
+                name, actual, expected
+            ),
+            Self::RefUpdateFailed { name, details } => write!(
+                f,
+                "objects stored, ref update failed for {}: {}",
+                name, details
+            ),
+            Self::Interrupted => write!(f, "aborted"),
         }
     }
 }
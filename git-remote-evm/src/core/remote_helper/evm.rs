@@ -1,24 +1,90 @@
+use crate::core::delta::DeltaObject;
+use crate::core::fast_export::ExportStream;
 use crate::core::git::Git;
 #[cfg(test)]
 use crate::core::git::MockGit;
-use crate::core::hash::Hash;
 #[cfg(test)]
+use crate::core::git::memory::{InMemoryGit, commit_objects};
+#[cfg(test)]
+use crate::core::git::mock::RecordingGit;
+use crate::core::hash::Hash;
 use crate::core::object::{Object, ObjectKind};
-use crate::core::reference::{Fetch, Push, Reference};
-use crate::core::remote_helper::executor::Executor;
+use crate::core::reference::{Fetch, Keys, Push, Reference};
 #[cfg(test)]
 use crate::core::remote_helper::executor::MockExecutor;
+use crate::core::remote_helper::executor::{Executor, ExpectedRef, Fetched, PushReceipt};
+use crate::core::remote_helper::interrupt;
+use crate::core::remote_helper::object_cache::ObjectCache;
+use crate::core::remote_helper::object_filter::ObjectFilter;
 use crate::core::remote_helper::{RemoteHelper, RemoteHelperError};
 use crate::print_user;
-use log::debug;
+use alloy::primitives::U256;
+use alloy::primitives::utils::{format_ether, format_units};
+use log::{debug, warn};
 #[cfg(test)]
-use mockall::predicate::eq;
-use std::collections::HashSet;
+use mockall::predicate::{always, eq};
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
+#[cfg(test)]
+use std::str::FromStr;
+
+const SYMBOLIC_REF_HEAD: &str = "HEAD";
+// Branches and tags keep their names on both sides
+const REFSPEC_HEADS: &str = "refspec refs/heads/*:refs/heads/*";
+const REFSPEC_TAGS: &str = "refspec refs/tags/*:refs/tags/*";
+// So are notes and replacements, they're stored like any other ref
+const REFSPEC_NOTES: &str = "refspec refs/notes/*:refs/notes/*";
+const REFSPEC_REPLACE: &str = "refspec refs/replace/*:refs/replace/*";
+// Where git keeps track of what has been exported, see the refspec capability
+const EXPORT_REFSPEC: &str = "refspec refs/*:refs/gitdem/exported/*";
+// Where an export stream is imported before being pushed, so local refs stay untouched
+const EXPORT_IMPORT_PREFIX: &str = "refs/gitdem/import/";
+
+// Objects, those stored as deltas, the references they complete and the remote hashes those
+// were computed against
+type PushBatch = (
+    Vec<Object>,
+    Vec<DeltaObject>,
+    Vec<Reference>,
+    Vec<ExpectedRef>,
+);
+
+#[derive(Debug, Default, Clone)]
+pub struct Settings {
+    // Abort a push instead of warning when the balance won't cover its estimated cost
+    pub strict_balance_check: bool,
+    // Advertise export instead of push, git always prefers push when both are available
+    pub use_export: bool,
+    // Abort a push containing a larger serialized object, None lifts the limit
+    pub max_object_bytes: Option<usize>,
+    // Abort a fetch that would download or queue more objects, None lifts the limit
+    pub max_fetch_objects: Option<usize>,
+    // Reject pushes and exports before anything is computed, fetching still works
+    pub read_only: bool,
+    // Target of HEAD when cloning an empty remote, takes precedence over the contract's
+    pub default_branch: Option<String>,
+    // Rules from .gitdemignore, a push with a matching blob is aborted
+    pub object_filter: Option<ObjectFilter>,
+    // Push changed blobs as deltas against their previous version when it's already on-chain
+    pub delta_objects: bool,
+    // Objects fetched from this contract by any clone, checked before the chain is asked
+    pub object_cache: Option<ObjectCache>,
+}
+
 pub struct Evm {
     runtime: tokio::runtime::Runtime,
     executor: Box<dyn Executor>,
     git: Rc<dyn Git>,
+    settings: Settings,
+    // Set by git through `option dry-run`, estimates the cost of a push instead of sending it
+    dry_run: Cell<bool>,
+    // Set by git through `option atomic`, never splits a push into multiple transactions
+    atomic: Cell<bool>,
+    // Learned from the first list, it can't change so later commands don't ask again
+    remote_format: OnceCell<String>,
+    // Set by git through `option push-option`, there are no hooks on-chain so they're only logged
+    push_options: RefCell<Vec<String>>,
 }
 
 impl Evm {
@@ -26,35 +92,635 @@ impl Evm {
         runtime: tokio::runtime::Runtime,
         executor: Box<dyn Executor>,
         git: Rc<dyn Git>,
+        settings: Settings,
     ) -> Result<Self, RemoteHelperError> {
         Ok(Self {
             runtime,
             executor,
             git,
+            settings,
+            dry_run: Cell::new(false),
+            atomic: Cell::new(false),
+            remote_format: OnceCell::new(),
+            push_options: RefCell::new(Vec::new()),
+        })
+    }
+
+    // Stops waiting on the executor once ctrl-c is pressed, objects already saved are kept
+    fn block_on<T>(
+        &self,
+        future: impl Future<Output = Result<T, RemoteHelperError>>,
+    ) -> Result<T, RemoteHelperError> {
+        self.runtime.block_on(interrupt::cancellable(future))
+    }
+
+    // git reads the object format before any ref, a missing one is inferred from the ref hashes
+    // and for an empty remote taken from the local repository
+    fn with_object_format_first(
+        &self,
+        references: Vec<Reference>,
+    ) -> Result<Vec<Reference>, RemoteHelperError> {
+        let (mut formats, mut references): (Vec<Reference>, Vec<Reference>) =
+            references.into_iter().partition(|r| {
+                matches!(
+                    r,
+                    Reference::KeyValue {
+                        key: Keys::ObjectFormat,
+                        ..
+                    }
+                )
+            });
+        if formats.is_empty() {
+            let hash = references.iter().find_map(|r| match r {
+                Reference::Normal { hash, .. } => Some(hash),
+                _ => None,
+            });
+            let is_sha256 = match hash {
+                Some(hash) => hash.is_sha256(),
+                None => self.git.is_sha256()?,
+            };
+            let value = if is_sha256 { "sha256" } else { "sha1" };
+            debug!("remote has no object format, using: {}", value);
+            formats.push(Reference::KeyValue {
+                key: Keys::ObjectFormat,
+                value: value.to_string(),
+            });
+        }
+        formats.append(&mut references);
+        Ok(formats)
+    }
+
+    // Lets git see what annotated tags point at, e.g. to follow tags, without fetching them.
+    // Only tags available without a request are peeled, git lists the others without a target.
+    fn peel_tags(&self, references: Vec<Reference>) -> Vec<Reference> {
+        references
+            .into_iter()
+            .map(|r| match r {
+                Reference::Normal { name, hash } if name.starts_with("refs/tags/") => {
+                    match self.peel(&hash) {
+                        Some(object) => Reference::Peeled {
+                            name,
+                            tag: hash,
+                            object,
+                        },
+                        None => Reference::Normal { name, hash },
+                    }
+                }
+                r => r,
+            })
+            .collect()
+    }
+
+    // None for lightweight tags and tags that aren't available locally or in the object cache,
+    // tags of tags are followed to the first other object
+    fn peel(&self, hash: &Hash) -> Option<Hash> {
+        let mut target = hash.clone();
+        let mut is_annotated = false;
+        loop {
+            let object = self.local_object(&target)?;
+            if !matches!(object.get_kind(), ObjectKind::Tag) {
+                return is_annotated.then_some(target);
+            }
+            target = object.get_related()[0].clone();
+            is_annotated = true;
+        }
+    }
+
+    // A failure only means the object isn't used, so it's logged instead of returned
+    fn local_object(&self, hash: &Hash) -> Option<Object> {
+        let object = match self.git.has_object(hash) {
+            Ok(true) => self.git.get_object(hash.clone()).map(Some),
+            Ok(false) => match &self.settings.object_cache {
+                Some(cache) => cache.get(hash),
+                None => Ok(None),
+            },
+            Err(e) => Err(e),
+        };
+        object.unwrap_or_else(|e| {
+            debug!("failed to read {}: {}", hash, e);
+            None
+        })
+    }
+
+    // A delta is applied to its base from fetched, the local repository or the remote, in which
+    // case the base is added to fetched so it's saved along with the object
+    fn fetch_object(
+        &self,
+        hash: Hash,
+        fetched: &mut Vec<Object>,
+    ) -> Result<Object, RemoteHelperError> {
+        let Some(cache) = &self.settings.object_cache else {
+            return self.fetch_remote_object(hash, fetched);
+        };
+        // The cache only saves a download, failing to use it never fails the fetch
+        match cache.get(&hash) {
+            Ok(Some(object)) => {
+                debug!("found {} in the object cache", hash);
+                return Ok(object);
+            }
+            Ok(None) => {}
+            Err(e) => warn!("failed to read {} from the object cache: {}", hash, e),
+        }
+        let object = self.fetch_remote_object(hash, fetched)?;
+        if let Err(e) = cache.put(&object) {
+            warn!(
+                "failed to add {} to the object cache: {}",
+                object.get_hash(),
+                e
+            );
+        }
+        Ok(object)
+    }
+
+    fn fetch_remote_object(
+        &self,
+        hash: Hash,
+        fetched: &mut Vec<Object>,
+    ) -> Result<Object, RemoteHelperError> {
+        let delta = match self.block_on(self.executor.fetch(hash))? {
+            Fetched::Object(object) => return Ok(object),
+            Fetched::Delta(delta) => delta,
+        };
+        let base = match fetched.iter().find(|o| o.get_hash() == delta.get_base()) {
+            Some(base) => base.clone(),
+            None if self.git.has_object(delta.get_base())? => {
+                self.git.get_object(delta.get_base().clone())?
+            }
+            None => {
+                let base = self.fetch_object(delta.get_base().clone(), fetched)?;
+                fetched.push(base.clone());
+                base
+            }
+        };
+        debug!(
+            "applying delta of {} against {}",
+            delta.get_hash(),
+            delta.get_base()
+        );
+        delta.apply(&base)
+    }
+
+    // Pairs each new blob with the one at the same path in the first parent of a new commit,
+    // as long as that one is already on-chain
+    fn delta_bases(
+        &self,
+        objects: &[Object],
+        remote_object_hashes: &[Hash],
+    ) -> Result<HashMap<Hash, Hash>, RemoteHelperError> {
+        let pushed: HashMap<&Hash, &Object> = objects.iter().map(|o| (o.get_hash(), o)).collect();
+        let get = |hash: &Hash| match pushed.get(hash) {
+            Some(object) => Ok((*object).clone()),
+            None => self.git.get_object(hash.clone()),
+        };
+
+        let mut bases = HashMap::new();
+        for commit in objects
+            .iter()
+            .filter(|o| matches!(o.get_kind(), ObjectKind::Commit))
+        {
+            let [tree, parent, ..] = commit.get_related().as_slice() else {
+                continue;
+            };
+            let parent_tree = get(parent)?.get_related()[0].clone();
+            // Trees that aren't pushed are unchanged, so are the blobs they contain
+            let mut trees = vec![(tree.clone(), parent_tree)];
+            while let Some((tree, parent_tree)) = trees.pop() {
+                if tree == parent_tree {
+                    continue;
+                }
+                let previous: HashMap<String, Hash> =
+                    get(&parent_tree)?.get_tree_entries().into_iter().collect();
+                for (name, hash) in get(&tree)?.get_tree_entries() {
+                    let Some(previous) = previous.get(&name) else {
+                        continue;
+                    };
+                    match pushed.get(&hash).map(|o| o.get_kind()) {
+                        Some(ObjectKind::Blob) if remote_object_hashes.contains(previous) => {
+                            bases.entry(hash).or_insert_with(|| previous.clone());
+                        }
+                        Some(ObjectKind::Tree) => trees.push((hash, previous.clone())),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        Ok(bases)
+    }
+
+    // Objects with a base are stored as a delta when it's small enough to be worth it
+    fn split_deltas(
+        &self,
+        objects: Vec<Object>,
+        bases: &HashMap<Hash, Hash>,
+    ) -> Result<(Vec<Object>, Vec<DeltaObject>), RemoteHelperError> {
+        let (mut full, mut deltas) = (vec![], vec![]);
+        for object in objects {
+            let delta = match bases.get(object.get_hash()) {
+                Some(base) => DeltaObject::compute(&self.git.get_object(base.clone())?, &object),
+                None => None,
+            };
+            match delta {
+                Some(delta) => {
+                    debug!(
+                        "pushing {} as a {} byte delta against {}",
+                        delta.get_hash(),
+                        delta.get_delta().len(),
+                        delta.get_base()
+                    );
+                    deltas.push(delta);
+                }
+                None => full.push(object),
+            }
+        }
+        Ok((full, deltas))
+    }
+
+    fn ensure_writable(&self) -> Result<(), RemoteHelperError> {
+        match self.settings.read_only {
+            true => Err(RemoteHelperError::Failure {
+                action: "push".to_string(),
+                details: Some("remote is read-only".to_string()),
+            }),
+            false => Ok(()),
+        }
+    }
+
+    fn save_objects(&self, mut objects: Vec<Object>) -> Result<(), RemoteHelperError> {
+        match objects.len() {
+            0 => Ok(()),
+            1 => self.git.save_object(objects.remove(0)),
+            _ => self.git.save_objects(objects),
+        }
+    }
+
+    fn check_object_size(&self, object: &Object) -> Result<(), RemoteHelperError> {
+        let Some(limit) = self.settings.max_object_bytes else {
+            return Ok(());
+        };
+        let size = object.serialized_size();
+        if size > limit {
+            return Err(RemoteHelperError::Invalid {
+                what: "object too large".to_string(),
+                value: format!("{} ({} bytes, limit: {})", object.get_hash(), size, limit),
+            });
+        }
+        Ok(())
+    }
+
+    // A remote could advertise objects with a huge fan-out, so what a fetch holds in memory is bounded
+    fn check_fetch_limit(&self, fetched: usize, queued: usize) -> Result<(), RemoteHelperError> {
+        let Some(limit) = self.settings.max_fetch_objects else {
+            return Ok(());
+        };
+        if fetched >= limit || queued > limit {
+            return Err(RemoteHelperError::Failure {
+                action: "fetching objects".to_string(),
+                details: Some(format!(
+                    "more than {} objects fetched or queued ({} fetched, {} queued)",
+                    limit, fetched, queued
+                )),
+            });
+        }
+        Ok(())
+    }
+
+    async fn check_balance(&self, cost: U256) -> Result<(), RemoteHelperError> {
+        let balance = self.executor.balance().await?;
+        if balance >= cost {
+            return Ok(());
+        }
+
+        let details = format!(
+            "balance of {} wei is lower than the estimated cost of {} wei",
+            balance, cost
+        );
+        if self.settings.strict_balance_check {
+            return Err(RemoteHelperError::Failure {
+                action: "checking balance".to_string(),
+                details: Some(details),
+            });
+        }
+        print_user!(event: "warning"; "warning: {}", details);
+        Ok(())
+    }
+
+    async fn report_push_cost(
+        &self,
+        objects: Vec<Object>,
+        deltas: Vec<DeltaObject>,
+        references: Vec<Reference>,
+    ) -> Result<(), RemoteHelperError> {
+        let (object_count, reference_count) = (objects.len() + deltas.len(), references.len());
+        let cost = self
+            .executor
+            .estimate_push_cost(objects, deltas, references)
+            .await?;
+        print_user!(
+            event: "push_estimate",
+            objects = object_count,
+            references = reference_count,
+            cost_wei = cost.to_string();
+            "would push {} object{} and {} reference{}, est. {} gwei / {} ETH",
+            object_count,
+            if object_count == 1 { "" } else { "s" },
+            reference_count,
+            if reference_count == 1 { "" } else { "s" },
+            format_gwei(cost)?,
+            format_ether(cost),
+        );
+        Ok(())
+    }
+
+    fn remote_object_format(&self) -> Result<String, RemoteHelperError> {
+        if let Some(format) = self.remote_format.get() {
+            return Ok(format.clone());
+        }
+        let refs = self.block_on(self.executor.list())?;
+        let format = object_format(&refs)?;
+        debug!("remote object format: {}", format);
+        Ok(self.remote_format.get_or_init(|| format).clone())
+    }
+
+    fn verify_object_format(&self) -> Result<(), RemoteHelperError> {
+        let remote_format = self.remote_object_format()?;
+        let local_format = if self.git.is_sha256()? {
+            "sha256"
+        } else {
+            "sha1"
+        };
+
+        if remote_format != local_format {
+            return Err(RemoteHelperError::Invalid {
+                what: "object format mismatch".to_string(),
+                value: format!("local: {}, remote: {}", local_format, remote_format),
+            });
+        }
+        Ok(())
+    }
+}
+
+fn object_format(refs: &[Reference]) -> Result<String, RemoteHelperError> {
+    refs.iter()
+        .find_map(|reference| match reference {
+            Reference::KeyValue {
+                key: Keys::ObjectFormat,
+                value,
+            } => Some(value.clone()),
+            _ => None,
+        })
+        .ok_or(RemoteHelperError::Missing {
+            what: "object format".to_string(),
+        })
+}
+
+fn report_push(receipt: &PushReceipt) {
+    if receipt.tx_hashes.is_empty() {
+        return;
+    }
+    let tx_hashes: Vec<String> = receipt.tx_hashes.iter().map(|h| h.to_string()).collect();
+    print_user!(
+        event: "push",
+        transactions = tx_hashes,
+        gas = receipt.total_gas,
+        cost_wei = receipt.total_cost.to_string();
+        "pushed in {} transaction{}, total gas {}, cost {} ETH: {}",
+        tx_hashes.len(),
+        if tx_hashes.len() == 1 { "" } else { "s" },
+        receipt.total_gas,
+        format_ether(receipt.total_cost),
+        tx_hashes.join(", ")
+    );
+}
+
+// Blobs before the trees that list them and trees before commits, so a fetch never finds
+// an object whose dependencies haven't been uploaded yet. Unrelated objects keep their order.
+fn topological_order(objects: Vec<Object>) -> Vec<Object> {
+    let index: HashMap<&Hash, usize> = objects
+        .iter()
+        .enumerate()
+        .map(|(i, object)| (object.get_hash(), i))
+        .collect();
+    let mut emitted = vec![false; objects.len()];
+    let mut order = Vec::with_capacity(objects.len());
+    for root in 0..objects.len() {
+        // The flag is set once the object's dependencies are on the stack above it
+        let mut stack = vec![(root, false)];
+        while let Some((i, expanded)) = stack.pop() {
+            if emitted[i] {
+                continue;
+            }
+            if expanded {
+                emitted[i] = true;
+                order.push(i);
+                continue;
+            }
+            stack.push((i, true));
+            for hash in objects[i].get_related().iter().rev() {
+                if let Some(&j) = index.get(hash) {
+                    stack.push((j, false));
+                }
+            }
+        }
+    }
+
+    let mut rank = vec![0; objects.len()];
+    for (position, i) in order.into_iter().enumerate() {
+        rank[i] = position;
+    }
+    let mut ranked: Vec<(usize, Object)> = rank.into_iter().zip(objects).collect();
+    ranked.sort_by_key(|(position, _)| *position);
+    ranked.into_iter().map(|(_, object)| object).collect()
+}
+
+// A HEAD pointing at a branch without a hash is reported as unborn, like protocol v2 does
+fn unborn_head(references: Vec<Reference>) -> Vec<Reference> {
+    let names: HashSet<String> = references
+        .iter()
+        .filter_map(|r| match r {
+            Reference::Normal { name, .. } => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    references
+        .into_iter()
+        .map(|r| match r {
+            Reference::Symbolic { name, target }
+                if name == SYMBOLIC_REF_HEAD && !names.contains(&target) =>
+            {
+                Reference::KeyValue {
+                    key: Keys::Unborn,
+                    value: target,
+                }
+            }
+            r => r,
         })
+        .collect()
+}
+
+// Node errors for a transaction that doesn't fit in a block or a request, the only ones
+// splitting the push can fix, anything else would fail or repeat for every reference
+const TOO_LARGE_ERRORS: [&str; 4] = [
+    "exceeds block gas limit",
+    "intrinsic gas too high",
+    "oversized data",
+    "too large",
+];
+
+fn is_too_large(error: &RemoteHelperError) -> bool {
+    match error {
+        RemoteHelperError::Failure {
+            details: Some(details),
+            ..
+        } => {
+            let details = details.to_lowercase();
+            TOO_LARGE_ERRORS.iter().any(|e| details.contains(e))
+        }
+        _ => false,
+    }
+}
+
+// The null hash expects the reference not to exist yet
+fn check_lease(
+    name: &str,
+    expected: &Hash,
+    actual: Option<&Hash>,
+) -> Result<(), RemoteHelperError> {
+    let matches = match actual {
+        Some(actual) => actual == expected,
+        None => expected.is_zero(),
+    };
+    if matches {
+        return Ok(());
+    }
+    Err(RemoteHelperError::NonFastForward {
+        name: name.to_string(),
+        expected: expected.to_string(),
+        actual: actual.map_or("nothing".to_string(), |hash| hash.to_string()),
+    })
+}
+
+// Keeps the objects whose related objects are all either existing or complete themselves, in order
+fn complete_objects(objects: Vec<Object>, existing: &[Hash]) -> Vec<Object> {
+    let mut complete: HashSet<Hash> = existing.iter().cloned().collect();
+    loop {
+        let mut changed = false;
+        for object in &objects {
+            if !complete.contains(object.get_hash())
+                && object.get_related().iter().all(|h| complete.contains(h))
+            {
+                complete.insert(object.get_hash().clone());
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
     }
+    objects
+        .into_iter()
+        .filter(|object| complete.contains(object.get_hash()))
+        .collect()
 }
 
 impl RemoteHelper for Evm {
     fn capabilities(&self) -> Vec<&'static str> {
-        vec!["*fetch", "*push"]
+        if self.settings.use_export {
+            vec![
+                "*fetch",
+                "*export",
+                EXPORT_REFSPEC,
+                "option",
+                "object-format",
+            ]
+        } else {
+            vec![
+                "*fetch",
+                "*push",
+                REFSPEC_HEADS,
+                REFSPEC_TAGS,
+                REFSPEC_NOTES,
+                REFSPEC_REPLACE,
+                "option",
+                "object-format",
+                // git sends deletions to any push helper, this only tells wrappers they're handled
+                "delete-refs",
+            ]
+        }
     }
 
-    fn list(&self, _is_for_push: bool) -> Result<Vec<Reference>, RemoteHelperError> {
-        self.runtime.block_on(self.executor.list())
+    fn list(&self, is_for_push: bool) -> Result<Vec<Reference>, RemoteHelperError> {
+        let references = self.block_on(self.executor.list())?;
+        if let Ok(format) = object_format(&references) {
+            let _ = self.remote_format.set(format);
+        }
+        if !is_for_push {
+            let is_empty = !references
+                .iter()
+                .any(|r| matches!(r, Reference::Normal { .. }));
+            let has_head = references.iter().any(
+                |r| matches!(r, Reference::Symbolic { name, .. } if name == SYMBOLIC_REF_HEAD),
+            );
+
+            // The object format is still listed so git initializes the clone correctly
+            let mut references = unborn_head(references);
+            if is_empty {
+                print_user!("remote repository is empty");
+                if let Some(target) = &self.settings.default_branch {
+                    references.retain(|r| {
+                        !matches!(
+                            r,
+                            Reference::KeyValue {
+                                key: Keys::Unborn,
+                                ..
+                            }
+                        )
+                    });
+                    references.insert(
+                        0,
+                        Reference::KeyValue {
+                            key: Keys::Unborn,
+                            value: target.clone(),
+                        },
+                    );
+                } else if !has_head {
+                    let target = self.block_on(self.executor.default_branch())?;
+                    references.insert(
+                        0,
+                        Reference::KeyValue {
+                            key: Keys::Unborn,
+                            value: target,
+                        },
+                    );
+                }
+            }
+            let references = self.with_object_format_first(references)?;
+            return Ok(self.peel_tags(references));
+        }
+
+        // git only needs the current values of normal refs to compute fast-forwards,
+        // refs missing from the list are treated as new so an empty repo is fine as is
+        let references: Vec<Reference> = references
+            .into_iter()
+            .filter(|r| !matches!(r, Reference::Symbolic { .. }))
+            .collect();
+        self.with_object_format_first(references)
     }
 
     fn fetch(&self, fetches: Vec<Fetch>) -> Result<(), RemoteHelperError> {
         print_user!(
+            event: "fetch_start",
+            references = fetches.len();
             "fetching {} reference{}",
             fetches.len(),
             if fetches.len() == 1 { "" } else { "s" }
         );
 
-        let existing_objects = self.git.list_all_objects()?;
+        self.verify_object_format()?;
+        let mut existing_objects = self.git.list_all_objects()?;
 
         let mut to_fetch: Vec<Hash> = fetches.into_iter().map(|f| f.hash).collect();
         let mut processed = HashSet::new();
+        let mut objects = Vec::new();
 
         while let Some(hash) = to_fetch.pop() {
             if existing_objects.contains(&hash) {
@@ -63,86 +729,449 @@ impl RemoteHelper for Evm {
             if !processed.insert(hash.clone()) {
                 continue;
             }
+            // Left behind by an interrupted fetch, which only saves objects along with everything they point at
+            if self.git.has_object(&hash)? {
+                debug!("object {} is already present locally", hash);
+                existing_objects.push(hash);
+                continue;
+            }
 
-            let object = self.runtime.block_on(self.executor.fetch(hash))?;
+            let before = objects.len();
+            let object = match self
+                .check_fetch_limit(objects.len(), to_fetch.len())
+                .and_then(|_| self.fetch_object(hash.clone(), &mut objects))
+            {
+                Ok(object) => object,
+                Err(e) => {
+                    // Existing objects are never traversed, so only those with everything they point at are kept
+                    let fetched = objects.len();
+                    let complete = complete_objects(objects, &existing_objects);
+                    let saved = complete.len();
+                    self.save_objects(complete)?;
+                    print_user!(
+                        event: "fetch_failed",
+                        hash = hash.to_string(),
+                        saved = saved,
+                        fetched = fetched;
+                        "failed to fetch {}, saved {} of {} fetched objects, fetching again resumes from there",
+                        hash,
+                        saved,
+                        fetched
+                    );
+                    return Err(e);
+                }
+            };
+            // Bases fetched for a delta aren't fetched again if something else points at them
+            for base in &objects[before..] {
+                processed.insert(base.get_hash().clone());
+                to_fetch.extend(base.get_related().iter().cloned());
+            }
             to_fetch.extend(object.get_related().iter().cloned());
-
-            self.git.save_object(object)?;
+            objects.push(object);
         }
 
+        let fetched = objects.len();
+        self.save_objects(objects)?;
+
         print_user!(
+            event: "fetch",
+            got = fetched;
             "got {} new object{}",
-            processed.len(),
-            if processed.len() == 1 { "" } else { "s" }
+            fetched,
+            if fetched == 1 { "" } else { "s" }
         );
         Ok(())
     }
 
     fn push(&self, pushes: Vec<Push>) -> Result<(), RemoteHelperError> {
+        let push_options = self.push_options.take();
+        if !push_options.is_empty() {
+            debug!("ignoring push options: {:?}", push_options);
+        }
+        self.ensure_writable()?;
         if pushes.is_empty() {
             print_user!("nothing to push");
             return Ok(());
         }
+        print_user!(
+            "pushing as 0x{}",
+            hex::encode(self.executor.signer_address())
+        );
 
+        self.verify_object_format()?;
         print_user!("calculating required updates");
 
+        // A push to HEAD changes what it points at rather than storing a hash under its name
+        let (symbolic_pushes, pushes): (Vec<Push>, Vec<Push>) = pushes
+            .into_iter()
+            .partition(|push| push.remote == SYMBOLIC_REF_HEAD);
+        let symbolic_references = symbolic_pushes
+            .into_iter()
+            .map(|push| match push.local.starts_with("refs/") {
+                true => Ok(Reference::Symbolic {
+                    name: push.remote,
+                    target: push.local,
+                }),
+                false => Err(RemoteHelperError::Invalid {
+                    what: "symbolic reference target".to_string(),
+                    value: push.local,
+                }),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if self.atomic.get() && !symbolic_references.is_empty() && !pushes.is_empty() {
+            return Err(RemoteHelperError::Invalid {
+                what: "atomic push".to_string(),
+                value: "HEAD can't be changed in the same transaction as other references"
+                    .to_string(),
+            });
+        }
+
+        // An empty source (`git push origin :branch`) deletes the remote reference
         let local_ref_hashes = pushes
             .iter()
-            .map(|push| self.git.resolve_reference(&push.local))
+            .map(|push| match push.local.is_empty() {
+                true => Ok(None),
+                false => self.git.resolve_reference(&push.local).map(Some),
+            })
             .collect::<Result<Vec<_>, _>>()?;
 
-        self.runtime.block_on(async move {
-            let remote_ref_names: Vec<String> =
-                pushes.into_iter().map(|push| push.remote).collect();
+        self.block_on(async move {
+            let (remote_ref_names, expected_remote_hashes): (Vec<String>, Vec<Option<Hash>>) =
+                pushes
+                    .into_iter()
+                    .map(|push| (push.remote, push.expected_remote))
+                    .unzip();
             let remote_ref_hashes = self
                 .executor
                 .resolve_references(remote_ref_names.clone())
                 .await?;
+            // Checked before anything is sent, so a stale lease leaves every reference untouched
+            for ((name, expected), actual) in remote_ref_names
+                .iter()
+                .zip(expected_remote_hashes.iter())
+                .zip(remote_ref_hashes.iter())
+            {
+                if let Some(expected) = expected {
+                    check_lease(name, expected, actual.as_ref())?;
+                }
+            }
             let remote_object_hashes = self.executor.list_all_objects().await?;
 
-            let mut references = Vec::new();
-            let mut objects = HashSet::new();
+            // Objects new to each reference, kept apart in case they have to be pushed one by one,
+            // with the remote hash the contract has to still see for the update to apply
+            let mut per_reference: Vec<(Vec<Hash>, Reference, ExpectedRef)> = Vec::new();
+            let mut seen = HashSet::new();
             for ((local_hash, remote_hash), remote_ref_name) in local_ref_hashes
                 .into_iter()
                 .zip(remote_ref_hashes.into_iter())
                 .zip(remote_ref_names.into_iter())
             {
-                if local_hash == remote_hash {
-                    debug!("remote ref {} is up to date", remote_ref_name);
+                // The contract deletes a reference pushed with the zero hash
+                let Some(local_hash) = local_hash else {
+                    match &remote_hash {
+                        Some(remote_hash) => per_reference.push((
+                            vec![],
+                            Reference::Normal {
+                                name: remote_ref_name.clone(),
+                                hash: Hash::zero(remote_hash.is_sha256()),
+                            },
+                            (remote_ref_name, Some(remote_hash.clone())),
+                        )),
+                        None => debug!("remote ref {} is already deleted", remote_ref_name),
+                    }
                     continue;
+                };
+                match &remote_hash {
+                    Some(remote_hash) if remote_hash == &local_hash => {
+                        debug!("remote ref {} is up to date", remote_ref_name);
+                        continue;
+                    }
+                    Some(_) => {}
+                    // Everything the local ref points at is new, apart from objects pushed for other refs
+                    None => debug!("remote ref {} doesn't exist yet", remote_ref_name),
                 }
 
-                references.push(Reference::Normal {
-                    name: remote_ref_name.clone(),
-                    hash: local_hash.clone(),
-                });
-                objects.extend(
-                    self.git
-                        .list_objects(local_hash.clone())?
-                        .into_iter()
-                        .filter(|hash| !remote_object_hashes.contains(hash))
-                        .map(|hash| self.git.get_object(hash.clone()))
-                        .collect::<Result<Vec<_>, _>>()?,
-                );
+                let hashes: Vec<Hash> = self
+                    .git
+                    .list_objects(local_hash.clone())?
+                    .into_iter()
+                    .filter(|hash| !remote_object_hashes.contains(hash))
+                    .filter(|hash| seen.insert(hash.clone()))
+                    .collect();
+                per_reference.push((
+                    hashes,
+                    Reference::Normal {
+                        name: remote_ref_name.clone(),
+                        hash: local_hash,
+                    },
+                    (remote_ref_name, remote_hash),
+                ));
             }
 
-            if objects.is_empty() && references.is_empty() {
+            let mut object_hashes: Vec<Hash> = per_reference
+                .iter()
+                .flat_map(|(hashes, _, _)| hashes.iter().cloned())
+                .collect();
+            let objects = match object_hashes.len() {
+                0 => vec![],
+                1 => vec![self.git.get_object(object_hashes.remove(0))?],
+                _ => self.git.get_objects(object_hashes)?,
+            };
+            for object in &objects {
+                debug!("pushing {}", object.describe());
+                self.check_object_size(object)?;
+            }
+            if let Some(filter) = &self.settings.object_filter {
+                filter.check(&objects)?;
+            }
+            let bases = match self.settings.delta_objects {
+                true => self.delta_bases(&objects, &remote_object_hashes)?,
+                false => HashMap::new(),
+            };
+            // Earlier batches never point into later ones, so the concatenation stays ordered too
+            let mut batches: Vec<PushBatch> = Vec::new();
+            let mut remaining = objects.into_iter();
+            for (hashes, reference, expected) in per_reference {
+                let (objects, deltas) = self.split_deltas(
+                    topological_order(remaining.by_ref().take(hashes.len()).collect()),
+                    &bases,
+                )?;
+                batches.push((objects, deltas, vec![reference], vec![expected]));
+            }
+            if !symbolic_references.is_empty() {
+                batches.push((vec![], vec![], symbolic_references, vec![]));
+            }
+            let objects: Vec<Object> = batches
+                .iter()
+                .flat_map(|(objects, _, _, _)| objects.iter().cloned())
+                .collect();
+            let deltas: Vec<DeltaObject> = batches
+                .iter()
+                .flat_map(|(_, deltas, _, _)| deltas.iter().cloned())
+                .collect();
+            let references: Vec<Reference> = batches
+                .iter()
+                .flat_map(|(_, _, references, _)| references.iter().cloned())
+                .collect();
+            let expected: Vec<ExpectedRef> = batches
+                .iter()
+                .flat_map(|(_, _, _, expected)| expected.iter().cloned())
+                .collect();
+
+            let object_count = objects.len() + deltas.len();
+            if object_count == 0 && references.is_empty() {
                 print_user!("no changes to push");
                 return Ok(());
             }
+            if self.dry_run.get() {
+                return self.report_push_cost(objects, deltas, references).await;
+            }
             print_user!(
+                event: "push_start",
+                objects = object_count,
+                references = references.len();
                 "pushing {} object{} and {} reference{}",
-                objects.len(),
-                if objects.len() == 1 { "" } else { "s" },
+                object_count,
+                if object_count == 1 { "" } else { "s" },
                 references.len(),
                 if references.len() == 1 { "" } else { "s" },
             );
-            debug!("objects: {:?}, references: {:?}", objects, references);
-            self.executor
-                .push(objects.into_iter().collect(), references)
+            debug!(
+                "objects: {:?}, deltas: {:?}, references: {:?}",
+                objects, deltas, references
+            );
+            match self
+                .executor
+                .estimate_push_cost(objects.clone(), deltas.clone(), references.clone())
                 .await
+            {
+                Ok(cost) => {
+                    self.check_balance(cost).await?;
+                    let receipt = self
+                        .executor
+                        .push(objects, deltas, references, expected)
+                        .await?;
+                    report_push(&receipt);
+                    return Ok(());
+                }
+                Err(e) if self.atomic.get() || batches.len() == 1 || !is_too_large(&e) => {
+                    return Err(e);
+                }
+                Err(e) => {
+                    print_user!(
+                        "can't push everything in one transaction ({}), pushing references one by one",
+                        e
+                    );
+                }
+            }
+
+            let mut receipt = PushReceipt::default();
+            let total = batches.len();
+            for (pushed, (objects, deltas, references, expected)) in batches.into_iter().enumerate()
+            {
+                for reference in &references {
+                    print_user!("pushing {}", reference);
+                }
+                let result = async {
+                    let cost = self
+                        .executor
+                        .estimate_push_cost(objects.clone(), deltas.clone(), references.clone())
+                        .await?;
+                    self.check_balance(cost).await?;
+                    self.executor
+                        .push(objects, deltas, references, expected)
+                        .await
+                }
+                .await;
+                match result {
+                    Ok(batch_receipt) => receipt.merge(batch_receipt),
+                    // Confirmed batches are on-chain, a retry finds their refs up to date and their objects listed
+                    Err(e) if pushed > 0 => {
+                        report_push(&receipt);
+                        print_user!(
+                            event: "push_failed",
+                            pushed = pushed,
+                            total = total;
+                            "pushed {} of {} batches, pushing again resumes from there",
+                            pushed,
+                            total
+                        );
+                        return Err(e);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            report_push(&receipt);
+            Ok(())
         })
     }
+
+    fn export(&self, stream: ExportStream) -> Result<(), RemoteHelperError> {
+        self.ensure_writable()?;
+        let names = stream.references();
+        if names.is_empty() {
+            print_user!("nothing to export");
+            return Ok(());
+        }
+
+        let private_name = |name: &str| {
+            format!(
+                "{}{}",
+                EXPORT_IMPORT_PREFIX,
+                name.trim_start_matches("refs/")
+            )
+        };
+        self.git.fast_import(stream.to_bytes(&private_name))?;
+
+        let pushes: Vec<Push> = names
+            .into_iter()
+            .map(|name| Push::new(private_name(&name), name, false))
+            .collect();
+        let result = self.push(pushes.clone());
+        for push in pushes {
+            self.git.delete_reference(&push.local)?;
+        }
+        result
+    }
+
+    fn set_option(&self, name: &str, value: &str) -> Result<bool, RemoteHelperError> {
+        // The format is always listed, git only asks for it so it knows to read it
+        if name == "object-format" {
+            return match value {
+                "true" => Ok(true),
+                _ => Err(RemoteHelperError::Invalid {
+                    what: name.to_string(),
+                    value: value.to_string(),
+                }),
+            };
+        }
+        // Only "false" can be honored, the contract has no way to verify a push certificate
+        if name == "pushcert" {
+            return Ok(value == "false");
+        }
+        let option = match name {
+            "dry-run" => &self.dry_run,
+            "atomic" => &self.atomic,
+            _ => return Ok(false),
+        };
+        let enabled = match value {
+            "true" => true,
+            "false" => false,
+            _ => {
+                return Err(RemoteHelperError::Invalid {
+                    what: name.to_string(),
+                    value: value.to_string(),
+                });
+            }
+        };
+        debug!("option {}: {}", name, enabled);
+        option.set(enabled);
+        Ok(true)
+    }
+
+    fn set_push_options(&self, options: Vec<String>) {
+        *self.push_options.borrow_mut() = options;
+    }
+
+    fn check(&self) -> Result<(), RemoteHelperError> {
+        self.block_on(self.executor.check())?;
+
+        let refs = self.block_on(self.executor.list())?;
+        let object_format = object_format(&refs)?;
+        let ref_count = refs
+            .iter()
+            .filter(|reference| matches!(reference, Reference::Normal { .. }))
+            .count();
+
+        print_user!(
+            event: "check",
+            object_format = object_format,
+            references = ref_count;
+            "remote is set up correctly, object format: {}, {} reference{}",
+            object_format,
+            ref_count,
+            if ref_count == 1 { "" } else { "s" }
+        );
+        Ok(())
+    }
+}
+
+fn format_gwei(wei: U256) -> Result<String, RemoteHelperError> {
+    format_units(wei, "gwei").map_err(|e| RemoteHelperError::Failure {
+        action: "formatting cost".to_string(),
+        details: Some(e.to_string()),
+    })
+}
+
+#[cfg(test)]
+fn expect_object_format(executor: &mut MockExecutor, git: &mut MockGit, is_sha256: bool) {
+    executor.expect_list().returning(|| {
+        Ok(vec![Reference::KeyValue {
+            key: Keys::ObjectFormat,
+            value: "sha256".to_string(),
+        }])
+    });
+    git.expect_is_sha256().returning(move || Ok(is_sha256));
+}
+
+// For tests with a real Git implementation, only the remote needs to report its format
+#[cfg(test)]
+fn expect_object_format_remote(executor: &mut MockExecutor) {
+    executor.expect_list().returning(|| {
+        Ok(vec![Reference::KeyValue {
+            key: Keys::ObjectFormat,
+            value: "sha256".to_string(),
+        }])
+    });
+}
+
+#[cfg(test)]
+fn expect_sufficient_balance(executor: &mut MockExecutor) {
+    executor
+        .expect_estimate_push_cost()
+        .returning(|_, _, _| Ok(U256::from(1)));
+    executor.expect_balance().returning(|| Ok(U256::from(2)));
 }
 
 #[test]
@@ -155,9 +1184,51 @@ fn test_capabilities() {
         runtime,
         Box::new(MockExecutor::new()),
         Rc::new(MockGit::new()),
+        Settings::default(),
+    )
+    .expect("should be set");
+    assert_eq!(
+        evm.capabilities(),
+        vec![
+            "*fetch",
+            "*push",
+            REFSPEC_HEADS,
+            REFSPEC_TAGS,
+            REFSPEC_NOTES,
+            REFSPEC_REPLACE,
+            "option",
+            "object-format",
+            "delete-refs",
+        ]
+    );
+}
+
+#[test]
+fn test_capabilities_export() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+    let evm = Evm::new(
+        runtime,
+        Box::new(MockExecutor::new()),
+        Rc::new(MockGit::new()),
+        Settings {
+            use_export: true,
+            ..Settings::default()
+        },
     )
     .expect("should be set");
-    assert_eq!(evm.capabilities(), vec!["*fetch", "*push"]);
+    assert_eq!(
+        evm.capabilities(),
+        vec![
+            "*fetch",
+            "*export",
+            "refspec refs/*:refs/gitdem/exported/*",
+            "option",
+            "object-format"
+        ]
+    );
 }
 
 #[test]
@@ -168,78 +1239,383 @@ fn test_list_empty() {
         .expect("failed to build runtime");
     let mut executor = Box::new(MockExecutor::new());
     executor.expect_list().returning(|| Ok(vec![]));
-    let evm = Evm::new(runtime, executor, Rc::new(MockGit::new())).expect("should be set");
+    executor
+        .expect_default_branch()
+        .returning(|| Ok("refs/heads/main".to_string()));
+    // Without refs or an object format on the remote, the local one is used
+    let mut git = MockGit::new();
+    git.expect_is_sha256().times(1).returning(|| Ok(false));
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     let refs = evm.list(false).expect("should be set");
-    assert_eq!(refs.len(), 0);
+    assert_eq!(
+        refs,
+        vec![
+            Reference::KeyValue {
+                key: Keys::ObjectFormat,
+                value: "sha1".to_string(),
+            },
+            Reference::KeyValue {
+                key: Keys::Unborn,
+                value: "refs/heads/main".to_string(),
+            }
+        ]
+    );
 }
 
 #[test]
-fn test_list_normal() {
+fn test_list_empty_repository() {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("failed to build runtime");
-    let data = b"1234567890";
     let refs = vec![
-        Reference::Normal {
-            name: "refs/heads/main".to_string(),
-            hash: Hash::from_data(data, true).expect("should be set"),
-        },
         Reference::Symbolic {
             name: "HEAD".to_string(),
             target: "refs/heads/main".to_string(),
         },
+        Reference::KeyValue {
+            key: Keys::ObjectFormat,
+            value: "sha1".to_string(),
+        },
     ];
     let mut executor = Box::new(MockExecutor::new());
     let refs_clone = refs.clone();
     executor
         .expect_list()
         .returning(move || Ok(refs_clone.clone()));
-    let evm = Evm::new(runtime, executor, Rc::new(MockGit::new())).expect("should be set");
-    let returned_refs = evm.list(true).expect("should be set");
-    assert_eq!(refs, returned_refs);
+    let evm = Evm::new(
+        runtime,
+        executor,
+        Rc::new(MockGit::new()),
+        Settings::default(),
+    )
+    .expect("should be set");
+    // The object format comes first even though the contract lists it last
+    let returned_refs = evm.list(false).expect("should be set");
+    assert_eq!(
+        returned_refs,
+        vec![
+            Reference::KeyValue {
+                key: Keys::ObjectFormat,
+                value: "sha1".to_string(),
+            },
+            Reference::KeyValue {
+                key: Keys::Unborn,
+                value: "refs/heads/main".to_string(),
+            },
+        ]
+    );
 }
 
 #[test]
-fn test_list_failure() {
+fn test_list_empty_repository_without_head() {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("failed to build runtime");
-
+    let object_format = Reference::KeyValue {
+        key: Keys::ObjectFormat,
+        value: "sha256".to_string(),
+    };
     let mut executor = Box::new(MockExecutor::new());
-    executor.expect_list().returning(|| {
-        Err(RemoteHelperError::Failure {
-            action: "list".to_string(),
-            details: Some("object".to_string()),
-        })
-    });
-    let evm = Evm::new(runtime, executor, Rc::new(MockGit::new())).expect("should be set");
-    evm.list(true).expect_err("should fail");
+    let object_format_clone = object_format.clone();
+    executor
+        .expect_list()
+        .returning(move || Ok(vec![object_format_clone.clone()]));
+    executor
+        .expect_default_branch()
+        .times(1)
+        .returning(|| Ok("refs/heads/trunk".to_string()));
+    let evm = Evm::new(
+        runtime,
+        executor,
+        Rc::new(MockGit::new()),
+        Settings::default(),
+    )
+    .expect("should be set");
+    assert_eq!(
+        evm.list(false).expect("should be set"),
+        vec![
+            object_format,
+            Reference::KeyValue {
+                key: Keys::Unborn,
+                value: "refs/heads/trunk".to_string(),
+            },
+        ]
+    );
 }
 
 #[test]
-fn test_fetch_one() {
+fn test_list_empty_repository_default_branch() {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("failed to build runtime");
-
+    let object_format = Reference::KeyValue {
+        key: Keys::ObjectFormat,
+        value: "sha1".to_string(),
+    };
+    let refs = vec![
+        Reference::Symbolic {
+            name: "HEAD".to_string(),
+            target: "refs/heads/main".to_string(),
+        },
+        object_format.clone(),
+    ];
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_list().returning(move || Ok(refs.clone()));
+    executor.expect_default_branch().never();
+    let evm = Evm::new(
+        runtime,
+        executor,
+        Rc::new(MockGit::new()),
+        Settings {
+            default_branch: Some("refs/heads/master".to_string()),
+            ..Settings::default()
+        },
+    )
+    .expect("should be set");
+    assert_eq!(
+        evm.list(false).expect("should be set"),
+        vec![
+            object_format,
+            Reference::KeyValue {
+                key: Keys::Unborn,
+                value: "refs/heads/master".to_string(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_list_normal() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+    let data = b"1234567890";
+    let refs = vec![
+        Reference::Normal {
+            name: "refs/heads/main".to_string(),
+            hash: Hash::from_data(data, true).expect("should be set"),
+        },
+        Reference::Symbolic {
+            name: "HEAD".to_string(),
+            target: "refs/heads/main".to_string(),
+        },
+    ];
+    let mut executor = Box::new(MockExecutor::new());
+    let refs_clone = refs.clone();
+    executor
+        .expect_list()
+        .returning(move || Ok(refs_clone.clone()));
+    let evm = Evm::new(
+        runtime,
+        executor,
+        Rc::new(MockGit::new()),
+        Settings::default(),
+    )
+    .expect("should be set");
+    // No object format on the remote, it's inferred from the hashes without asking git
+    let mut expected = vec![Reference::KeyValue {
+        key: Keys::ObjectFormat,
+        value: "sha256".to_string(),
+    }];
+    expected.extend(refs);
+    let returned_refs = evm.list(false).expect("should be set");
+    assert_eq!(returned_refs, expected);
+}
+
+#[test]
+fn test_list_tags() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+    let commit = commit_objects(None, &[b"file"]).remove(0);
+    let tag = |name: &str| {
+        Object::new(
+            ObjectKind::Tag,
+            format!(
+                "object {}\ntype commit\ntag {}\ntagger A <a@example.com> 0 +0000\n\n{}\n",
+                commit.get_hash(),
+                name,
+                name
+            )
+            .into_bytes(),
+            true,
+        )
+        .expect("should be set")
+    };
+    let (local_tag, cached_tag, remote_tag) = (tag("v2.0.0"), tag("v3.0.0"), tag("v4.0.0"));
+    let refs = vec![
+        Reference::Normal {
+            name: "refs/heads/main".to_string(),
+            hash: commit.get_hash().clone(),
+        },
+        Reference::Normal {
+            name: "refs/tags/v1.0.0".to_string(),
+            hash: commit.get_hash().clone(),
+        },
+        Reference::Normal {
+            name: "refs/tags/v2.0.0".to_string(),
+            hash: local_tag.get_hash().clone(),
+        },
+        Reference::Normal {
+            name: "refs/tags/v3.0.0".to_string(),
+            hash: cached_tag.get_hash().clone(),
+        },
+        Reference::Normal {
+            name: "refs/tags/v4.0.0".to_string(),
+            hash: remote_tag.get_hash().clone(),
+        },
+    ];
+    // Nothing is fetched, a tag only on the remote is listed without its target
+    let mut executor = Box::new(MockExecutor::new());
+    let refs_clone = refs.clone();
+    executor
+        .expect_list()
+        .returning(move || Ok(refs_clone.clone()));
+    executor.expect_fetch().never();
+    let git = InMemoryGit::new(true).with_objects(vec![commit.clone(), local_tag.clone()]);
+    let cache_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let cache = ObjectCache::new(cache_dir.path(), [0u8; 20]);
+    cache.put(&cached_tag).expect("should succeed");
+    let settings = Settings {
+        object_cache: Some(cache),
+        ..Settings::default()
+    };
+    let evm = Evm::new(runtime, executor, Rc::new(git), settings).expect("should be set");
+
+    // Tags are mapped onto themselves, same as branches
+    assert!(evm.capabilities().contains(&REFSPEC_TAGS));
+    let expected = vec![
+        Reference::KeyValue {
+            key: Keys::ObjectFormat,
+            value: "sha256".to_string(),
+        },
+        refs[0].clone(),
+        refs[1].clone(),
+        Reference::Peeled {
+            name: "refs/tags/v2.0.0".to_string(),
+            tag: local_tag.get_hash().clone(),
+            object: commit.get_hash().clone(),
+        },
+        Reference::Peeled {
+            name: "refs/tags/v3.0.0".to_string(),
+            tag: cached_tag.get_hash().clone(),
+            object: commit.get_hash().clone(),
+        },
+        refs[4].clone(),
+    ];
+    assert_eq!(evm.list(false).expect("should be set"), expected);
+}
+
+#[test]
+fn test_list_for_push() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+    let data = b"1234567890";
+    let normal = Reference::Normal {
+        name: "refs/heads/main".to_string(),
+        hash: Hash::from_data(data, true).expect("should be set"),
+    };
+    let object_format = Reference::KeyValue {
+        key: Keys::ObjectFormat,
+        value: "sha256".to_string(),
+    };
+    let refs = vec![
+        normal.clone(),
+        Reference::Symbolic {
+            name: "HEAD".to_string(),
+            target: "refs/heads/main".to_string(),
+        },
+        object_format.clone(),
+    ];
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_list().returning(move || Ok(refs.clone()));
+    let mut git = MockGit::new();
+    git.expect_is_sha256().never();
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    let returned_refs = evm.list(true).expect("should be set");
+    assert_eq!(returned_refs, vec![object_format, normal]);
+}
+
+#[test]
+fn test_list_for_push_empty() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_list().returning(|| Ok(vec![]));
+    let mut git = MockGit::new();
+    git.expect_is_sha256().returning(|| Ok(false));
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    let returned_refs = evm.list(true).expect("should be set");
+    assert_eq!(
+        returned_refs,
+        vec![Reference::KeyValue {
+            key: Keys::ObjectFormat,
+            value: "sha1".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn test_list_failure() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_list().returning(|| {
+        Err(RemoteHelperError::Failure {
+            action: "list".to_string(),
+            details: Some("object".to_string()),
+        })
+    });
+    let evm = Evm::new(
+        runtime,
+        executor,
+        Rc::new(MockGit::new()),
+        Settings::default(),
+    )
+    .expect("should be set");
+    evm.list(true).expect_err("should fail");
+}
+
+#[test]
+fn test_fetch_one() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
     let mut executor = Box::new(MockExecutor::new());
     let object = Object::new(ObjectKind::Blob, b"1234567890".to_vec(), true)
         .expect("failed to create object");
     let object_clone = object.clone();
     executor
         .expect_fetch()
-        .returning(move |_| Ok(object_clone.clone()));
+        .returning(move |_| Ok(Fetched::Object(object_clone.clone())));
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
     git.expect_list_all_objects().returning(|| Ok(vec![]));
+    git.expect_has_object().returning(|_| Ok(false));
     git.expect_save_object()
         .with(eq(object.clone()))
         .returning(|_| Ok(()));
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     evm.fetch(vec![Fetch {
         hash: object.get_hash().clone(),
         name: "refs/heads/main".to_string(),
@@ -268,29 +1644,167 @@ fn test_fetch_multiple() {
     executor
         .expect_fetch()
         .with(eq(object_blob_clone.get_hash().clone()))
-        .returning(move |_| Ok(object_blob_clone.clone()));
+        .returning(move |_| Ok(Fetched::Object(object_blob_clone.clone())));
     executor
         .expect_fetch()
         .with(eq(object_tree_clone.get_hash().clone()))
-        .returning(move |_| Ok(object_tree_clone.clone()));
+        .returning(move |_| Ok(Fetched::Object(object_tree_clone.clone())));
 
-    let mut git = MockGit::new();
-    let object_tree_clone = object_tree.clone();
-    git.expect_list_all_objects().returning(|| Ok(vec![]));
-    git.expect_save_object()
-        .with(eq(object_tree_clone.clone()))
-        .returning(|_| Ok(()));
-    let object_blob_clone = object_blob.clone();
-    git.expect_save_object()
-        .with(eq(object_blob_clone.clone()))
-        .returning(|_| Ok(()));
+    executor.expect_list().returning(|| {
+        Ok(vec![Reference::KeyValue {
+            key: Keys::ObjectFormat,
+            value: "sha256".to_string(),
+        }])
+    });
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let git = Rc::new(RecordingGit::new(true));
+    let evm = Evm::new(runtime, executor, git.clone(), Settings::default()).expect("should be set");
     evm.fetch(vec![Fetch {
         hash: object_tree.get_hash().clone(),
         name: "refs/heads/main".to_string(),
     }])
     .expect("should succeed");
+    assert_eq!(git.saved_objects(), vec![object_tree, object_blob]);
+}
+
+#[test]
+fn test_fetch_partial_resume() {
+    let blob_one =
+        Object::new(ObjectKind::Blob, b"one".to_vec(), true).expect("failed to create object");
+    let blob_two =
+        Object::new(ObjectKind::Blob, b"two".to_vec(), true).expect("failed to create object");
+    let mut tree_data = b"100644 one\0".to_vec();
+    tree_data.extend(hex::decode(blob_one.get_hash().to_string()).expect("should succeed"));
+    tree_data.extend(b"100644 two\0");
+    tree_data.extend(hex::decode(blob_two.get_hash().to_string()).expect("should succeed"));
+    let tree = Object::new(ObjectKind::Tree, tree_data, true).expect("failed to create object");
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_list().returning(|| {
+        Ok(vec![Reference::KeyValue {
+            key: Keys::ObjectFormat,
+            value: "sha256".to_string(),
+        }])
+    });
+    for object in [tree.clone(), blob_two.clone()] {
+        executor
+            .expect_fetch()
+            .with(eq(object.get_hash().clone()))
+            .returning(move |_| Ok(Fetched::Object(object.clone())));
+    }
+    // The first attempt fails on the blob fetched last, the retry succeeds
+    executor
+        .expect_fetch()
+        .with(eq(blob_one.get_hash().clone()))
+        .times(1)
+        .returning(|_| {
+            Err(RemoteHelperError::Failure {
+                action: "fetching object".to_string(),
+                details: None,
+            })
+        });
+    let blob_one_clone = blob_one.clone();
+    executor
+        .expect_fetch()
+        .with(eq(blob_one.get_hash().clone()))
+        .times(1)
+        .returning(move |_| Ok(Fetched::Object(blob_one_clone.clone())));
+
+    let git = Rc::new(RecordingGit::new(true));
+    let evm = Evm::new(runtime, executor, git.clone(), Settings::default()).expect("should be set");
+    let fetches = vec![Fetch {
+        hash: tree.get_hash().clone(),
+        name: "refs/heads/main".to_string(),
+    }];
+
+    evm.fetch(fetches.clone()).expect_err("should fail");
+    // The tree points at the missing blob, so it has to be fetched again
+    assert_eq!(git.saved_objects(), vec![blob_two.clone()]);
+
+    evm.fetch(fetches).expect("should succeed");
+    assert_eq!(git.saved_objects(), vec![blob_two, tree, blob_one]);
+}
+
+#[test]
+fn test_push_stale_lease() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+    let expected = Hash::from_data(b"expected", true).expect("should be set");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_resolve_references().returning(|_| {
+        Ok(vec![Some(
+            Hash::from_data(b"moved", true).expect("should be set"),
+        )])
+    });
+    executor.expect_list_all_objects().never();
+    executor.expect_push().never();
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    git.expect_resolve_reference()
+        .returning(|_| Ok(Hash::from_data(b"local", true).expect("should be set")));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    let mut push = Push::new(
+        "refs/heads/main".to_string(),
+        "refs/heads/main".to_string(),
+        true,
+    );
+    push.expected_remote = Some(expected.clone());
+    let err = evm.push(vec![push]).expect_err("should fail");
+    assert_eq!(
+        err,
+        RemoteHelperError::NonFastForward {
+            name: "refs/heads/main".to_string(),
+            expected: expected.to_string(),
+            actual: Hash::from_data(b"moved", true)
+                .expect("should be set")
+                .to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_check_lease() {
+    let hash = Hash::from_data(b"hash", true).expect("should be set");
+    let zero = Hash::from_str(&"0".repeat(40)).expect("should be set");
+    assert!(zero.is_zero());
+    assert!(!hash.is_zero());
+
+    check_lease("refs/heads/main", &hash, Some(&hash)).expect("should match");
+    check_lease("refs/heads/main", &zero, None).expect("should match a missing reference");
+    check_lease("refs/heads/main", &hash, None).expect_err("should fail for a missing reference");
+    check_lease("refs/heads/main", &zero, Some(&hash))
+        .expect_err("should fail for an existing reference");
+}
+
+#[test]
+fn test_complete_objects() {
+    let blob = Object::new(ObjectKind::Blob, b"blob".to_vec(), true).expect("should be set");
+    let missing = Hash::from_data(b"missing", true).expect("should be set");
+    let mut tree_data = b"100644 blob\0".to_vec();
+    tree_data.extend(hex::decode(blob.get_hash().to_string()).expect("should succeed"));
+    let tree = Object::new(ObjectKind::Tree, tree_data.clone(), true).expect("should be set");
+    tree_data.extend(b"100644 missing\0");
+    tree_data.extend(hex::decode(missing.to_string()).expect("should succeed"));
+    let incomplete_tree = Object::new(ObjectKind::Tree, tree_data, true).expect("should be set");
+
+    let objects = vec![tree.clone(), incomplete_tree.clone(), blob.clone()];
+    assert_eq!(
+        complete_objects(objects.clone(), &[]),
+        vec![tree.clone(), blob.clone()]
+    );
+    assert_eq!(complete_objects(objects.clone(), &[missing]), objects);
 }
 
 #[test]
@@ -302,14 +1816,16 @@ fn test_fetch_already_exists() {
 
     let hash = Hash::from_data(b"1234567890", true).expect("should be set");
 
-    let executor = Box::new(MockExecutor::new());
+    let mut executor = Box::new(MockExecutor::new());
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
     let hash_clone = hash.clone();
     git.expect_list_all_objects()
         .returning(move || Ok(vec![hash_clone.clone()]));
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     evm.fetch(vec![Fetch {
         hash,
         name: "refs/heads/main".to_string(),
@@ -317,6 +1833,47 @@ fn test_fetch_already_exists() {
     .expect("should succeed");
 }
 
+#[test]
+fn test_fetch_present_unreachable() {
+    let blob = Object::new(ObjectKind::Blob, b"blob".to_vec(), true).expect("should be set");
+    let mut tree_data = b"100644 blob\0".to_vec();
+    tree_data.extend(hex::decode(blob.get_hash().to_string()).expect("should succeed"));
+    let tree = Object::new(ObjectKind::Tree, tree_data, true).expect("should be set");
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    // Only the tree is fetched, the blob is in the object store without a reference to it
+    let mut executor = Box::new(MockExecutor::new());
+    let tree_clone = tree.clone();
+    executor
+        .expect_fetch()
+        .with(eq(tree.get_hash().clone()))
+        .times(1)
+        .returning(move |_| Ok(Fetched::Object(tree_clone.clone())));
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    git.expect_list_all_objects().returning(|| Ok(vec![]));
+    let blob_hash = blob.get_hash().clone();
+    git.expect_has_object()
+        .returning(move |hash| Ok(hash == &blob_hash));
+    git.expect_save_object()
+        .with(eq(tree.clone()))
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.fetch(vec![Fetch {
+        hash: tree.get_hash().clone(),
+        name: "refs/heads/main".to_string(),
+    }])
+    .expect("should succeed");
+}
+
 #[test]
 fn test_fetch_missing() {
     let runtime = tokio::runtime::Builder::new_current_thread()
@@ -332,9 +1889,12 @@ fn test_fetch_missing() {
     });
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
     git.expect_list_all_objects().returning(|| Ok(vec![]));
+    git.expect_has_object().returning(|_| Ok(false));
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     let hash = Hash::from_data(b"1234567890", true).expect("should be set");
     evm.fetch(vec![Fetch {
         hash,
@@ -359,9 +1919,12 @@ fn test_fetch_failure() {
     });
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
     git.expect_list_all_objects().returning(|| Ok(vec![]));
+    git.expect_has_object().returning(|_| Ok(false));
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     evm.fetch(vec![Fetch {
         hash: Hash::from_data(b"1234567890", true).expect("should be set"),
         name: "refs/heads/main".to_string(),
@@ -382,10 +1945,12 @@ fn test_fetch_save_failure() {
     let object_clone = object.clone();
     executor
         .expect_fetch()
-        .returning(move |_| Ok(object_clone.clone()));
+        .returning(move |_| Ok(Fetched::Object(object_clone.clone())));
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
     git.expect_list_all_objects().returning(|| Ok(vec![]));
+    git.expect_has_object().returning(|_| Ok(false));
     git.expect_save_object()
         .with(eq(object.clone()))
         .returning(|_| {
@@ -395,7 +1960,8 @@ fn test_fetch_save_failure() {
             })
         });
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     evm.fetch(vec![Fetch {
         hash: object.get_hash().clone(),
         name: "refs/heads/main".to_string(),
@@ -413,6 +1979,7 @@ fn test_push_empty() {
         runtime,
         Box::new(MockExecutor::new()),
         Rc::new(MockGit::new()),
+        Settings::default(),
     )
     .expect("should be set");
     evm.push(vec![]).expect("should succeed");
@@ -430,19 +1997,23 @@ fn test_push_up_to_date() {
     let hash_clone = hash.clone();
     executor
         .expect_resolve_references()
-        .returning(move |_| Ok(vec![hash_clone.clone()]));
+        .returning(move |_| Ok(vec![Some(hash_clone.clone())]));
     executor.expect_list_all_objects().returning(|| Ok(vec![]));
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
     git.expect_resolve_reference()
         .with(eq("refs/heads/main".to_string()))
         .returning(move |_| Ok(hash.clone()));
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     evm.push(vec![Push {
         local: "refs/heads/main".to_string(),
         remote: "refs/heads/main".to_string(),
         is_force: false,
+        expected_remote: None,
     }])
     .expect("should succeed");
 }
@@ -459,9 +2030,9 @@ fn test_push_no_new_objects() {
 
     let mut executor = Box::new(MockExecutor::new());
     executor.expect_resolve_references().returning(move |_| {
-        Ok(vec![
+        Ok(vec![Some(
             Hash::from_data(b"ref_one", true).expect("should be set"),
-        ])
+        )])
     });
     let object_hash_clone = object_hash.clone();
     executor
@@ -470,277 +2041,1832 @@ fn test_push_no_new_objects() {
     executor
         .expect_push()
         .with(
+            eq(vec![]),
             eq(vec![]),
             eq(vec![Reference::Normal {
                 name: "refs/heads/main".to_string(),
                 hash: new_ref_hash.clone(),
             }]),
+            always(),
         )
-        .returning(move |_, _| Ok(()));
+        .returning(move |_, _, _, _| Ok(PushReceipt::default()));
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    expect_sufficient_balance(&mut executor);
     git.expect_resolve_reference()
         .returning(move |_| Ok(new_ref_hash.clone()));
     git.expect_list_objects()
         .returning(move |_| Ok(vec![object_hash.clone()]));
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     evm.push(vec![Push {
         local: "refs/heads/main".to_string(),
         remote: "refs/heads/main".to_string(),
         is_force: false,
+        expected_remote: None,
     }])
     .expect("should succeed");
 }
 
 #[test]
-fn test_push_new_object() {
+fn test_push_new_reference() {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("failed to build runtime");
 
-    let object =
-        Object::new(ObjectKind::Blob, b"object_data".to_vec(), true).expect("should be set");
-    let new_ref_hash = Hash::from_data(b"ref_two", true).expect("should be set");
+    // main is already on-chain, feature builds on it and shares a file
+    let main = commit_objects(None, &[b"shared"]);
+    let feature = commit_objects(Some(main[0].get_hash()), &[b"shared", b"new"]);
+    let feature_hash = feature[0].get_hash().clone();
+    let git = InMemoryGit::new(true)
+        .with_objects(main.iter().chain(feature.iter()).cloned().collect())
+        .with_reference("refs/heads/feature", feature_hash.clone());
 
     let mut executor = Box::new(MockExecutor::new());
-    executor.expect_resolve_references().returning(move |_| {
-        Ok(vec![
-            Hash::from_data(b"ref_one", true).expect("should be set"),
-        ])
-    });
+    expect_object_format_remote(&mut executor);
+    executor
+        .expect_resolve_references()
+        .with(eq(vec!["refs/heads/feature".to_string()]))
+        .returning(|_| Ok(vec![None]));
+    let pushed: Vec<Hash> = main.iter().map(|o| o.get_hash().clone()).collect();
     executor
         .expect_list_all_objects()
-        .returning(move || Ok(vec![]));
-    let object_clone = object.clone();
+        .returning(move || Ok(pushed.clone()));
+    let new_blob = feature[3].clone();
     executor
         .expect_push()
         .with(
-            eq(vec![object_clone]),
+            // Uploaded in dependency order, the blob, then its tree, then the commit
+            eq(vec![new_blob, feature[1].clone(), feature[0].clone()]),
+            eq(vec![]),
             eq(vec![Reference::Normal {
-                name: "refs/heads/main".to_string(),
-                hash: new_ref_hash.clone(),
+                name: "refs/heads/feature".to_string(),
+                hash: feature_hash,
             }]),
+            eq(vec![("refs/heads/feature".to_string(), None)]),
         )
-        .returning(move |_, _| Ok(()));
-
-    let mut git = MockGit::new();
-    git.expect_resolve_reference()
-        .returning(move |_| Ok(new_ref_hash.clone()));
-    let object_hash = object.get_hash().clone();
-    git.expect_list_objects()
-        .returning(move |_| Ok(vec![object_hash.clone()]));
-    let object_hash = object.get_hash().clone();
-    git.expect_get_object()
-        .with(eq(object_hash.clone()))
-        .returning(move |_| Ok(object.clone()));
-
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
-    evm.push(vec![Push {
-        local: "refs/heads/main".to_string(),
-        remote: "refs/heads/main".to_string(),
-        is_force: false,
-    }])
+        .times(1)
+        .returning(|_, _, _, _| Ok(PushReceipt::default()));
+    executor.expect_signer_address().return_const([0u8; 20]);
+    expect_sufficient_balance(&mut executor);
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![Push::new(
+        "refs/heads/feature".to_string(),
+        "refs/heads/feature".to_string(),
+        false,
+    )])
     .expect("should succeed");
 }
 
 #[test]
-fn test_push_resolve_local_reference_failure() {
+fn test_push_batches_in_dependency_order() {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("failed to build runtime");
 
-    let mut git = MockGit::new();
-    git.expect_resolve_reference().returning(move |_| {
-        Err(RemoteHelperError::Failure {
-            action: "resolve references".to_string(),
-            details: Some("object".to_string()),
-        })
-    });
+    // dev builds on main, both are new and have to be pushed one reference at a time
+    let main = commit_objects(None, &[b"main"]);
+    let dev = commit_objects(Some(main[0].get_hash()), &[b"main", b"dev"]);
+    let git = InMemoryGit::new(true)
+        .with_objects(main.iter().chain(dev.iter()).cloned().collect())
+        .with_reference("refs/heads/main", main[0].get_hash().clone())
+        .with_reference("refs/heads/dev", dev[0].get_hash().clone());
 
-    let evm = Evm::new(runtime, Box::new(MockExecutor::new()), Rc::new(git)).expect("should be set");
+    let mut executor = Box::new(MockExecutor::new());
+    expect_object_format_remote(&mut executor);
+    executor
+        .expect_resolve_references()
+        .returning(|_| Ok(vec![None, None]));
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor
+        .expect_estimate_push_cost()
+        .returning(|_, _, references| match references.len() {
+            1 => Ok(U256::from(1)),
+            _ => Err(RemoteHelperError::Failure {
+                action: "estimating gas".to_string(),
+                details: Some("exceeds block gas limit".to_string()),
+            }),
+        });
+    executor.expect_balance().returning(|| Ok(U256::from(2)));
+    // Every object an uploaded one points at is either in an earlier position or already sent
+    let main_hashes: Vec<Hash> = main.iter().map(|o| o.get_hash().clone()).collect();
+    executor
+        .expect_push()
+        .withf(move |objects, _, _, _| {
+            objects.iter().enumerate().all(|(i, object)| {
+                object.get_related().iter().all(|hash| {
+                    objects[..i].iter().any(|o| o.get_hash() == hash) || main_hashes.contains(hash)
+                })
+            })
+        })
+        .times(2)
+        .returning(|_, _, _, _| Ok(PushReceipt::default()));
+    executor.expect_signer_address().return_const([0u8; 20]);
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![
+        Push::new(
+            "refs/heads/main".to_string(),
+            "refs/heads/main".to_string(),
+            false,
+        ),
+        Push::new(
+            "refs/heads/dev".to_string(),
+            "refs/heads/dev".to_string(),
+            false,
+        ),
+    ])
+    .expect("should succeed");
+}
+
+#[test]
+fn test_push_partial_resume() {
+    // Pushed one reference at a time, dev fails after main is confirmed
+    let main = commit_objects(None, &[b"main"]);
+    let dev = commit_objects(Some(main[0].get_hash()), &[b"main", b"dev"]);
+    let git = Rc::new(
+        InMemoryGit::new(true)
+            .with_objects(main.iter().chain(dev.iter()).cloned().collect())
+            .with_reference("refs/heads/main", main[0].get_hash().clone())
+            .with_reference("refs/heads/dev", dev[0].get_hash().clone()),
+    );
+    let pushes = vec![
+        Push::new(
+            "refs/heads/main".to_string(),
+            "refs/heads/main".to_string(),
+            false,
+        ),
+        Push::new(
+            "refs/heads/dev".to_string(),
+            "refs/heads/dev".to_string(),
+            false,
+        ),
+    ];
+    let runtime = || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build runtime")
+    };
+
+    let mut executor = Box::new(MockExecutor::new());
+    expect_object_format_remote(&mut executor);
+    executor.expect_balance().returning(|| Ok(U256::from(2)));
+    executor
+        .expect_resolve_references()
+        .returning(|_| Ok(vec![None, None]));
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor
+        .expect_estimate_push_cost()
+        .returning(|_, _, references| match references.len() {
+            1 => Ok(U256::from(1)),
+            _ => Err(RemoteHelperError::Failure {
+                action: "estimating gas".to_string(),
+                details: Some("exceeds block gas limit".to_string()),
+            }),
+        });
+    executor
+        .expect_push()
+        .withf(|_, _, references, _| references[0].to_string().contains("refs/heads/main"))
+        .times(1)
+        .returning(|_, _, _, _| Ok(PushReceipt::default()));
+    executor
+        .expect_push()
+        .withf(|_, _, references, _| references[0].to_string().contains("refs/heads/dev"))
+        .times(1)
+        .returning(|_, _, _, _| {
+            Err(RemoteHelperError::Failure {
+                action: "pushing objects and refs".to_string(),
+                details: None,
+            })
+        });
+    executor.expect_signer_address().return_const([0u8; 20]);
+    let evm =
+        Evm::new(runtime(), executor, git.clone(), Settings::default()).expect("should be set");
+    evm.push(pushes.clone()).expect_err("should fail");
+
+    // The retry sees main on-chain and only sends what dev adds on top of it
+    let main_hash = main[0].get_hash().clone();
+    let main_hashes: Vec<Hash> = main.iter().map(|o| o.get_hash().clone()).collect();
+    let dev_objects: Vec<Hash> = dev
+        .iter()
+        .map(|o| o.get_hash().clone())
+        .filter(|hash| !main_hashes.contains(hash))
+        .collect();
+    let mut executor = Box::new(MockExecutor::new());
+    expect_object_format_remote(&mut executor);
+    expect_sufficient_balance(&mut executor);
+    executor
+        .expect_resolve_references()
+        .returning(move |_| Ok(vec![Some(main_hash.clone()), None]));
+    executor
+        .expect_list_all_objects()
+        .returning(move || Ok(main_hashes.clone()));
+    executor
+        .expect_push()
+        .withf(move |objects, _, references, _| {
+            let mut hashes: Vec<Hash> = objects.iter().map(|o| o.get_hash().clone()).collect();
+            hashes.sort_by_key(|hash| hash.to_string());
+            let mut expected = dev_objects.clone();
+            expected.sort_by_key(|hash| hash.to_string());
+            hashes == expected && references.len() == 1
+        })
+        .times(1)
+        .returning(|_, _, _, _| Ok(PushReceipt::default()));
+    executor.expect_signer_address().return_const([0u8; 20]);
+    let evm = Evm::new(runtime(), executor, git, Settings::default()).expect("should be set");
+    evm.push(pushes).expect("should succeed");
+}
+
+#[test]
+fn test_push_estimate_failure_not_split() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let main = commit_objects(None, &[b"main"]);
+    let dev = commit_objects(Some(main[0].get_hash()), &[b"main", b"dev"]);
+    let git = InMemoryGit::new(true)
+        .with_objects(main.iter().chain(dev.iter()).cloned().collect())
+        .with_reference("refs/heads/main", main[0].get_hash().clone())
+        .with_reference("refs/heads/dev", dev[0].get_hash().clone());
+
+    // A transient rpc error isn't fixed by pushing references one by one
+    let mut executor = Box::new(MockExecutor::new());
+    expect_object_format_remote(&mut executor);
+    executor
+        .expect_resolve_references()
+        .returning(|_| Ok(vec![None, None]));
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor
+        .expect_estimate_push_cost()
+        .times(1)
+        .returning(|_, _, _| {
+            Err(RemoteHelperError::Failure {
+                action: "estimating gas".to_string(),
+                details: Some("connection reset by peer".to_string()),
+            })
+        });
+    executor.expect_push().never();
+    executor.expect_signer_address().return_const([0u8; 20]);
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![
+        Push::new(
+            "refs/heads/main".to_string(),
+            "refs/heads/main".to_string(),
+            false,
+        ),
+        Push::new(
+            "refs/heads/dev".to_string(),
+            "refs/heads/dev".to_string(),
+            false,
+        ),
+    ])
+    .expect_err("should fail");
+
+    assert!(is_too_large(&RemoteHelperError::Failure {
+        action: "estimating gas".to_string(),
+        details: Some("Exceeds block gas limit".to_string()),
+    }));
+    assert!(!is_too_large(&RemoteHelperError::Failure {
+        action: "estimating gas".to_string(),
+        details: Some("execution reverted".to_string()),
+    }));
+}
+
+#[test]
+fn test_topological_order() {
+    let first = commit_objects(None, &[b"a", b"b"]);
+    let second = commit_objects(Some(first[0].get_hash()), &[b"c"]);
+    let blob = Object::new(ObjectKind::Blob, b"unrelated".to_vec(), true).expect("should be set");
+    let mut objects = vec![blob.clone()];
+    objects.extend(second.iter().cloned());
+    objects.extend(first.iter().cloned());
+
+    let ordered = topological_order(objects);
+    let hashes: Vec<&Hash> = ordered.iter().map(|o| o.get_hash()).collect();
+    assert_eq!(
+        hashes,
+        vec![
+            blob.get_hash(),
+            second[2].get_hash(),
+            second[1].get_hash(),
+            first[2].get_hash(),
+            first[3].get_hash(),
+            first[1].get_hash(),
+            first[0].get_hash(),
+            second[0].get_hash(),
+        ]
+    );
+}
+
+#[cfg(test)]
+fn delta_history() -> (Vec<Object>, Vec<Object>) {
+    let content: Vec<u8> = (0..100)
+        .flat_map(|i| format!("line {}\n", i).into_bytes())
+        .collect();
+    let mut changed = content.clone();
+    changed.extend_from_slice(b"line 100\n");
+    let main = commit_objects(None, &[&content, b"small"]);
+    let dev = commit_objects(Some(main[0].get_hash()), &[&changed, b"other"]);
+    (main, dev)
+}
+
+#[test]
+fn test_push_delta_objects() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    // main is on-chain, dev appends a line to its first file and replaces the second
+    let (main, dev) = delta_history();
+    let git = InMemoryGit::new(true)
+        .with_objects(main.iter().chain(dev.iter()).cloned().collect())
+        .with_reference("refs/heads/dev", dev[0].get_hash().clone());
+    let delta = DeltaObject::compute(&main[2], &dev[2]).expect("should be worth a delta");
+
+    let mut executor = Box::new(MockExecutor::new());
+    expect_object_format_remote(&mut executor);
+    expect_sufficient_balance(&mut executor);
+    let main_commit = main[0].get_hash().clone();
+    executor
+        .expect_resolve_references()
+        .returning(move |_| Ok(vec![Some(main_commit.clone())]));
+    let main_hashes: Vec<Hash> = main.iter().map(|o| o.get_hash().clone()).collect();
+    executor
+        .expect_list_all_objects()
+        .returning(move || Ok(main_hashes.clone()));
+    let full: HashSet<Hash> = [&dev[0], &dev[1], &dev[3]]
+        .iter()
+        .map(|o| o.get_hash().clone())
+        .collect();
+    executor
+        .expect_push()
+        .withf(move |objects, deltas, _, _| {
+            objects
+                .iter()
+                .map(|o| o.get_hash().clone())
+                .collect::<HashSet<_>>()
+                == full
+                && deltas == &vec![delta.clone()]
+        })
+        .times(1)
+        .returning(|_, _, _, _| Ok(PushReceipt::default()));
+    executor.expect_signer_address().return_const([0u8; 20]);
+
+    let settings = Settings {
+        delta_objects: true,
+        ..Settings::default()
+    };
+    let evm = Evm::new(runtime, executor, Rc::new(git), settings).expect("should be set");
+    evm.push(vec![Push::new(
+        "refs/heads/dev".to_string(),
+        "refs/heads/main".to_string(),
+        false,
+    )])
+    .expect("should succeed");
+}
+
+#[test]
+fn test_fetch_delta_objects() {
+    let (main, dev) = delta_history();
+    let delta = DeltaObject::compute(&main[2], &dev[2]).expect("should be worth a delta");
+
+    // The base is taken from the local repository when it's there, fetched otherwise
+    for has_main in [true, false] {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build runtime");
+        let mut executor = Box::new(MockExecutor::new());
+        expect_object_format_remote(&mut executor);
+        let remote =
+            InMemoryGit::new(true).with_objects(main.iter().chain(dev.iter()).cloned().collect());
+        let delta = delta.clone();
+        executor
+            .expect_fetch()
+            .returning(move |hash| match &hash == delta.get_hash() {
+                true => Ok(Fetched::Delta(delta.clone())),
+                false => remote.get_object(hash).map(Fetched::Object),
+            });
+
+        let git = Rc::new(match has_main {
+            true => InMemoryGit::new(true).with_objects(main.clone()),
+            false => InMemoryGit::new(true),
+        });
+        let evm =
+            Evm::new(runtime, executor, git.clone(), Settings::default()).expect("should be set");
+        evm.fetch(vec![Fetch {
+            hash: dev[0].get_hash().clone(),
+            name: "refs/heads/main".to_string(),
+        }])
+        .expect("should succeed");
+        assert!(dev.iter().all(|o| git.contains(o.get_hash())));
+        assert!(git.contains(main[2].get_hash()));
+    }
+}
+
+#[test]
+fn test_fetch_object_cache() {
+    let main = commit_objects(None, &[b"cached"]);
+    let cache_dir = tempfile::tempdir().expect("failed to create temp dir");
+    let settings = Settings {
+        object_cache: Some(ObjectCache::new(cache_dir.path(), [0u8; 20])),
+        ..Settings::default()
+    };
+    let fetches = vec![Fetch {
+        hash: main[0].get_hash().clone(),
+        name: "refs/heads/main".to_string(),
+    }];
+
+    // The first clone downloads every object, the second one finds them all in the cache
+    for times in [main.len(), 0] {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to build runtime");
+        let mut executor = Box::new(MockExecutor::new());
+        expect_object_format_remote(&mut executor);
+        let remote = InMemoryGit::new(true).with_objects(main.clone());
+        executor
+            .expect_fetch()
+            .times(times)
+            .returning(move |hash| remote.get_object(hash).map(Fetched::Object));
+
+        let git = Rc::new(InMemoryGit::new(true));
+        let evm =
+            Evm::new(runtime, executor, git.clone(), settings.clone()).expect("should be set");
+        evm.fetch(fetches.clone()).expect("should succeed");
+        assert!(main.iter().all(|o| git.contains(o.get_hash())));
+    }
+}
+
+#[test]
+fn test_fetch_commit_graph() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let main = commit_objects(None, &[b"shared"]);
+    let next = commit_objects(Some(main[0].get_hash()), &[b"shared", b"new"]);
+    let next_hash = next[0].get_hash().clone();
+
+    // Only objects missing locally may be requested, each of them once
+    let remote =
+        InMemoryGit::new(true).with_objects(main.iter().chain(next.iter()).cloned().collect());
+    let mut executor = Box::new(MockExecutor::new());
+    expect_object_format_remote(&mut executor);
+    for object in [&next[0], &next[1], &next[3]] {
+        let hash = object.get_hash().clone();
+        let object = remote.get_object(hash.clone()).expect("should be set");
+        executor
+            .expect_fetch()
+            .with(eq(hash))
+            .times(1)
+            .returning(move |_| Ok(Fetched::Object(object.clone())));
+    }
+
+    let git = Rc::new(
+        InMemoryGit::new(true)
+            .with_objects(main.clone())
+            .with_reference("refs/heads/main", main[0].get_hash().clone()),
+    );
+    let evm = Evm::new(runtime, executor, git.clone(), Settings::default()).expect("should be set");
+    evm.fetch(vec![Fetch {
+        hash: next_hash.clone(),
+        name: "refs/heads/main".to_string(),
+    }])
+    .expect("should succeed");
+
+    let hashes = git
+        .list_objects(next_hash)
+        .expect("graph should be complete");
+    assert_eq!(hashes.len(), 6);
+}
+
+#[test]
+fn test_fetch_object_limit() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let objects = commit_objects(None, &[b"one", b"two"]);
+    let commit = objects[0].get_hash().clone();
+    let remote = InMemoryGit::new(true).with_objects(objects);
+    let mut executor = Box::new(MockExecutor::new());
+    expect_object_format_remote(&mut executor);
+    executor
+        .expect_fetch()
+        .times(2)
+        .returning(move |hash| remote.get_object(hash).map(Fetched::Object));
+
+    let git = Rc::new(InMemoryGit::new(true));
+    let settings = Settings {
+        max_fetch_objects: Some(2),
+        ..Settings::default()
+    };
+    let evm = Evm::new(runtime, executor, git.clone(), settings).expect("should be set");
+    let err = evm
+        .fetch(vec![Fetch {
+            hash: commit.clone(),
+            name: "refs/heads/main".to_string(),
+        }])
+        .expect_err("should stop at the limit");
+    assert_eq!(
+        err,
+        RemoteHelperError::Failure {
+            action: "fetching objects".to_string(),
+            details: Some(
+                "more than 2 objects fetched or queued (2 fetched, 1 queued)".to_string()
+            ),
+        }
+    );
+    assert!(!git.contains(&commit));
+}
+
+#[test]
+fn test_fetch_tags_shared_history() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let first = commit_objects(None, &[b"shared"]);
+    let second = commit_objects(Some(first[0].get_hash()), &[b"shared", b"new"]);
+    let tag = |name: &str, target: &Hash| {
+        Object::new(
+            ObjectKind::Tag,
+            format!(
+                "object {}\ntype commit\ntag {}\ntagger A <a@example.com> 0 +0000\n\n{}\n",
+                target, name, name
+            )
+            .into_bytes(),
+            true,
+        )
+        .expect("should be set")
+    };
+    let v1 = tag("v1", first[0].get_hash());
+    let v2 = tag("v2", second[0].get_hash());
+
+    // Both tags reach the first commit, its objects are still requested once for the whole batch
+    let mut all = vec![v1.clone(), v2.clone()];
+    all.extend(first.iter().cloned());
+    all.extend(second.iter().cloned());
+    let unique = all
+        .iter()
+        .map(|object| object.get_hash().clone())
+        .collect::<HashSet<_>>();
+    let mut executor = Box::new(MockExecutor::new());
+    expect_object_format_remote(&mut executor);
+    for hash in &unique {
+        let object = all
+            .iter()
+            .find(|object| object.get_hash() == hash)
+            .expect("should be set")
+            .clone();
+        executor
+            .expect_fetch()
+            .with(eq(hash.clone()))
+            .times(1)
+            .returning(move |_| Ok(Fetched::Object(object.clone())));
+    }
+
+    let git = Rc::new(InMemoryGit::new(true));
+    let evm = Evm::new(runtime, executor, git.clone(), Settings::default()).expect("should be set");
+    evm.fetch(vec![
+        Fetch {
+            hash: v1.get_hash().clone(),
+            name: "refs/tags/v1".to_string(),
+        },
+        Fetch {
+            hash: v2.get_hash().clone(),
+            name: "refs/tags/v2".to_string(),
+        },
+    ])
+    .expect("should succeed");
+
+    assert!(unique.iter().all(|hash| git.contains(hash)));
+}
+
+#[test]
+fn test_push_read_only() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    // Any call to the executor or git would fail the test
+    let evm = Evm::new(
+        runtime,
+        Box::new(MockExecutor::new()),
+        Rc::new(MockGit::new()),
+        Settings {
+            read_only: true,
+            ..Settings::default()
+        },
+    )
+    .expect("should be set");
+    let expected = RemoteHelperError::Failure {
+        action: "push".to_string(),
+        details: Some("remote is read-only".to_string()),
+    };
+    assert_eq!(
+        evm.push(vec![Push::new(
+            "refs/heads/main".to_string(),
+            "refs/heads/main".to_string(),
+            false
+        )])
+        .expect_err("should fail because the remote is read-only"),
+        expected
+    );
+    assert_eq!(
+        evm.export(ExportStream::default())
+            .expect_err("should fail because the remote is read-only"),
+        expected
+    );
+}
+
+#[test]
+fn test_push_new_object() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let object =
+        Object::new(ObjectKind::Blob, b"object_data".to_vec(), true).expect("should be set");
+    let new_ref_hash = Hash::from_data(b"ref_two", true).expect("should be set");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_resolve_references().returning(move |_| {
+        Ok(vec![Some(
+            Hash::from_data(b"ref_one", true).expect("should be set"),
+        )])
+    });
+    executor
+        .expect_list_all_objects()
+        .returning(move || Ok(vec![]));
+    let object_clone = object.clone();
+    executor
+        .expect_push()
+        .with(
+            eq(vec![object_clone]),
+            eq(vec![]),
+            eq(vec![Reference::Normal {
+                name: "refs/heads/main".to_string(),
+                hash: new_ref_hash.clone(),
+            }]),
+            always(),
+        )
+        .returning(move |_, _, _, _| Ok(PushReceipt::default()));
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    expect_sufficient_balance(&mut executor);
+    git.expect_resolve_reference()
+        .returning(move |_| Ok(new_ref_hash.clone()));
+    let object_hash = object.get_hash().clone();
+    git.expect_list_objects()
+        .returning(move |_| Ok(vec![object_hash.clone()]));
+    let object_hash = object.get_hash().clone();
+    git.expect_get_object()
+        .with(eq(object_hash.clone()))
+        .returning(move |_| Ok(object.clone()));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![Push {
+        local: "refs/heads/main".to_string(),
+        remote: "refs/heads/main".to_string(),
+        is_force: false,
+        expected_remote: None,
+    }])
+    .expect("should succeed");
+}
+
+#[test]
+fn test_push_note() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    // A notes ref points at a commit whose tree maps annotated objects to note blobs
+    let note = Object::new(ObjectKind::Blob, b"reviewed\n".to_vec(), true).expect("should be set");
+    let notes_ref_hash = Hash::from_data(b"notes_commit", true).expect("should be set");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor
+        .expect_resolve_references()
+        .with(eq(vec!["refs/notes/commits".to_string()]))
+        .returning(|_| {
+            Ok(vec![Some(
+                Hash::from_data(b"old_notes", true).expect("should be set"),
+            )])
+        });
+    executor
+        .expect_list_all_objects()
+        .returning(move || Ok(vec![]));
+    let note_clone = note.clone();
+    let notes_ref_hash_clone = notes_ref_hash.clone();
+    executor
+        .expect_push()
+        .with(
+            eq(vec![note_clone]),
+            eq(vec![]),
+            eq(vec![Reference::Normal {
+                name: "refs/notes/commits".to_string(),
+                hash: notes_ref_hash_clone,
+            }]),
+            always(),
+        )
+        .returning(move |_, _, _, _| Ok(PushReceipt::default()));
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    expect_sufficient_balance(&mut executor);
+    git.expect_resolve_reference()
+        .with(eq("refs/notes/commits"))
+        .returning(move |_| Ok(notes_ref_hash.clone()));
+    let note_hash = note.get_hash().clone();
+    git.expect_list_objects()
+        .returning(move |_| Ok(vec![note_hash.clone()]));
+    git.expect_get_object()
+        .with(eq(note.get_hash().clone()))
+        .returning(move |_| Ok(note.clone()));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![Push {
+        local: "refs/notes/commits".to_string(),
+        remote: "refs/notes/commits".to_string(),
+        is_force: false,
+        expected_remote: None,
+    }])
+    .expect("should succeed");
+}
+
+#[test]
+fn test_push_new_objects_batched() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let objects = vec![
+        Object::new(ObjectKind::Blob, b"object_one".to_vec(), true).expect("should be set"),
+        Object::new(ObjectKind::Blob, b"object_two".to_vec(), true).expect("should be set"),
+    ];
+    let object_hashes: Vec<Hash> = objects.iter().map(|o| o.get_hash().clone()).collect();
+    let new_ref_hash = Hash::from_data(b"ref_two", true).expect("should be set");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_resolve_references().returning(move |_| {
+        Ok(vec![Some(
+            Hash::from_data(b"ref_one", true).expect("should be set"),
+        )])
+    });
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor
+        .expect_push()
+        .with(
+            eq(objects.clone()),
+            eq(vec![]),
+            eq(vec![Reference::Normal {
+                name: "refs/heads/main".to_string(),
+                hash: new_ref_hash.clone(),
+            }]),
+            always(),
+        )
+        .returning(|_, _, _, _| Ok(PushReceipt::default()));
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    expect_sufficient_balance(&mut executor);
+    git.expect_resolve_reference()
+        .returning(move |_| Ok(new_ref_hash.clone()));
+    let object_hashes_clone = object_hashes.clone();
+    git.expect_list_objects()
+        .returning(move |_| Ok(object_hashes_clone.clone()));
+    git.expect_get_object().never();
+    git.expect_get_objects()
+        .withf(move |hashes| {
+            hashes.len() == object_hashes.len() && object_hashes.iter().all(|h| hashes.contains(h))
+        })
+        .times(1)
+        .returning(move |_| Ok(objects.clone()));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![Push {
+        local: "refs/heads/main".to_string(),
+        remote: "refs/heads/main".to_string(),
+        is_force: false,
+        expected_remote: None,
+    }])
+    .expect("should succeed");
+}
+
+#[test]
+fn test_push_object_too_large() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let small = Object::new(ObjectKind::Blob, b"small".to_vec(), true).expect("should be set");
+    let large = Object::new(ObjectKind::Blob, vec![b'a'; 64], true).expect("should be set");
+    let objects = vec![small.clone(), large.clone()];
+    let object_hashes: Vec<Hash> = objects.iter().map(|o| o.get_hash().clone()).collect();
+    let new_ref_hash = Hash::from_data(b"ref", true).expect("should be set");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_resolve_references().returning(|_| {
+        Ok(vec![Some(
+            Hash::from_data(b"old", true).expect("should be set"),
+        )])
+    });
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor.expect_estimate_push_cost().never();
+    executor.expect_push().never();
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    git.expect_resolve_reference()
+        .returning(move |_| Ok(new_ref_hash.clone()));
+    git.expect_list_objects()
+        .returning(move |_| Ok(object_hashes.clone()));
+    git.expect_get_objects()
+        .returning(move |_| Ok(objects.clone()));
+
+    let settings = Settings {
+        max_object_bytes: Some(32),
+        ..Settings::default()
+    };
+    let evm = Evm::new(runtime, executor, Rc::new(git), settings).expect("should be set");
+    let err = evm
+        .push(vec![Push {
+            local: "refs/heads/main".to_string(),
+            remote: "refs/heads/main".to_string(),
+            is_force: false,
+            expected_remote: None,
+        }])
+        .expect_err("should fail");
+    assert_eq!(
+        err,
+        RemoteHelperError::Invalid {
+            what: "object too large".to_string(),
+            value: format!("{} (72 bytes, limit: 32)", large.get_hash()),
+        }
+    );
+}
+
+#[test]
+fn test_push_object_filtered() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let objects = commit_objects(None, &[b"source", b"artifact"]);
+    let artifact = objects[3].get_hash().clone();
+    let object_hashes: Vec<Hash> = objects.iter().map(|o| o.get_hash().clone()).collect();
+    let new_ref_hash = objects[0].get_hash().clone();
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_resolve_references().returning(|_| {
+        Ok(vec![Some(
+            Hash::from_data(b"old", true).expect("should be set"),
+        )])
+    });
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor.expect_estimate_push_cost().never();
+    executor.expect_push().never();
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    git.expect_resolve_reference()
+        .returning(move |_| Ok(new_ref_hash.clone()));
+    git.expect_list_objects()
+        .returning(move |_| Ok(object_hashes.clone()));
+    git.expect_get_objects()
+        .returning(move |_| Ok(objects.clone()));
+
+    let settings = Settings {
+        object_filter: Some(ObjectFilter::parse("file1\n").expect("failed to parse")),
+        ..Settings::default()
+    };
+    let evm = Evm::new(runtime, executor, Rc::new(git), settings).expect("should be set");
+    let err = evm
+        .push(vec![Push {
+            local: "refs/heads/main".to_string(),
+            remote: "refs/heads/main".to_string(),
+            is_force: false,
+            expected_remote: None,
+        }])
+        .expect_err("should fail");
+    assert_eq!(
+        err,
+        RemoteHelperError::Invalid {
+            what: "object excluded by .gitdemignore".to_string(),
+            value: format!("blob {} at file1 (8 bytes, rule: file1)", artifact),
+        }
+    );
+}
+
+#[test]
+fn test_push_resolve_local_reference_failure() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    git.expect_resolve_reference().returning(move |_| {
+        Err(RemoteHelperError::Failure {
+            action: "resolve references".to_string(),
+            details: Some("object".to_string()),
+        })
+    });
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![Push {
+        local: "refs/heads/main".to_string(),
+        remote: "refs/heads/main".to_string(),
+        is_force: false,
+        expected_remote: None,
+    }])
+    .expect_err("should fail");
+}
+
+#[test]
+fn test_push_resolve_remote_reference_failure() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_resolve_references().returning(|_| {
+        Err(RemoteHelperError::Failure {
+            action: "resolve references".to_string(),
+            details: Some("object".to_string()),
+        })
+    });
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    git.expect_resolve_reference()
+        .returning(|_| Hash::from_data(b"ref_one", true));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![Push {
+        local: "refs/heads/main".to_string(),
+        remote: "refs/heads/main".to_string(),
+        is_force: false,
+        expected_remote: None,
+    }])
+    .expect_err("should fail");
+}
+
+#[test]
+fn test_push_list_remote_objects_failure() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_resolve_references().returning(|_| {
+        Ok(vec![Some(
+            Hash::from_data(b"ref_one", true).expect("should be set"),
+        )])
+    });
+    executor.expect_list_all_objects().returning(|| {
+        Err(RemoteHelperError::Failure {
+            action: "list objects".to_string(),
+            details: Some("object".to_string()),
+        })
+    });
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    git.expect_resolve_reference()
+        .returning(|_| Hash::from_data(b"ref_two", true));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![Push {
+        local: "refs/heads/main".to_string(),
+        remote: "refs/heads/main".to_string(),
+        is_force: false,
+        expected_remote: None,
+    }])
+    .expect_err("should fail");
+}
+
+#[test]
+fn test_push_list_local_objects_failure() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor.expect_resolve_references().returning(|_| {
+        Ok(vec![Some(
+            Hash::from_data(b"ref_one", true).expect("should be set"),
+        )])
+    });
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    git.expect_resolve_reference()
+        .returning(|_| Hash::from_data(b"ref_two", true));
+    git.expect_list_objects().returning(|_| {
+        Err(RemoteHelperError::Failure {
+            action: "list objects".to_string(),
+            details: Some("object".to_string()),
+        })
+    });
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![Push {
+        local: "refs/heads/main".to_string(),
+        remote: "refs/heads/main".to_string(),
+        is_force: false,
+        expected_remote: None,
+    }])
+    .expect_err("should fail");
+}
+
+#[test]
+fn test_push_get_object_failure() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor.expect_resolve_references().returning(|_| {
+        Ok(vec![Some(
+            Hash::from_data(b"ref_one", true).expect("should be set"),
+        )])
+    });
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    git.expect_resolve_reference()
+        .returning(|_| Hash::from_data(b"ref_two", true));
+    git.expect_list_objects().returning(|_| {
+        Ok(vec![
+            Hash::from_data(b"object_hash", true).expect("should be set"),
+        ])
+    });
+    git.expect_get_object().returning(|_| {
+        Err(RemoteHelperError::Failure {
+            action: "get object".to_string(),
+            details: Some("object".to_string()),
+        })
+    });
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![Push {
+        local: "refs/heads/main".to_string(),
+        remote: "refs/heads/main".to_string(),
+        is_force: false,
+        expected_remote: None,
+    }])
+    .expect_err("should fail");
+}
+
+#[test]
+fn test_push_failure() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor.expect_resolve_references().returning(|_| {
+        Ok(vec![Some(
+            Hash::from_data(b"ref_one", true).expect("should be set"),
+        )])
+    });
+    executor.expect_push().returning(|_, _, _, _| {
+        Err(RemoteHelperError::Failure {
+            action: "push".to_string(),
+            details: Some("object".to_string()),
+        })
+    });
+
+    let object =
+        Object::new(ObjectKind::Blob, b"object_data".to_vec(), true).expect("should be set");
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    expect_sufficient_balance(&mut executor);
+    git.expect_resolve_reference()
+        .returning(|_| Hash::from_data(b"ref_two", true));
+    let object_hash = object.get_hash().clone();
+    git.expect_list_objects()
+        .returning(move |_| Ok(vec![object_hash.clone()]));
+    git.expect_get_object()
+        .returning(move |_| Ok(object.clone()));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.push(vec![Push {
+        local: "refs/heads/main".to_string(),
+        remote: "refs/heads/main".to_string(),
+        is_force: false,
+        expected_remote: None,
+    }])
+    .expect_err("should fail");
+}
+
+#[test]
+fn test_check() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_check().returning(|| Ok(()));
+    executor.expect_list().returning(|| {
+        Ok(vec![
+            Reference::Normal {
+                name: "refs/heads/main".to_string(),
+                hash: Hash::from_data(b"1234567890", true).expect("should be set"),
+            },
+            Reference::KeyValue {
+                key: Keys::ObjectFormat,
+                value: "sha256".to_string(),
+            },
+        ])
+    });
+
+    let evm = Evm::new(
+        runtime,
+        executor,
+        Rc::new(MockGit::new()),
+        Settings::default(),
+    )
+    .expect("should be set");
+    evm.check().expect("should succeed");
+}
+
+#[test]
+fn test_check_no_code() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_check().returning(|| {
+        Err(RemoteHelperError::Missing {
+            what: "contract code".to_string(),
+        })
+    });
+
+    let evm = Evm::new(
+        runtime,
+        executor,
+        Rc::new(MockGit::new()),
+        Settings::default(),
+    )
+    .expect("should be set");
+    evm.check().expect_err("should fail");
+}
+
+#[test]
+fn test_check_no_object_format() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_check().returning(|| Ok(()));
+    executor.expect_list().returning(|| Ok(vec![]));
+
+    let evm = Evm::new(
+        runtime,
+        executor,
+        Rc::new(MockGit::new()),
+        Settings::default(),
+    )
+    .expect("should be set");
+    evm.check().expect_err("should fail");
+}
+
+#[test]
+fn test_fetch_object_format_mismatch() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, false);
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    let err = evm
+        .fetch(vec![Fetch {
+            hash: Hash::from_data(b"1234567890", true).expect("should be set"),
+            name: "refs/heads/main".to_string(),
+        }])
+        .expect_err("should fail");
+    assert_eq!(
+        err,
+        RemoteHelperError::Invalid {
+            what: "object format mismatch".to_string(),
+            value: "local: sha1, remote: sha256".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_push_object_format_mismatch() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, false);
+    executor.expect_signer_address().return_const([0u8; 20]);
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    let err = evm
+        .push(vec![Push {
+            local: "refs/heads/main".to_string(),
+            remote: "refs/heads/main".to_string(),
+            is_force: false,
+            expected_remote: None,
+        }])
+        .expect_err("should fail");
+    assert_eq!(
+        err,
+        RemoteHelperError::Invalid {
+            what: "object format mismatch".to_string(),
+            value: "local: sha1, remote: sha256".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_push_symbolic() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor
+        .expect_resolve_references()
+        .with(eq(vec![]))
+        .returning(|_| Ok(vec![]));
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor
+        .expect_push()
+        .with(
+            eq(vec![]),
+            eq(vec![]),
+            eq(vec![Reference::Symbolic {
+                name: "HEAD".to_string(),
+                target: "refs/heads/dev".to_string(),
+            }]),
+            always(),
+        )
+        .returning(|_, _, _, _| Ok(PushReceipt::default()));
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    expect_sufficient_balance(&mut executor);
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     evm.push(vec![Push {
-        local: "refs/heads/main".to_string(),
-        remote: "refs/heads/main".to_string(),
+        local: "refs/heads/dev".to_string(),
+        remote: "HEAD".to_string(),
         is_force: false,
+        expected_remote: None,
     }])
-    .expect_err("should fail");
+    .expect("should succeed");
 }
 
 #[test]
-fn test_push_resolve_remote_reference_failure() {
+fn test_push_symbolic_invalid_target() {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("failed to build runtime");
 
     let mut executor = Box::new(MockExecutor::new());
-    executor.expect_resolve_references().returning(|_| {
-        Err(RemoteHelperError::Failure {
-            action: "resolve references".to_string(),
-            details: Some("object".to_string()),
-        })
-    });
-
     let mut git = MockGit::new();
-    git.expect_resolve_reference()
-        .returning(|_| Hash::from_data(b"ref_one", true));
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     evm.push(vec![Push {
-        local: "refs/heads/main".to_string(),
-        remote: "refs/heads/main".to_string(),
+        local: "dev".to_string(),
+        remote: "HEAD".to_string(),
         is_force: false,
+        expected_remote: None,
     }])
     .expect_err("should fail");
 }
 
 #[test]
-fn test_push_list_remote_objects_failure() {
+fn test_push_insufficient_balance() {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("failed to build runtime");
 
+    let object =
+        Object::new(ObjectKind::Blob, b"object_data".to_vec(), true).expect("should be set");
+
     let mut executor = Box::new(MockExecutor::new());
     executor.expect_resolve_references().returning(|_| {
-        Ok(vec![
+        Ok(vec![Some(
             Hash::from_data(b"ref_one", true).expect("should be set"),
-        ])
-    });
-    executor.expect_list_all_objects().returning(|| {
-        Err(RemoteHelperError::Failure {
-            action: "list objects".to_string(),
-            details: Some("object".to_string()),
-        })
+        )])
     });
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor
+        .expect_estimate_push_cost()
+        .returning(|_, _, _| Ok(U256::from(2)));
+    executor.expect_balance().returning(|| Ok(U256::from(1)));
+    executor
+        .expect_push()
+        .times(1)
+        .returning(|_, _, _, _| Ok(PushReceipt::default()));
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
     git.expect_resolve_reference()
         .returning(|_| Hash::from_data(b"ref_two", true));
+    let object_hash = object.get_hash().clone();
+    git.expect_list_objects()
+        .returning(move |_| Ok(vec![object_hash.clone()]));
+    git.expect_get_object()
+        .returning(move |_| Ok(object.clone()));
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
     evm.push(vec![Push {
         local: "refs/heads/main".to_string(),
         remote: "refs/heads/main".to_string(),
         is_force: false,
+        expected_remote: None,
     }])
-    .expect_err("should fail");
+    .expect("should succeed with a warning");
 }
 
 #[test]
-fn test_push_list_local_objects_failure() {
+fn test_push_insufficient_balance_strict() {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("failed to build runtime");
 
+    let object =
+        Object::new(ObjectKind::Blob, b"object_data".to_vec(), true).expect("should be set");
+
     let mut executor = Box::new(MockExecutor::new());
-    executor.expect_list_all_objects().returning(|| Ok(vec![]));
     executor.expect_resolve_references().returning(|_| {
-        Ok(vec![
+        Ok(vec![Some(
             Hash::from_data(b"ref_one", true).expect("should be set"),
-        ])
+        )])
     });
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor
+        .expect_estimate_push_cost()
+        .returning(|_, _, _| Ok(U256::from(2)));
+    executor.expect_balance().returning(|| Ok(U256::from(1)));
+    executor.expect_push().never();
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
     git.expect_resolve_reference()
         .returning(|_| Hash::from_data(b"ref_two", true));
-    git.expect_list_objects().returning(|_| {
-        Err(RemoteHelperError::Failure {
-            action: "list objects".to_string(),
-            details: Some("object".to_string()),
-        })
-    });
+    let object_hash = object.get_hash().clone();
+    git.expect_list_objects()
+        .returning(move |_| Ok(vec![object_hash.clone()]));
+    git.expect_get_object()
+        .returning(move |_| Ok(object.clone()));
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
+    let settings = Settings {
+        strict_balance_check: true,
+        ..Settings::default()
+    };
+    let evm = Evm::new(runtime, executor, Rc::new(git), settings).expect("should be set");
     evm.push(vec![Push {
         local: "refs/heads/main".to_string(),
         remote: "refs/heads/main".to_string(),
         is_force: false,
+        expected_remote: None,
     }])
     .expect_err("should fail");
 }
 
 #[test]
-fn test_push_get_object_failure() {
+fn test_export() {
+    use std::io::{BufReader, Cursor};
+
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("failed to build runtime");
+    let hash = Hash::from_data(b"1234567890", true).expect("should be set");
+    let stream = b"feature done\nreset refs/heads/main\nfrom :1\n\ndone\n";
+    let stream = ExportStream::read(&mut BufReader::new(Cursor::new(stream.to_vec())))
+        .expect("failed to read stream");
 
     let mut executor = Box::new(MockExecutor::new());
+    let hash_clone = hash.clone();
+    executor
+        .expect_resolve_references()
+        .with(eq(vec!["refs/heads/main".to_string()]))
+        .returning(move |_| Ok(vec![Some(hash_clone.clone())]));
     executor.expect_list_all_objects().returning(|| Ok(vec![]));
-    executor.expect_resolve_references().returning(|_| {
-        Ok(vec![
-            Hash::from_data(b"ref_one", true).expect("should be set"),
-        ])
-    });
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    git.expect_fast_import()
+        .with(eq(
+            b"feature done\nreset refs/gitdem/import/heads/main\nfrom :1\n\ndone\n".to_vec(),
+        ))
+        .times(1)
+        .returning(|_| Ok(()));
     git.expect_resolve_reference()
-        .returning(|_| Hash::from_data(b"ref_two", true));
-    git.expect_list_objects().returning(|_| {
-        Ok(vec![
-            Hash::from_data(b"object_hash", true).expect("should be set"),
-        ])
-    });
-    git.expect_get_object().returning(|_| {
+        .with(eq("refs/gitdem/import/heads/main".to_string()))
+        .returning(move |_| Ok(hash.clone()));
+    git.expect_delete_reference()
+        .with(eq("refs/gitdem/import/heads/main".to_string()))
+        .times(1)
+        .returning(|_| Ok(()));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.export(stream).expect("should succeed");
+}
+
+#[test]
+fn test_export_import_failure() {
+    use std::io::{BufReader, Cursor};
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+    let stream = b"reset refs/heads/main\nfrom :1\n\ndone\n";
+    let stream = ExportStream::read(&mut BufReader::new(Cursor::new(stream.to_vec())))
+        .expect("failed to read stream");
+
+    let mut git = MockGit::new();
+    git.expect_fast_import().returning(|_| {
         Err(RemoteHelperError::Failure {
-            action: "get object".to_string(),
-            details: Some("object".to_string()),
+            action: "running git fast-import".to_string(),
+            details: None,
         })
     });
+    git.expect_delete_reference().never();
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)).expect("should be set");
-    evm.push(vec![Push {
-        local: "refs/heads/main".to_string(),
-        remote: "refs/heads/main".to_string(),
-        is_force: false,
-    }])
-    .expect_err("should fail");
+    let evm = Evm::new(
+        runtime,
+        Box::new(MockExecutor::new()),
+        Rc::new(git),
+        Settings::default(),
+    )
+    .expect("should be set");
+    evm.export(stream).expect_err("should fail");
 }
 
 #[test]
-fn test_push_failure() {
+fn test_set_option() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+    let evm = Evm::new(
+        runtime,
+        Box::new(MockExecutor::new()),
+        Rc::new(MockGit::new()),
+        Settings::default(),
+    )
+    .expect("should be set");
+
+    assert!(evm.set_option("dry-run", "true").expect("should succeed"));
+    assert!(evm.dry_run.get());
+    assert!(evm.set_option("dry-run", "false").expect("should succeed"));
+    assert!(!evm.dry_run.get());
+    evm.set_option("dry-run", "yes")
+        .expect_err("should fail because of invalid value");
+    assert!(evm.set_option("atomic", "true").expect("should succeed"));
+    assert!(evm.atomic.get());
+    assert!(!evm.set_option("progress", "true").expect("should succeed"));
+    assert!(
+        evm.set_option("object-format", "true")
+            .expect("should succeed")
+    );
+    evm.set_option("object-format", "sha256")
+        .expect_err("should fail because git only sends true");
+    assert!(evm.set_option("pushcert", "false").expect("should succeed"));
+    assert!(!evm.set_option("pushcert", "true").expect("should succeed"));
+    assert!(
+        !evm.set_option("pushcert", "if-asked")
+            .expect("should succeed")
+    );
+}
+
+#[test]
+fn test_object_format_cached() {
     let runtime = tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
         .expect("failed to build runtime");
 
     let mut executor = Box::new(MockExecutor::new());
-    executor.expect_list_all_objects().returning(|| Ok(vec![]));
-    executor.expect_resolve_references().returning(|_| {
+    executor.expect_list().times(1).returning(|| {
         Ok(vec![
-            Hash::from_data(b"ref_one", true).expect("should be set"),
+            Reference::Normal {
+                name: "refs/heads/main".to_string(),
+                hash: Hash::from_data(b"main", true).expect("should be set"),
+            },
+            Reference::KeyValue {
+                key: Keys::ObjectFormat,
+                value: "sha256".to_string(),
+            },
         ])
     });
-    executor.expect_push().returning(|_, _| {
-        Err(RemoteHelperError::Failure {
-            action: "push".to_string(),
-            details: Some("object".to_string()),
-        })
-    });
+    let mut git = MockGit::new();
+    git.expect_is_sha256().returning(|| Ok(true));
+    git.expect_list_all_objects().returning(|| Ok(vec![]));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.list(false).expect("should succeed");
+    // Verifying the format doesn't list the remote again
+    evm.fetch(vec![]).expect("should succeed");
+}
+
+#[test]
+fn test_push_dry_run() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
 
     let object =
         Object::new(ObjectKind::Blob, b"object_data".to_vec(), true).expect("should be set");
+    let new_ref_hash = Hash::from_data(b"ref_two", true).expect("should be set");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_resolve_references().returning(move |_| {
+        Ok(vec![Some(
+            Hash::from_data(b"ref_one", true).expect("should be set"),
+        )])
+    });
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    executor
+        .expect_estimate_push_cost()
+        .times(1)
+        .returning(|_, _, _| Ok(U256::from(1_500_000_000u64)));
+    executor.expect_balance().never();
+    executor.expect_push().never();
 
     let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
     git.expect_resolve_reference()
-        .returning(|_| Hash::from_data(b"ref_two", true));
+        .returning(move |_| Ok(new_ref_hash.clone()));
     let object_hash = object.get_hash().clone();
     git.expect_list_objects()
         .returning(move |_| Ok(vec![object_hash.clone()]));
     git.expect_get_object()
         .returning(move |_| Ok(object.clone()));
 
-    let evm = Evm::new(runtime, executor, Rc::new(git)  ).expect("should be set");
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.set_option("dry-run", "true").expect("should succeed");
     evm.push(vec![Push {
         local: "refs/heads/main".to_string(),
         remote: "refs/heads/main".to_string(),
         is_force: false,
+        expected_remote: None,
     }])
-    .expect_err("should fail");
+    .expect("should succeed");
+}
+
+#[test]
+fn test_format_gwei() {
+    assert_eq!(
+        format_gwei(U256::from(1_500_000_000u64)).expect("should succeed"),
+        "1.500000000"
+    );
+}
+
+#[cfg(test)]
+fn setup_two_reference_push(atomic: bool) -> Evm {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let object_main =
+        Object::new(ObjectKind::Blob, b"main_data".to_vec(), true).expect("should be set");
+    let object_dev =
+        Object::new(ObjectKind::Blob, b"dev_data".to_vec(), true).expect("should be set");
+    let hash_main = Hash::from_data(b"main", true).expect("should be set");
+    let hash_dev = Hash::from_data(b"dev", true).expect("should be set");
+
+    let mut executor = Box::new(MockExecutor::new());
+    executor.expect_resolve_references().returning(|_| {
+        Ok(vec![
+            Some(Hash::from_data(b"old_main", true).expect("should be set")),
+            Some(Hash::from_data(b"old_dev", true).expect("should be set")),
+        ])
+    });
+    executor.expect_list_all_objects().returning(|| Ok(vec![]));
+    // Only single reference batches fit in a transaction
+    executor
+        .expect_estimate_push_cost()
+        .returning(|_, _, references| match references.len() {
+            1 => Ok(U256::from(1)),
+            _ => Err(RemoteHelperError::Failure {
+                action: "estimating gas".to_string(),
+                details: Some("exceeds block gas limit".to_string()),
+            }),
+        });
+    executor.expect_balance().returning(|| Ok(U256::from(2)));
+    if atomic {
+        executor.expect_push().never();
+    } else {
+        let (object, hash) = (object_main.clone(), hash_main.clone());
+        executor
+            .expect_push()
+            .with(
+                eq(vec![object]),
+                eq(vec![]),
+                eq(vec![Reference::Normal {
+                    name: "refs/heads/main".to_string(),
+                    hash,
+                }]),
+                eq(vec![(
+                    "refs/heads/main".to_string(),
+                    Some(Hash::from_data(b"old_main", true).expect("should be set")),
+                )]),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(PushReceipt::default()));
+        let (object, hash) = (object_dev.clone(), hash_dev.clone());
+        executor
+            .expect_push()
+            .with(
+                eq(vec![object]),
+                eq(vec![]),
+                eq(vec![Reference::Normal {
+                    name: "refs/heads/dev".to_string(),
+                    hash,
+                }]),
+                eq(vec![(
+                    "refs/heads/dev".to_string(),
+                    Some(Hash::from_data(b"old_dev", true).expect("should be set")),
+                )]),
+            )
+            .times(1)
+            .returning(|_, _, _, _| Ok(PushReceipt::default()));
+    }
+
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    let hash = hash_main.clone();
+    git.expect_resolve_reference()
+        .with(eq("refs/heads/main".to_string()))
+        .returning(move |_| Ok(hash.clone()));
+    let hash = hash_dev.clone();
+    git.expect_resolve_reference()
+        .with(eq("refs/heads/dev".to_string()))
+        .returning(move |_| Ok(hash.clone()));
+    let object_hash = object_main.get_hash().clone();
+    git.expect_list_objects()
+        .with(eq(hash_main))
+        .returning(move |_| Ok(vec![object_hash.clone()]));
+    let object_hash = object_dev.get_hash().clone();
+    git.expect_list_objects()
+        .with(eq(hash_dev))
+        .returning(move |_| Ok(vec![object_hash.clone()]));
+    git.expect_get_objects()
+        .returning(move |_| Ok(vec![object_main.clone(), object_dev.clone()]));
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.set_option("atomic", if atomic { "true" } else { "false" })
+        .expect("should succeed");
+    evm
+}
+
+#[cfg(test)]
+fn two_reference_pushes() -> Vec<Push> {
+    vec![
+        Push::new(
+            "refs/heads/main".to_string(),
+            "refs/heads/main".to_string(),
+            false,
+        ),
+        Push::new(
+            "refs/heads/dev".to_string(),
+            "refs/heads/dev".to_string(),
+            false,
+        ),
+    ]
+}
+
+#[test]
+fn test_push_per_reference_fallback() {
+    let evm = setup_two_reference_push(false);
+    evm.push(two_reference_pushes()).expect("should succeed");
+}
+
+#[test]
+fn test_push_atomic_no_fallback() {
+    let evm = setup_two_reference_push(true);
+    evm.push(two_reference_pushes())
+        .expect_err("should fail instead of splitting the push");
+}
+
+#[test]
+fn test_push_atomic_symbolic() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build runtime");
+
+    let mut executor = Box::new(MockExecutor::new());
+    let mut git = MockGit::new();
+    expect_object_format(&mut executor, &mut git, true);
+    executor.expect_signer_address().return_const([0u8; 20]);
+    executor.expect_push().never();
+
+    let evm =
+        Evm::new(runtime, executor, Rc::new(git), Settings::default()).expect("should be set");
+    evm.set_option("atomic", "true").expect("should succeed");
+    evm.push(vec![
+        Push::new(
+            "refs/heads/dev".to_string(),
+            "refs/heads/dev".to_string(),
+            false,
+        ),
+        Push::new("refs/heads/dev".to_string(), "HEAD".to_string(), false),
+    ])
+    .expect_err("should fail because HEAD needs a separate transaction");
 }
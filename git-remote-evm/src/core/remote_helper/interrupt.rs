@@ -0,0 +1,81 @@
+use crate::core::remote_helper::error::RemoteHelperError;
+use std::future::Future;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Poll;
+use std::time::Duration;
+
+// Set by the SIGINT handler, storing a flag is about all a handler may safely do
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// How often a waiting future checks the flag
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    // A second ctrl-c doesn't wait for the current step to notice the first one
+    if INTERRUPTED.swap(true, Ordering::Relaxed) {
+        unsafe { libc::_exit(130) };
+    }
+}
+
+pub fn install() -> Result<(), RemoteHelperError> {
+    let handler = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    if unsafe { libc::signal(libc::SIGINT, handler) } == libc::SIG_ERR {
+        return Err(RemoteHelperError::Failure {
+            action: "installing the interrupt handler".to_string(),
+            details: Some(std::io::Error::last_os_error().to_string()),
+        });
+    }
+    Ok(())
+}
+
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+// Drops the future once ctrl-c is pressed, whatever it already wrote to git stays there
+pub async fn cancellable<T>(
+    future: impl Future<Output = Result<T, RemoteHelperError>>,
+) -> Result<T, RemoteHelperError> {
+    cancellable_on(future, &INTERRUPTED).await
+}
+
+async fn cancellable_on<T>(
+    future: impl Future<Output = Result<T, RemoteHelperError>>,
+    flag: &AtomicBool,
+) -> Result<T, RemoteHelperError> {
+    let mut future = pin!(future);
+    let mut tick = pin!(tokio::time::sleep(POLL_INTERVAL));
+    std::future::poll_fn(|cx| {
+        if flag.load(Ordering::Relaxed) {
+            return Poll::Ready(Err(RemoteHelperError::Interrupted));
+        }
+        if let Poll::Ready(result) = future.as_mut().poll(cx) {
+            return Poll::Ready(result);
+        }
+        // Nothing wakes the task when the flag is set, so it's checked on a timer
+        while tick.as_mut().poll(cx).is_ready() {
+            tick.as_mut()
+                .reset(tokio::time::Instant::now() + POLL_INTERVAL);
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+#[tokio::test]
+async fn test_cancellable() {
+    let flag = AtomicBool::new(false);
+    let result = cancellable_on(async { Ok(1) }, &flag).await;
+    assert_eq!(result, Ok(1));
+
+    // Never finishes on its own, only the flag ends it
+    let pending = async {
+        std::future::pending::<()>().await;
+        Ok(())
+    };
+    let result = tokio::join!(cancellable_on(pending, &flag), async {
+        tokio::time::sleep(POLL_INTERVAL / 2).await;
+        flag.store(true, Ordering::Relaxed);
+    });
+    assert_eq!(result.0, Err(RemoteHelperError::Interrupted));
+}
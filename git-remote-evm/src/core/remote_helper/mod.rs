@@ -1,8 +1,14 @@
+pub mod blob_store;
+pub mod builder;
 pub mod config;
 pub mod error;
 pub mod evm;
 pub mod executor;
+pub mod interrupt;
+pub mod object_cache;
+pub mod object_filter;
 
+use crate::core::fast_export::ExportStream;
 use crate::core::reference::{Fetch, Push, Reference};
 use error::RemoteHelperError;
 use mockall::automock;
@@ -13,4 +19,10 @@ pub trait RemoteHelper {
     fn list(&self, is_for_push: bool) -> Result<Vec<Reference>, RemoteHelperError>;
     fn fetch(&self, fetches: Vec<Fetch>) -> Result<(), RemoteHelperError>;
     fn push(&self, pushes: Vec<Push>) -> Result<(), RemoteHelperError>;
+    fn export(&self, stream: ExportStream) -> Result<(), RemoteHelperError>;
+    fn check(&self) -> Result<(), RemoteHelperError>;
+    // Returns whether the option is supported, see the option command in gitremote-helpers.adoc
+    fn set_option(&self, name: &str, value: &str) -> Result<bool, RemoteHelperError>;
+    // From --push-option, they apply to the next push only
+    fn set_push_options(&self, options: Vec<String>);
 }
@@ -0,0 +1,89 @@
+use crate::core::hash::Hash;
+use crate::core::object::Object;
+use crate::core::remote_helper::error::RemoteHelperError;
+use log::debug;
+use std::path::{Path, PathBuf};
+
+// Objects fetched from a contract, shared by every clone of it on this machine.
+// Laid out like git's loose objects under a directory per contract, but not compressed.
+#[derive(Debug, Clone)]
+pub struct ObjectCache {
+    dir: PathBuf,
+}
+
+impl ObjectCache {
+    pub fn new(root: &Path, address: [u8; 20]) -> Self {
+        Self {
+            dir: root.join(format!("0x{}", hex::encode(address))),
+        }
+    }
+
+    fn path(&self, hash: &Hash) -> PathBuf {
+        let hex = hash.to_string();
+        self.dir.join(&hex[..2]).join(&hex[2..])
+    }
+
+    // Anyone can write to the cache, so an entry is only used if it hashes to what it's stored under
+    pub fn get(&self, hash: &Hash) -> Result<Option<Object>, RemoteHelperError> {
+        let path = self.path(hash);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(RemoteHelperError::Failure {
+                    action: format!("reading {}", path.display()),
+                    details: Some(e.to_string()),
+                });
+            }
+        };
+        if let Err(e) = Object::verify_serialized(&data, hash) {
+            debug!("ignoring cached {}: {}", hash, e);
+            return Ok(None);
+        }
+        Object::deserialize(&data, hash.is_sha256()).map(Some)
+    }
+
+    // Written under a temporary name first, so a concurrent clone never reads half an object
+    pub fn put(&self, object: &Object) -> Result<(), RemoteHelperError> {
+        let path = self.path(object.get_hash());
+        let failure = |action: &str, e: std::io::Error| RemoteHelperError::Failure {
+            action: format!("{} {}", action, path.display()),
+            details: Some(e.to_string()),
+        };
+        if path.exists() {
+            return Ok(());
+        }
+        let parent = path.parent().expect("the path should have a parent");
+        std::fs::create_dir_all(parent).map_err(|e| failure("creating the parent of", e))?;
+        let temp = path.with_extension(format!("tmp-{}", std::process::id()));
+        std::fs::write(&temp, object.serialize()).map_err(|e| failure("writing", e))?;
+        std::fs::rename(&temp, &path).map_err(|e| failure("writing", e))
+    }
+}
+
+#[test]
+fn test_object_cache() {
+    use crate::core::object::ObjectKind;
+
+    let root = tempfile::tempdir().expect("failed to create temp dir");
+    let cache = ObjectCache::new(root.path(), [1u8; 20]);
+    let object = Object::new(ObjectKind::Blob, b"cached".to_vec(), true).expect("should be set");
+
+    assert_eq!(cache.get(object.get_hash()).expect("should succeed"), None);
+    cache.put(&object).expect("should succeed");
+    cache.put(&object).expect("storing it again should succeed");
+    assert_eq!(
+        cache.get(object.get_hash()).expect("should succeed"),
+        Some(object.clone())
+    );
+
+    // Each contract has its own directory
+    let other = ObjectCache::new(root.path(), [2u8; 20]);
+    assert_eq!(other.get(object.get_hash()).expect("should succeed"), None);
+
+    // A corrupted entry is treated as missing
+    let tampered =
+        Object::new(ObjectKind::Blob, b"tampered".to_vec(), true).expect("should be set");
+    std::fs::write(cache.path(object.get_hash()), tampered.serialize()).expect("should succeed");
+    assert_eq!(cache.get(object.get_hash()).expect("should succeed"), None);
+}
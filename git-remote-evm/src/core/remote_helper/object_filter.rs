@@ -0,0 +1,279 @@
+use crate::core::hash::Hash;
+use crate::core::object::{Object, ObjectKind};
+use crate::core::remote_helper::error::RemoteHelperError;
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::Path;
+
+// Read from the working tree root, which is where git runs the helper
+pub const OBJECT_FILTER_FILE: &str = ".gitdemignore";
+
+#[derive(Debug, Clone)]
+enum Rule {
+    // Blobs with more bytes of content than this
+    Size(usize),
+    // Paths of blobs, with gitignore's `*`, `**` and `?`
+    Path(Regex),
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    rule: Rule,
+    text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+// Blobs that mustn't go on-chain. Matching ones fail the push, skipping them would leave
+// trees pointing at objects the remote doesn't have.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectFilter {
+    lines: Vec<Line>,
+}
+
+impl ObjectFilter {
+    // None when the file doesn't exist
+    pub fn load(path: &Path) -> Result<Option<Self>, RemoteHelperError> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Self::parse(&content).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(RemoteHelperError::Failure {
+                action: format!("reading {}", path.display()),
+                details: Some(e.to_string()),
+            }),
+        }
+    }
+
+    // One rule per line: `size > <bytes>[k|m|g]` or a path pattern, `#` starts a comment
+    pub fn parse(content: &str) -> Result<Self, RemoteHelperError> {
+        let mut lines = vec![];
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = match line.strip_prefix("size") {
+                Some(rest) if rest.trim_start().starts_with('>') => {
+                    Rule::Size(parse_size(rest.trim_start()[1..].trim()).ok_or(
+                        RemoteHelperError::Invalid {
+                            what: format!("{} size rule", OBJECT_FILTER_FILE),
+                            value: line.to_string(),
+                        },
+                    )?)
+                }
+                _ => Rule::Path(glob_to_regex(line)?),
+            };
+            lines.push(Line {
+                rule,
+                text: line.to_string(),
+            });
+        }
+        Ok(Self { lines })
+    }
+
+    // Paths come from the trees of the pushed commits, blobs under trees that are
+    // already on-chain are on-chain too so they never need checking
+    pub fn check(&self, objects: &[Object]) -> Result<(), RemoteHelperError> {
+        let by_hash: HashMap<&Hash, &Object> = objects.iter().map(|o| (o.get_hash(), o)).collect();
+        let mut visited = HashSet::new();
+        for commit in objects
+            .iter()
+            .filter(|o| matches!(o.get_kind(), ObjectKind::Commit))
+        {
+            // A commit's first related object is its tree
+            let mut trees = vec![(String::new(), &commit.get_related()[0])];
+            while let Some((prefix, hash)) = trees.pop() {
+                let Some(tree) = by_hash.get(hash) else {
+                    continue;
+                };
+                if !visited.insert((prefix.clone(), hash)) {
+                    continue;
+                }
+                for (name, hash) in tree.get_tree_entries() {
+                    let Some(object) = by_hash.get(&hash) else {
+                        continue;
+                    };
+                    let path = format!("{}{}", prefix, name);
+                    match object.get_kind() {
+                        ObjectKind::Tree => {
+                            let hash = object.get_hash();
+                            trees.push((format!("{}/", path), hash));
+                        }
+                        ObjectKind::Blob => self.check_blob(object, Some(&path))?,
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        // Blobs not reachable from a pushed commit, e.g. tagged directly, only have a size
+        for blob in objects
+            .iter()
+            .filter(|o| matches!(o.get_kind(), ObjectKind::Blob))
+        {
+            self.check_blob(blob, None)?;
+        }
+        Ok(())
+    }
+
+    fn check_blob(&self, blob: &Object, path: Option<&str>) -> Result<(), RemoteHelperError> {
+        let size = blob.get_data().len();
+        let line = self.lines.iter().find(|line| match &line.rule {
+            Rule::Size(limit) => size > *limit,
+            Rule::Path(regex) => path.is_some_and(|path| regex.is_match(path)),
+        });
+        let Some(line) = line else {
+            return Ok(());
+        };
+        let location = match path {
+            Some(path) => format!(" at {}", path),
+            None => String::new(),
+        };
+        Err(RemoteHelperError::Invalid {
+            what: format!("object excluded by {}", OBJECT_FILTER_FILE),
+            value: format!(
+                "blob {}{} ({} bytes, rule: {})",
+                blob.get_hash(),
+                location,
+                size,
+                line
+            ),
+        })
+    }
+}
+
+fn parse_size(value: &str) -> Option<usize> {
+    let multiplier = match value.chars().last()?.to_ascii_lowercase() {
+        'k' => 1024,
+        'm' => 1024 * 1024,
+        'g' => 1024 * 1024 * 1024,
+        _ => 1,
+    };
+    // The suffixes are ascii, so dropping one byte stays on a char boundary
+    let number = match multiplier {
+        1 => value,
+        _ => &value[..value.len() - 1],
+    };
+    number.trim().parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+// Like gitignore, a pattern without a slash matches names at any depth, a leading slash
+// anchors it to the root and a trailing one matches everything under a directory
+fn glob_to_regex(pattern: &str) -> Result<Regex, RemoteHelperError> {
+    let (pattern, is_dir) = match pattern.strip_suffix('/') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+
+    let mut regex = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                regex.push_str(".*");
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push_str(if is_dir { "/.*$" } else { "(?:/.*)?$" });
+
+    Regex::new(&regex).map_err(|e| RemoteHelperError::Invalid {
+        what: format!("{} pattern", OBJECT_FILTER_FILE),
+        value: format!("{} ({})", pattern, e),
+    })
+}
+
+#[test]
+fn test_parse_object_filter() {
+    let filter = ObjectFilter::parse("# comment\n\nsize > 2k\nsize>1M\n*.bin\nbuild/\n")
+        .expect("failed to parse");
+    assert_eq!(filter.lines.len(), 4);
+    assert!(matches!(filter.lines[0].rule, Rule::Size(2048)));
+    assert!(matches!(filter.lines[1].rule, Rule::Size(1048576)));
+
+    for invalid in ["size > ", "size > 1x", "size > -1"] {
+        ObjectFilter::parse(invalid).expect_err("should fail");
+    }
+}
+
+#[test]
+fn test_glob_to_regex() {
+    let cases = [
+        ("*.bin", "a.bin", true),
+        ("*.bin", "dir/a.bin", true),
+        ("*.bin", "a.bin.txt", false),
+        ("/*.bin", "dir/a.bin", false),
+        ("build/", "build/out.o", true),
+        ("build/", "src/build/out.o", true),
+        ("build/", "build", false),
+        ("docs/*.pdf", "docs/a.pdf", true),
+        ("docs/*.pdf", "docs/sub/a.pdf", false),
+        ("docs/**/*.pdf", "docs/sub/a.pdf", true),
+        ("file?.txt", "file1.txt", true),
+        ("vendor", "vendor/lib.rs", true),
+    ];
+    for (pattern, path, expected) in cases {
+        let regex = glob_to_regex(pattern).expect("failed to convert");
+        assert_eq!(regex.is_match(path), expected, "{} on {}", pattern, path);
+    }
+}
+
+#[test]
+fn test_object_filter_check() {
+    use crate::core::git::memory::commit_objects;
+
+    // commit_objects names its files file0, file1, ...
+    let objects = commit_objects(None, &[b"small", b"a larger blob"]);
+    let large = objects[3].get_hash().clone();
+
+    ObjectFilter::parse("size > 64\n*.bin\n")
+        .expect("failed to parse")
+        .check(&objects)
+        .expect("nothing should match");
+
+    let err = ObjectFilter::parse("size > 8\n")
+        .expect("failed to parse")
+        .check(&objects)
+        .expect_err("the larger blob should match");
+    assert_eq!(
+        err,
+        RemoteHelperError::Invalid {
+            what: "object excluded by .gitdemignore".to_string(),
+            value: format!("blob {} at file1 (13 bytes, rule: size > 8)", large),
+        }
+    );
+
+    let err = ObjectFilter::parse("file1\n")
+        .expect("failed to parse")
+        .check(&objects)
+        .expect_err("the path should match");
+    assert_eq!(
+        err,
+        RemoteHelperError::Invalid {
+            what: "object excluded by .gitdemignore".to_string(),
+            value: format!("blob {} at file1 (13 bytes, rule: file1)", large),
+        }
+    );
+
+    // A blob without a pushed tree above it is still checked by size
+    let err = ObjectFilter::parse("size > 8\n")
+        .expect("failed to parse")
+        .check(&objects[3..])
+        .expect_err("the larger blob should match");
+    assert_eq!(
+        err,
+        RemoteHelperError::Invalid {
+            what: "object excluded by .gitdemignore".to_string(),
+            value: format!("blob {} (13 bytes, rule: size > 8)", large),
+        }
+    );
+}
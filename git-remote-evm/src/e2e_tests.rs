@@ -1,4 +1,12 @@
-use std::{io::Write, path::PathBuf, process::Command};
+use std::{
+    env::consts::EXE_SUFFIX,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// npx is a batch script on Windows, which Command doesn't resolve without its extension
+const NPX: &str = if cfg!(windows) { "npx.cmd" } else { "npx" };
 
 fn deploy_contract(manifest_dir: &PathBuf) -> String {
     let on_chain_dir = manifest_dir
@@ -6,7 +14,7 @@ fn deploy_contract(manifest_dir: &PathBuf) -> String {
         .expect("failed to get parent")
         .join("on-chain");
 
-    let output = Command::new("npx")
+    let output = Command::new(NPX)
         .args(&[
             "hardhat",
             "ignition",
@@ -50,20 +58,38 @@ fn build_and_link(manifest_dir: &PathBuf) -> String {
         );
     }
 
-    // 2. Symlink git-remote-evm to git-remote-eth
-    let target_dir = manifest_dir.join("target/release");
-    let evm_path = target_dir.join("git-remote-evm");
-    let eth_path = target_dir.join("git-remote-eth");
-    if let Err(e) = std::os::unix::fs::symlink(evm_path, eth_path) {
-        if !e.to_string().contains("exists") {
-            panic!("failed to link git-remote-evm to git-remote-eth: {}", e);
-        }
-    }
+    // 2. Make git-remote-evm available as git-remote-eth
+    let target_dir = manifest_dir.join("target").join("release");
+    let evm_path = target_dir.join(format!("git-remote-evm{}", EXE_SUFFIX));
+    let eth_path = target_dir.join(format!("git-remote-eth{}", EXE_SUFFIX));
+    link_alias(&evm_path, &eth_path);
 
     // 3. Prepare a new PATH with the target/release/ as the first match
-    let path = std::env::var("PATH").expect("PATH is not set");
-    let new_path = format!("{}:{}", target_dir.display(), path);
+    let path = std::env::var_os("PATH").expect("PATH is not set");
+    let paths = std::iter::once(target_dir).chain(std::env::split_paths(&path));
+    let new_path = std::env::join_paths(paths).expect("failed to join PATH");
     new_path
+        .into_string()
+        .expect("PATH should be valid unicode")
+}
+
+// The protocol is detected from the executable's name, a symlink is enough where it's cheap
+#[cfg(unix)]
+fn link_alias(evm_path: &Path, eth_path: &Path) {
+    if let Err(e) = std::os::unix::fs::symlink(evm_path, eth_path)
+        && e.kind() != std::io::ErrorKind::AlreadyExists
+    {
+        panic!("failed to link git-remote-evm to git-remote-eth: {}", e);
+    }
+}
+
+// Symlinks need extra privileges on Windows, a copy is renamed the same way
+#[cfg(windows)]
+fn link_alias(evm_path: &Path, eth_path: &Path) {
+    // Copied every time, an old copy would test a stale build
+    std::fs::copy(evm_path, eth_path).unwrap_or_else(|e| {
+        panic!("failed to copy git-remote-evm to git-remote-eth: {}", e);
+    });
 }
 
 fn prepare() -> (tempfile::TempDir, String, impl Fn() -> Command) {
@@ -77,12 +103,12 @@ fn prepare() -> (tempfile::TempDir, String, impl Fn() -> Command) {
     let command_builder = move || {
         let mut cmd = Command::new("git");
         cmd.env("PATH", path.as_str())
-            .env("GITDEM_WALLET", "environment")
+            .env("GITDEM_EVM_WALLET", "environment")
             .env(
                 "GITDEM_PRIVATE_KEY",
                 "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
             )
-            .env("GITDEM_ETH_RPC", "http://127.0.0.1:8545/")
+            .env("GITDEM_EVM_ETH_RPC", "http://127.0.0.1:8545/")
             .current_dir(&repo_path);
         cmd
     };
@@ -186,3 +212,34 @@ fn push_simple() {
         );
     }
 }
+
+#[test]
+fn list_in_process() {
+    use crate::core::git::mock::RecordingGit;
+    use crate::core::reference::{Keys, Reference};
+    use crate::core::remote_helper::RemoteHelper;
+    use crate::core::remote_helper::builder::EvmBuilder;
+    use crate::core::remote_helper::config::Wallet;
+    use std::rc::Rc;
+
+    let manifest_dir = PathBuf::from(&std::env::var("CARGO_MANIFEST_DIR").expect("has to be set"));
+    let repo_address = deploy_contract(&manifest_dir);
+    let address = hex::decode(repo_address.trim_start_matches("0x"))
+        .expect("failed to decode address")
+        .try_into()
+        .expect("address should be 20 bytes");
+
+    let evm = EvmBuilder::new("eth", address, Rc::new(RecordingGit::new(false)))
+        .wallet(Wallet::PrivateKey(
+            "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".to_string(),
+        ))
+        .rpc("http://127.0.0.1:8545/")
+        .build()
+        .expect("failed to build remote helper");
+
+    let references = evm.list(false).expect("failed to list");
+    assert!(references.contains(&Reference::KeyValue {
+        key: Keys::ObjectFormat,
+        value: "sha1".to_string(),
+    }));
+}
@@ -1,11 +1,69 @@
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Set by `option verbosity 0` (git's --quiet) or GITDEM_QUIET, only errors reach stderr then
+static QUIET: AtomicBool = AtomicBool::new(false);
+// Set by GITDEM_OUTPUT=json, user-facing output is then one JSON event per line for wrappers to parse
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+pub fn set_json_output(json: bool) {
+    JSON_OUTPUT.store(json, Ordering::Relaxed);
+}
+
+pub fn is_json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+// The prose is kept under "message" so nothing is lost for events without fields
+pub fn format_event(event: &str, fields: Vec<(&str, Value)>, message: &str) -> String {
+    let mut object = Map::new();
+    object.insert("event".to_string(), Value::from(event));
+    for (key, value) in fields {
+        object.insert(key.to_string(), value);
+    }
+    object.insert("message".to_string(), Value::from(message));
+    Value::Object(object).to_string()
+}
+
 // Git's remote helper protocol uses stderr as the user-facing output.
-// This macro prints to stderr with a "remote:" prefix.
+// This macro prints to stderr with a "remote:" prefix, unless quiet.
 // It also prints to the log with a "[user-facing]" prefix.
+// Messages worth parsing name an event and its fields: print_user!(event: "fetch", got = n; "got {}", n)
 #[macro_export]
 macro_rules! print_user {
-    ($($arg:tt)*) => {
+    (event: $event:literal $(, $key:ident = $value:expr)* ; $($arg:tt)*) => {{
         let msg = format!($($arg)*);
         log::info!("[user-facing] remote: {}", msg);
-        eprintln!("remote: {}", msg);
+        if !$crate::macros::is_quiet() {
+            if $crate::macros::is_json_output() {
+                let fields = vec![$((stringify!($key), serde_json::json!($value))),*];
+                eprintln!("{}", $crate::macros::format_event($event, fields, &msg));
+            } else {
+                eprintln!("remote: {}", msg);
+            }
+        }
+    }};
+    ($($arg:tt)*) => {
+        $crate::print_user!(event: "message"; $($arg)*)
     };
 }
+
+#[test]
+fn test_format_event() {
+    assert_eq!(
+        format_event("fetch", vec![("got", Value::from(3))], "got 3 new objects"),
+        r#"{"event":"fetch","got":3,"message":"got 3 new objects"}"#
+    );
+    assert_eq!(
+        format_event("message", vec![], "say \"hi\""),
+        r#"{"event":"message","message":"say \"hi\""}"#
+    );
+}
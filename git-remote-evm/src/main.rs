@@ -3,21 +3,32 @@
 mod args;
 mod cli;
 mod core;
-#[cfg(test)]
+#[cfg(all(test, feature = "anvil-tests"))]
 mod e2e_tests;
 mod macros;
 
+use alloy::primitives::Address;
 use args::Args;
 use cli::CLI;
-use core::git::Git;
-use core::kv_source::EnvSource;
-use core::remote_helper::executor::Background;
-use core::remote_helper::{error::RemoteHelperError, evm::Evm};
+use core::address::verify_checksum;
+use core::git::{Git, GitConfigSource};
+use core::kv_source::{EnvSource, KeyValueSource};
+use core::remote_helper::blob_store::Offload;
+use core::remote_helper::config::{Config, RedactedRpc, Wallet};
+use core::remote_helper::executor::FeeBump;
+use core::remote_helper::interrupt;
+use core::remote_helper::object_cache::ObjectCache;
+use core::remote_helper::object_filter::{OBJECT_FILTER_FILE, ObjectFilter};
+use core::remote_helper::{
+    builder::EvmBuilder,
+    error::RemoteHelperError,
+    evm::{Evm, Settings},
+};
 use flexi_logger::{FileSpec, Logger, WriteMode};
 use log::{debug, error, warn};
 use std::error::Error;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 // Remote helpers are run by git
@@ -25,6 +36,14 @@ use std::rc::Rc;
 #[cfg(debug_assertions)]
 const DEBUG_ENV_VAR: &str = "DEBUG_WAIT";
 const GIT_DIR_ENV_VAR: &str = "GIT_DIR";
+// Takes precedence over the address from the remote's url
+const CONTRACT_ADDRESS_ENV_VAR: &str = "GITDEM_CONTRACT_ADDRESS";
+// Suppresses user-facing output, same as git's --quiet
+const QUIET_ENV_VAR: &str = "GITDEM_QUIET";
+// "json" makes user-facing output machine readable, the git protocol on stdout is unaffected
+const OUTPUT_ENV_VAR: &str = "GITDEM_OUTPUT";
+// Prints the resolved configuration and exits before talking to git or the chain
+const PRINT_CONFIG_ENV_VAR: &str = "GITDEM_PRINT_CONFIG";
 
 fn setup_panic_hook() {
     let default_hook = std::panic::take_hook();
@@ -42,55 +61,252 @@ fn setup_panic_hook() {
     }));
 }
 
-fn construct_remote_helper(args: Args) -> Result<Evm, RemoteHelperError> {
-    use core::git::SystemGit;
-    use core::remote_helper::config::Config;
+fn parse_address(value: &str) -> Result<[u8; 20], RemoteHelperError> {
+    let err = RemoteHelperError::Invalid {
+        what: format!(
+            "address in {} (it takes precedence over the remote url)",
+            CONTRACT_ADDRESS_ENV_VAR
+        ),
+        value: value.to_string(),
+    };
 
-    debug!("using evm remote helper");
-    let git = Rc::new(SystemGit::new(args.directory().clone()));
+    let address_str = value.trim().strip_prefix("0x").ok_or(err.clone())?;
+    let address = hex::decode(address_str).map_err(|_| err.clone())?;
+    let address: &[u8; 20] = address.as_array().ok_or(err)?;
+    verify_checksum(address_str)?;
+    Ok(*address)
+}
 
-    let git_version = git.version()?;
-    debug!("git version: {}", git_version);
-    if git_version.major < 3 && git_version.minor < 42 {
-        warn!("sha256 has been fully supported since git 2.42.0, unexpected results may occur");
+fn address_from_env() -> Result<Option<[u8; 20]>, RemoteHelperError> {
+    address_from_value(std::env::var(CONTRACT_ADDRESS_ENV_VAR).ok().as_deref())
+}
+
+// Takes the variable's value so tests don't have to change the process environment
+fn address_from_value(value: Option<&str>) -> Result<Option<[u8; 20]>, RemoteHelperError> {
+    value.map(parse_address).transpose()
+}
+
+// For flags set through the environment, e.g. GITDEM_QUIET=0 leaves output on
+fn is_enabled(value: &str) -> bool {
+    !matches!(value.trim(), "" | "0" | "false" | "no" | "off")
+}
+
+// Git always sets it, fall back to the current directory when the helper is run by hand
+fn git_dir() -> PathBuf {
+    match std::env::var(GIT_DIR_ENV_VAR) {
+        Ok(value) => PathBuf::from(value),
+        Err(_) => {
+            debug!(
+                "{} is not set, using the current directory",
+                GIT_DIR_ENV_VAR
+            );
+            PathBuf::from(".")
+        }
     }
+}
 
-    let runtime = tokio::runtime::Builder::new_current_thread()
-        .enable_all()
-        .build()
-        .map_err(|e| RemoteHelperError::Failure {
-            action: "creating runtime".to_string(),
-            details: Some(e.to_string()),
-        })?;
-    let env_source = Rc::new(EnvSource::new());
-    let config = Config::new(args.protocol().to_string(), vec![env_source, git.clone()]);
-
-    let address = if let Some(address) = args.address() {
-        *address
+// Environment variables come first so a single run can override git config, e.g.
+// GITDEM_EVM_ETH_RPC over evm.eth.rpc, see test_env_over_git_config
+fn construct_config(args: &Args, git: Rc<dyn KeyValueSource>) -> Config {
+    let env_source = Rc::new(EnvSource::default());
+    Config::new(
+        args.protocol().to_string(),
+        args.remote_name().map(|name| name.to_string()),
+        vec![env_source, git],
+    )
+}
+
+// libgit2 only reads sha1 repositories, sha256 ones spawn git like the default build
+fn open_git(args: &Args) -> Result<Rc<dyn Git>, RemoteHelperError> {
+    #[cfg(feature = "libgit2")]
+    if let Some(git) = core::git::libgit2::Libgit2Git::open(args.directory().clone())? {
+        return Ok(Rc::new(git));
+    }
+    Ok(Rc::new(core::git::SystemGit::new(args.directory().clone())))
+}
+
+fn resolve_config(args: &Args, git: Rc<dyn Git>) -> Result<Config, RemoteHelperError> {
+    let config = construct_config(args, git);
+    match config.get_config_scope()? {
+        Some(scope) => {
+            debug!("reading git config from the {} scope only", scope);
+            let scoped = GitConfigSource::new_scoped(args.directory().clone(), scope);
+            Ok(construct_config(args, Rc::new(scoped)))
+        }
+        None => Ok(config),
+    }
+}
+
+// Precedence: GITDEM_CONTRACT_ADDRESS, then the url passed by git, then the saved remote's url
+fn resolve_address(args: &Args, git: &dyn Git) -> Result<[u8; 20], RemoteHelperError> {
+    if let Some(address) = address_from_env()? {
+        debug!("using address from {}", CONTRACT_ADDRESS_ENV_VAR);
+        Ok(address)
+    } else if let Some(address) = args.address() {
+        Ok(*address)
     } else {
         git.get_address(
             args.protocol(),
             args.remote_name().ok_or(RemoteHelperError::Missing {
                 what: "remote name".to_string(),
             })?,
-        )?
+        )
+    }
+}
+
+// What the helper would run with after layering env, git config and defaults
+fn describe_config(
+    args: &Args,
+    config: &Config,
+    address: [u8; 20],
+    is_sha256: bool,
+) -> Result<Vec<(&'static str, String)>, RemoteHelperError> {
+    let wallet = if config.get_read_only()? {
+        "none (read-only)".to_string()
+    } else if config.has_wallet()? {
+        match config.get_wallet()? {
+            Wallet::Keypair(path) => format!("keypair at {}", path.display()),
+            Wallet::Environment => "environment".to_string(),
+            Wallet::Browser => "browser".to_string(),
+            #[cfg(test)]
+            Wallet::PrivateKey(_) => "private key".to_string(),
+        }
+    } else {
+        "none".to_string()
     };
+    let mut entries = vec![
+        ("protocol", args.protocol().to_string()),
+        ("remote", args.remote_name().unwrap_or("none").to_string()),
+        ("rpc", RedactedRpc(&config.get_rpc()?).to_string()),
+        ("wallet", wallet),
+    ];
+    if let Some(proxy) = config.get_rpc_proxy()? {
+        entries.push(("proxy", RedactedRpc(&proxy).to_string()));
+    }
+    if let Some(Wallet::Keypair(path)) = config.get_sponsor()? {
+        entries.push(("sponsor", format!("keypair at {}", path.display())));
+    }
+    if let Some(dir) = config.get_cache_dir()? {
+        entries.push(("object cache", dir.display().to_string()));
+    }
+    entries.push(("address", Address::from(address).to_checksum(None)));
+    let object_format = if is_sha256 { "sha256" } else { "sha1" };
+    entries.push(("object format", object_format.to_string()));
+    Ok(entries)
+}
+
+fn print_config(args: &Args) -> Result<(), RemoteHelperError> {
+    let git = open_git(args)?;
+    let config = resolve_config(args, git.clone())?;
+    let address = resolve_address(args, git.as_ref())?;
+    // Asked for explicitly, so unlike print_user! it's shown even when quiet
+    for (key, value) in describe_config(args, &config, address, git.is_sha256()?)? {
+        let msg = format!("{}: {}", key, value);
+        if macros::is_json_output() {
+            let fields = vec![("key", key.into()), ("value", value.into())];
+            eprintln!("{}", macros::format_event("config", fields, &msg));
+        } else {
+            eprintln!("remote: {}", msg);
+        }
+    }
+    Ok(())
+}
 
-    let executor = runtime.block_on(Background::new(
-        config.get_wallet()?,
-        &config.get_rpc()?,
-        address,
-    ))?;
+fn construct_remote_helper(args: Args) -> Result<Evm, RemoteHelperError> {
+    debug!("using evm remote helper");
+    let git = open_git(&args)?;
+    let config = resolve_config(&args, git.clone())?;
 
-    Evm::new(runtime, Box::new(executor), git)
+    let git_version = git.version()?;
+    debug!("git version: {}", git_version);
+    if !git_version.supports_sha256() && !config.get_suppress_version_warning()? {
+        warn!("sha256 has been fully supported since git 2.42.0, unexpected results may occur");
+    }
+
+    let address = resolve_address(&args, git.as_ref())?;
+
+    let offload = match config.get_blob_store()? {
+        Some(kind) => {
+            let threshold = config.get_offload_threshold()?;
+            debug!("offloading objects above {} bytes to {:?}", threshold, kind);
+            Some(Offload::new(kind, threshold))
+        }
+        None => None,
+    };
+
+    let fee_bump = match config.get_max_fee_gwei()? {
+        Some(max_fee_gwei) => {
+            let fee_bump = FeeBump::new(
+                config.get_fee_bump_interval()? as u64,
+                config.get_fee_bump_percent()? as u128,
+                max_fee_gwei as u128,
+            );
+            debug!("bumping fees of stuck transactions: {:?}", fee_bump);
+            Some(fee_bump)
+        }
+        None => None,
+    };
+
+    let settings = Settings {
+        strict_balance_check: config.get_strict_balance_check()?,
+        use_export: config.get_use_export()?,
+        max_object_bytes: match config.get_allow_large_objects()? {
+            true => None,
+            false => Some(config.get_max_object_bytes()?),
+        },
+        max_fetch_objects: Some(config.get_max_fetch_objects()?),
+        read_only: config.get_read_only()?,
+        default_branch: config.get_default_branch()?,
+        object_filter: ObjectFilter::load(Path::new(OBJECT_FILTER_FILE))?,
+        delta_objects: config.get_delta_objects()?,
+        object_cache: config
+            .get_cache_dir()?
+            .map(|dir| ObjectCache::new(&dir, address)),
+    };
+
+    // Clones and fetches don't sign anything, so they work before a wallet is set up
+    let mut builder = EvmBuilder::new(args.protocol(), address, git);
+    if settings.read_only {
+        debug!("remote is read-only, not loading a wallet");
+    } else if config.has_wallet()? {
+        builder = builder
+            .wallet(config.get_wallet()?)
+            .sponsor(config.get_sponsor()?);
+    } else {
+        debug!("no wallet configured, only fetching is possible");
+    }
+    builder
+        .rpc(&config.get_rpc()?)
+        .rpc_headers(config.get_rpc_headers()?)
+        .rpc_proxy(config.get_rpc_proxy()?)
+        .offload(offload)
+        .fee_bump(fee_bump)
+        .worker_threads(config.get_worker_threads()?)
+        .settings(settings)
+        .build()
 }
 
 fn exit_with_error(msg: &str, e: Box<dyn Error>) -> ! {
     error!("{}: {}", msg, e);
-    eprintln!("remote: {}", e);
+    if macros::is_json_output() {
+        eprintln!("{}", macros::format_event("error", vec![], &e.to_string()));
+    } else {
+        eprintln!("remote: {}", e);
+    }
     std::process::exit(1);
 }
 
+// Exit status of a process killed by SIGINT, git reports the remote as failed
+fn exit_aborted() -> ! {
+    error!("interrupted");
+    if macros::is_json_output() {
+        eprintln!("{}", macros::format_event("aborted", vec![], "aborted"));
+    } else {
+        eprintln!("remote: aborted");
+    }
+    std::process::exit(130);
+}
+
 fn main() {
     let _logger = Logger::try_with_str("trace")
         .expect("failed to create logger")
@@ -100,6 +316,19 @@ fn main() {
         .unwrap_or_else(|e| exit_with_error("failed to start logger", e.into()));
 
     setup_panic_hook();
+    interrupt::install()
+        .unwrap_or_else(|e| exit_with_error("failed to handle interrupts", e.into()));
+
+    if let Ok(value) = std::env::var(QUIET_ENV_VAR) {
+        macros::set_quiet(is_enabled(&value));
+    }
+    if let Ok(value) = std::env::var(OUTPUT_ENV_VAR) {
+        match value.trim() {
+            "json" => macros::set_json_output(true),
+            "" | "text" => {}
+            other => warn!("unknown {}: {}, using text", OUTPUT_ENV_VAR, other),
+        }
+    }
 
     #[cfg(debug_assertions)]
     if std::env::var(DEBUG_ENV_VAR).is_ok() {
@@ -107,16 +336,17 @@ fn main() {
         std::thread::sleep(std::time::Duration::from_secs(10));
     }
 
-    let git_dir_var = std::env::var(GIT_DIR_ENV_VAR).unwrap_or_else(|e| {
-        exit_with_error("failed to get git dir", e.into());
-    });
-    let git_dir = PathBuf::from(git_dir_var);
-
     let cmd_args = std::env::args().collect::<Vec<String>>();
-    let args = Args::parse(&cmd_args, git_dir)
+    let args = Args::parse(&cmd_args, git_dir())
         .unwrap_or_else(|e| exit_with_error("failed to collect args", e.into()));
     debug!("running with {:?}", args);
 
+    if std::env::var(PRINT_CONFIG_ENV_VAR).is_ok_and(|value| is_enabled(&value)) {
+        print_config(&args)
+            .unwrap_or_else(|e| exit_with_error("failed to resolve config", e.into()));
+        return;
+    }
+
     let remote_helper = construct_remote_helper(args)
         .unwrap_or_else(|e| exit_with_error("failed to construct remote helper", e.into()));
 
@@ -124,6 +354,183 @@ fn main() {
     let mut stdout = io::stdout();
 
     let mut cli = CLI::new(Box::new(remote_helper), &mut stdin, &mut stdout);
-    cli.run()
-        .unwrap_or_else(|e| exit_with_error("failed to run cli", e.into()));
+    if let Err(e) = cli.run() {
+        if interrupt::is_interrupted() {
+            exit_aborted();
+        }
+        exit_with_error("failed to run cli", e.into());
+    }
+}
+
+#[cfg(test)]
+fn get_rpc_for_executable(executable: &str) -> Result<String, RemoteHelperError> {
+    use core::kv_source::MockKeyValueSource;
+
+    let cmd_args = vec![executable.to_string(), "origin".to_string()];
+    let args = Args::parse(&cmd_args, PathBuf::from("/some-dir")).expect("failed to parse args");
+
+    let mut git = MockKeyValueSource::new();
+    git.expect_read().returning(|_| Ok(None));
+    construct_config(&args, Rc::new(git)).get_rpc()
+}
+
+#[test]
+fn test_default_rpc_from_executable() {
+    use core::remote_helper::config::{DEFAULT_RPC_ARB1, DEFAULT_RPC_AVAX, DEFAULT_RPC_ETH};
+
+    let rpc = get_rpc_for_executable("git-remote-eth").expect("failed to get rpc");
+    assert_eq!(rpc, DEFAULT_RPC_ETH);
+
+    let rpc = get_rpc_for_executable("/usr/bin/git-remote-arb1").expect("failed to get rpc");
+    assert_eq!(rpc, DEFAULT_RPC_ARB1);
+
+    let rpc = get_rpc_for_executable("git-remote-avax").expect("failed to get rpc");
+    assert_eq!(rpc, DEFAULT_RPC_AVAX);
+
+    let err = get_rpc_for_executable("git-remote-unknown").expect_err("expected error");
+    assert_eq!(
+        err,
+        RemoteHelperError::Missing {
+            what: "rpc".to_string(),
+        }
+    );
+}
+
+#[test]
+fn test_parse_address() {
+    let address = parse_address("0xc0ffee254729296a45a3885639AC7E10F9d54979")
+        .expect("failed to parse address");
+    assert_eq!(
+        hex::encode(address),
+        "c0ffee254729296a45a3885639ac7e10f9d54979"
+    );
+
+    let invalid = vec![
+        "",
+        "c0ffee254729296a45a3885639AC7E10F9d54979",
+        "0xc0ffee254729296a45a3885639AC7E10F9d5497",
+        "0xc0ffee254729296a45a3885639AC7E10F9d54979ab",
+        "0xzzffee254729296a45a3885639AC7E10F9d54979",
+    ];
+    for value in invalid {
+        let err = parse_address(value).expect_err("expected error");
+        assert_eq!(
+            err,
+            RemoteHelperError::Invalid {
+                what: format!(
+                    "address in {} (it takes precedence over the remote url)",
+                    CONTRACT_ADDRESS_ENV_VAR
+                ),
+                value: value.to_string(),
+            }
+        );
+    }
+
+    parse_address("0xc0ffee254729296a45a3885639AC7E10F9d5497A")
+        .expect_err("should fail because of invalid checksum");
+}
+
+#[test]
+fn test_is_enabled() {
+    for value in ["1", "true", "yes", " on "] {
+        assert!(is_enabled(value), "{:?} should be enabled", value);
+    }
+    for value in ["", "0", "false", "no", "off"] {
+        assert!(!is_enabled(value), "{:?} should be disabled", value);
+    }
+}
+
+#[test]
+fn test_address_from_value() {
+    assert_eq!(address_from_value(None).expect("failed to read"), None);
+
+    let address = address_from_value(Some("0x0000000000000000000000000000000000000001"))
+        .expect("failed to read")
+        .expect("address should be set");
+    assert_eq!(address[19], 1);
+
+    address_from_value(Some("0x01")).expect_err("should fail with an invalid address");
+}
+
+#[test]
+fn test_describe_config() {
+    use core::kv_source::MockKeyValueSource;
+
+    let cmd_args = vec!["git-remote-eth".to_string(), "origin".to_string()];
+    let args = Args::parse(&cmd_args, PathBuf::from("/some-dir")).expect("failed to parse args");
+    let mut address = [0u8; 20];
+    address[19] = 1;
+
+    let mut git = MockKeyValueSource::new();
+    git.expect_read().returning(|key| {
+        Ok(match key {
+            "evm.eth.rpc" => Some("https://rpc.example.com/v2/0123456789abcdef0123".to_string()),
+            "evm.wallet" => Some("keypair".to_string()),
+            "evm.keypair" => Some("/keys/wallet".to_string()),
+            _ => None,
+        })
+    });
+    let config = construct_config(&args, Rc::new(git));
+    let entries = describe_config(&args, &config, address, true).expect("failed to describe");
+    assert_eq!(
+        entries,
+        vec![
+            ("protocol", "eth".to_string()),
+            ("remote", "origin".to_string()),
+            ("rpc", "https://rpc.example.com/v2/***".to_string()),
+            ("wallet", "keypair at /keys/wallet".to_string()),
+            (
+                "address",
+                "0x0000000000000000000000000000000000000001".to_string()
+            ),
+            ("object format", "sha256".to_string()),
+        ]
+    );
+
+    let mut git = MockKeyValueSource::new();
+    git.expect_read().returning(|key| {
+        Ok(match key {
+            "evm.read-only" => Some("true".to_string()),
+            _ => None,
+        })
+    });
+    let config = construct_config(&args, Rc::new(git));
+    let entries = describe_config(&args, &config, address, false).expect("failed to describe");
+    assert_eq!(entries[3], ("wallet", "none (read-only)".to_string()));
+    assert_eq!(entries[5], ("object format", "sha1".to_string()));
+}
+
+#[test]
+fn test_env_over_git_config() {
+    use std::process::Command;
+
+    let repo_dir = tempfile::tempdir().expect("failed to create temp dir");
+    for git_args in [
+        vec!["init"],
+        vec!["config", "evm.envorder.rpc", "https://git-rpc.com"],
+    ] {
+        let output = Command::new("git")
+            .args(git_args)
+            .current_dir(repo_dir.path())
+            .output()
+            .expect("failed to run git");
+        assert!(output.status.success());
+    }
+
+    // A protocol that no other test uses, so the variable doesn't leak into them
+    let cmd_args = vec!["git-remote-envorder".to_string(), "origin".to_string()];
+    let args = Args::parse(&cmd_args, repo_dir.path().to_path_buf()).expect("failed to parse args");
+    let git = Rc::new(core::git::SystemGit::new(repo_dir.path().to_path_buf()));
+
+    let rpc = construct_config(&args, git.clone()).get_rpc();
+    assert_eq!(rpc, Ok("https://git-rpc.com".to_string()));
+
+    unsafe {
+        std::env::set_var("GITDEM_EVM_ENVORDER_RPC", "https://env-rpc.com");
+    }
+    let rpc = construct_config(&args, git).get_rpc();
+    unsafe {
+        std::env::remove_var("GITDEM_EVM_ENVORDER_RPC");
+    }
+    assert_eq!(rpc, Ok("https://env-rpc.com".to_string()));
 }
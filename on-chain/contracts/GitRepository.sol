@@ -2,6 +2,8 @@
 pragma solidity ^0.8.28;
 
 import "@openzeppelin/contracts/access/Ownable2Step.sol";
+import "@openzeppelin/contracts/utils/cryptography/ECDSA.sol";
+import "@openzeppelin/contracts/utils/cryptography/MessageHashUtils.sol";
 import "./external/SHA1.sol";
 
 /// @title Git Repository
@@ -24,12 +26,39 @@ contract GitRepository is Ownable2Step {
     mapping(bytes32 => bytes) _objects;
     /// @dev The hashes of all objects
     bytes32[] _objectHashes;
+    /// @dev Padded SHA1 or SHA256 hash -> CID of the object data stored off-chain
+    mapping(bytes32 => string) _offloadedObjects;
+    /// @dev Padded SHA1 or SHA256 hash -> hash of the object its delta applies to
+    mapping(bytes32 => bytes32) _deltaBases;
+    /// @dev Padded SHA1 or SHA256 hash -> delta against its base, see _deltaBases
+    mapping(bytes32 => bytes) _deltas;
+
+    /// @dev Bumped whenever the interface used by the remote helper changes.
+    uint256 constant VERSION = 6;
+    /// @dev Bumped whenever the way objects and references are stored changes.
+    uint256 constant FORMAT_VERSION = 2;
+
+    /// @dev Incremented by every relayed call so an owner signature can't be replayed.
+    uint256 public relayNonce;
 
     /// @param isSHA256 Whether to use SHA256 hashes. Once set, it cannot be changed.
     constructor(bool isSHA256) Ownable(msg.sender) {
         _isSHA256 = isSHA256;
     }
 
+    /// @notice Returns the version of the contract's interface.
+    /// @return The interface version.
+    function version() public pure returns (uint256) {
+        return VERSION;
+    }
+
+    /// @notice Returns the version of the storage format.
+    /// @dev Clients refuse repositories with a format newer than they support.
+    /// @return The storage format version.
+    function formatVersion() public pure returns (uint256) {
+        return FORMAT_VERSION;
+    }
+
     /// @notice Sets the default branch of the repository.
     /// @param newDefaultBranch The name of the new default branch.
     function setDefaultBranch(
@@ -40,14 +69,87 @@ contract GitRepository is Ownable2Step {
         defaultBranchRef = string.concat("refs/heads/", newDefaultBranch);
     }
 
+    /// @dev Emitted when a symbolic reference is changed.
+    event SymbolicRefChanged(string name, string target);
+
+    /// @notice Points a symbolic reference at another reference.
+    /// @dev Only HEAD is supported, its target is the default branch.
+    /// @param name The name of the symbolic reference.
+    /// @param target The name of the reference to point at.
+    function setSymbolicRef(
+        string calldata name,
+        string calldata target
+    ) public onlyOwner {
+        _setSymbolicRef(name, target);
+    }
+
+    /// @notice Same as setSymbolicRef, but sent by anyone with the owner's signature.
+    /// @dev Lets a sponsor pay the gas while the change is still authorized by the owner.
+    /// @param name The name of the symbolic reference.
+    /// @param target The name of the reference to point at.
+    /// @param signature The owner's signature, see verifyOwnerSignature.
+    function setSymbolicRefSigned(
+        string calldata name,
+        string calldata target,
+        bytes calldata signature
+    ) public {
+        verifyOwnerSignature(keccak256(abi.encode(name, target)), signature);
+        _setSymbolicRef(name, target);
+    }
+
+    function _setSymbolicRef(
+        string calldata name,
+        string calldata target
+    ) internal {
+        require(
+            keccak256(bytes(name)) == keccak256(bytes("HEAD")),
+            "Only HEAD is supported"
+        );
+        validateRefName(target);
+        defaultBranchRef = target;
+        emit SymbolicRefChanged(name, target);
+    }
+
     /// @notice Retrieves an object by its hash.
+    /// @dev Offloaded and delta objects have no data on-chain, see getObjectCid and getObjectDelta.
     /// @param hash The hash of the object to retrieve.
-    /// @return The object data.
+    /// @return The object data, empty if the object is offloaded or stored as a delta.
     function getObject(bytes32 hash) public view returns (bytes memory) {
-        require(_objects[hash].length > 0, "Object not found");
+        require(objectExists(hash), "Object not found");
         return _objects[hash];
     }
 
+    /// @notice Retrieves the CID of an object stored off-chain.
+    /// @param hash The hash of the object.
+    /// @return The CID of the object data.
+    function getObjectCid(bytes32 hash) public view returns (string memory) {
+        require(
+            bytes(_offloadedObjects[hash]).length > 0,
+            "Object not offloaded"
+        );
+        return _offloadedObjects[hash];
+    }
+
+    /// @notice Retrieves an object stored as a delta against another object.
+    /// @param hash The hash of the object.
+    /// @return The hash of the base object and the delta to apply to it.
+    function getObjectDelta(
+        bytes32 hash
+    ) public view returns (bytes32, bytes memory) {
+        require(_deltas[hash].length > 0, "Object not a delta");
+        return (_deltaBases[hash], _deltas[hash]);
+    }
+
+    /// @notice Checks if an object was added, either on-chain, offloaded or as a delta.
+    /// @param hash The hash of the object.
+    /// @return Whether the object exists.
+    function objectExists(bytes32 hash) internal view returns (bool) {
+        return
+            _objects[hash].length > 0 ||
+            bytes(_offloadedObjects[hash]).length > 0 ||
+            _deltas[hash].length > 0;
+    }
+
     /// @dev Represents a git object.
     struct Object {
         bytes32 hash;
@@ -62,7 +164,7 @@ contract GitRepository is Ownable2Step {
     function addObject(Object calldata object) internal {
         require(object.data.length > 0, "Object is empty");
         require(object.hash != bytes32(0), "Hash is empty");
-        require(_objects[object.hash].length == 0, "Object already exists");
+        require(!objectExists(object.hash), "Object already exists");
 
         bytes32 computedHash = _isSHA256
             ? sha256(object.data)
@@ -74,6 +176,53 @@ contract GitRepository is Ownable2Step {
         emit ObjectAdded(object.hash);
     }
 
+    /// @dev Represents a git object whose data is stored off-chain.
+    struct OffloadedObject {
+        bytes32 hash;
+        string cid;
+    }
+
+    /// @dev Emitted when an offloaded object is added.
+    event ObjectOffloaded(bytes32 hash, string cid);
+
+    /// @notice Adds an object whose data is stored off-chain.
+    /// @dev The data isn't available, so its hash is verified by the reader instead.
+    /// @param object The object hash and CID.
+    function addOffloadedObject(OffloadedObject calldata object) internal {
+        require(bytes(object.cid).length > 0, "CID is empty");
+        require(object.hash != bytes32(0), "Hash is empty");
+        require(!objectExists(object.hash), "Object already exists");
+
+        _offloadedObjects[object.hash] = object.cid;
+        _objectHashes.push(object.hash);
+        emit ObjectOffloaded(object.hash, object.cid);
+    }
+
+    /// @dev Represents a git object stored as a delta against another object.
+    struct DeltaObject {
+        bytes32 hash;
+        bytes32 base;
+        bytes delta;
+    }
+
+    /// @dev Emitted when an object is added as a delta.
+    event ObjectDeltaAdded(bytes32 hash, bytes32 base);
+
+    /// @notice Adds an object stored as a delta against another object.
+    /// @dev The full data isn't available, so its hash is verified by the reader instead.
+    /// @param object The object hash, the hash of its base and the delta.
+    function addDeltaObject(DeltaObject calldata object) internal {
+        require(object.delta.length > 0, "Delta is empty");
+        require(object.hash != bytes32(0), "Hash is empty");
+        require(!objectExists(object.hash), "Object already exists");
+        require(objectExists(object.base), "Base not found");
+
+        _deltaBases[object.hash] = object.base;
+        _deltas[object.hash] = object.delta;
+        _objectHashes.push(object.hash);
+        emit ObjectDeltaAdded(object.hash, object.base);
+    }
+
     /// @notice Returns the hashes of all objects.
     /// @return The hashes of all objects.
     function getObjectHashes() public view returns (bytes32[] memory) {
@@ -163,7 +312,7 @@ contract GitRepository is Ownable2Step {
     /// @param ref The reference to upsert.
     function upsertRef(RefNormal calldata ref) internal {
         require(ref.hash != bytes32(0), "Hash is empty");
-        require(_objects[ref.hash].length > 0, "Object not found");
+        require(objectExists(ref.hash), "Object not found");
         validateRefName(ref.name);
 
         bytes memory nameBytes = bytes(ref.name);
@@ -201,22 +350,61 @@ contract GitRepository is Ownable2Step {
     }
 
     /// @dev A struct representing the data to push to the repository.
+    ///      `expected` holds the hashes references must have before the push, zero if missing.
     struct PushData {
         Object[] objects;
+        OffloadedObject[] offloaded;
+        DeltaObject[] deltas;
         RefNormal[] refs;
+        RefNormal[] expected;
     }
 
     /// @notice Pushes objects and references to the repository.
     /// @param data The data to push to the repository.
     function pushObjectsAndRefs(PushData calldata data) public onlyOwner {
+        _pushObjectsAndRefs(data);
+    }
+
+    /// @notice Same as pushObjectsAndRefs, but sent by anyone with the owner's signature.
+    /// @dev Lets a sponsor pay the gas while the push is still authorized by the owner.
+    /// @param data The data to push to the repository.
+    /// @param signature The owner's signature, see verifyOwnerSignature.
+    function pushObjectsAndRefsSigned(
+        PushData calldata data,
+        bytes calldata signature
+    ) public {
+        verifyOwnerSignature(keccak256(abi.encode(data)), signature);
+        _pushObjectsAndRefs(data);
+    }
+
+    function _pushObjectsAndRefs(PushData calldata data) internal {
         require(
-            data.objects.length > 0 || data.refs.length > 0,
+            data.objects.length > 0 ||
+                data.offloaded.length > 0 ||
+                data.deltas.length > 0 ||
+                data.refs.length > 0,
             "No data to push"
         );
 
+        // Compare-and-set, so a racing push can't be overwritten based on stale state
+        for (uint256 i = 0; i < data.expected.length; i++) {
+            require(
+                _references[keccak256(bytes(data.expected[i].name))] ==
+                    data.expected[i].hash,
+                "Stale reference"
+            );
+        }
+
         for (uint256 i = 0; i < data.objects.length; i++) {
             addObject(data.objects[i]);
         }
+        for (uint256 i = 0; i < data.offloaded.length; i++) {
+            addOffloadedObject(data.offloaded[i]);
+        }
+        // Added last, so a base can be pushed along with the objects stored against it
+        for (uint256 i = 0; i < data.deltas.length; i++) {
+            addDeltaObject(data.deltas[i]);
+        }
 
         for (uint256 i = 0; i < data.refs.length; i++) {
             if (data.refs[i].hash != bytes32(0)) {
@@ -226,4 +414,24 @@ contract GitRepository is Ownable2Step {
             }
         }
     }
+
+    /// @notice Checks that the owner signed a relayed call and consumes the nonce.
+    /// @dev The owner signs (EIP-191) keccak256(abi.encode(chainid, this, relayNonce, payload)).
+    /// @param payload The hash of the call's abi-encoded arguments.
+    /// @param signature The owner's signature.
+    function verifyOwnerSignature(
+        bytes32 payload,
+        bytes calldata signature
+    ) internal {
+        bytes32 digest = MessageHashUtils.toEthSignedMessageHash(
+            keccak256(
+                abi.encode(block.chainid, address(this), relayNonce, payload)
+            )
+        );
+        require(
+            ECDSA.recover(digest, signature) == owner(),
+            "Invalid signature"
+        );
+        relayNonce++;
+    }
 }
@@ -18,6 +18,18 @@ function generateHash(isSHA256: boolean, data?: Uint8Array): Uint8Array {
   return bytes;
 }
 
+const PUSH_DATA_TYPE = "tuple(tuple(bytes32 hash, bytes data)[] objects, tuple(bytes32 hash, string cid)[] offloaded, tuple(bytes32 hash, bytes32 base, bytes delta)[] deltas, tuple(string name, bytes32 hash)[] refs, tuple(string name, bytes32 hash)[] expected)";
+
+async function signRelayed(gitRepository: any, signer: any, payload: string): Promise<string> {
+  const coder = ethers.AbiCoder.defaultAbiCoder();
+  const { chainId } = await ethers.provider.getNetwork();
+  const digest = ethers.keccak256(coder.encode(
+    ["uint256", "address", "uint256", "bytes32"],
+    [chainId, await gitRepository.getAddress(), await gitRepository.relayNonce(), payload],
+  ));
+  return signer.signMessage(ethers.getBytes(digest));
+}
+
 describe("GitRepository", function () {
   async function deployGitRepositoryFixtureBase(isSHA256: boolean) {
     const [owner, otherAccount] = await hre.ethers.getSigners();
@@ -47,7 +59,10 @@ describe("GitRepository", function () {
 
     await gitRepository.pushObjectsAndRefs({
       objects: [{ hash, data }, { hash: otherHash, data: otherData }],
+      offloaded: [],
+      deltas: [],
       refs: [],
+      expected: [],
     });
 
     return { gitRepository, owner, otherAccount, data, hash, otherData, otherHash };
@@ -76,6 +91,18 @@ describe("GitRepository", function () {
       expect(await gitRepository.owner()).to.equal(owner.address);
     });
 
+    it("should report the interface version", async function () {
+      const { gitRepository } = await loadFixture(deployGitRepositoryFixture);
+
+      expect(await gitRepository.version()).to.equal(6);
+    });
+
+    it("should report the storage format version", async function () {
+      const { gitRepository } = await loadFixture(deployGitRepositoryFixture);
+
+      expect(await gitRepository.formatVersion()).to.equal(2);
+    });
+
     it("can transfer ownership with confirmation", async function () {
       const { gitRepository, otherAccount } = await loadFixture(deployGitRepositoryFixture);
 
@@ -129,10 +156,13 @@ describe("GitRepository", function () {
 
         await gitRepository.pushObjectsAndRefs({
           objects: [{ hash, data }],
+          offloaded: [],
+          deltas: [],
           refs: [{
             name: "refs/heads/main",
             hash: hash,
           }],
+          expected: [],
         })
 
         const hashes = await gitRepository.resolveRefs(["refs/heads/main"]);
@@ -155,7 +185,7 @@ describe("GitRepository", function () {
           const refs = await gitRepository.listRefs();
           expect(refs.symbolic.length).to.equal(0);
           expect(refs.normal.length).to.equal(0);
-          expect(refs.kv.length).to.equal(1); // object-format
+          expect(refs.kv.length).to.equal(2); // object-format
         });
 
         it("is included if the default branch exists", async function () {
@@ -165,14 +195,17 @@ describe("GitRepository", function () {
           const hash = generateHash(true, data);
           await gitRepository.pushObjectsAndRefs({
             objects: [{ hash, data }],
+            offloaded: [],
+            deltas: [],
             refs: [{
               name: "refs/heads/main",
               hash: hash,
             }],
+            expected: [],
           })
 
           const refs = await gitRepository.listRefs();
-          expect(refs.symbolic.length).to.equal(1);
+          expect(refs.symbolic.length).to.equal(2);
           expect(refs.symbolic[0].name).to.equal("HEAD");
           expect(refs.symbolic[0].target).to.equal("refs/heads/main");
         });
@@ -185,29 +218,32 @@ describe("GitRepository", function () {
 
           await gitRepository.pushObjectsAndRefs({
             objects: [{ hash, data }],
+            offloaded: [],
+            deltas: [],
             refs: [{
               name: "refs/heads/other",
               hash: hash,
             }],
+            expected: [],
           })
 
           const newRefs = await gitRepository.listRefs();
           expect(newRefs.symbolic.length).to.equal(0);
-          expect(newRefs.normal.length).to.equal(1); // refs/heads/other
-          expect(newRefs.kv.length).to.equal(1); // object-format
+          expect(newRefs.normal.length).to.equal(2); // refs/heads/other
+          expect(newRefs.kv.length).to.equal(2); // object-format
         });
       });
 
       it("has object-format kv ref", async function () {
         const { gitRepository } = await loadFixture(existingObjectFixture);
         const refs = await gitRepository.listRefs();
-        expect(refs.kv.length).to.equal(1);
+        expect(refs.kv.length).to.equal(2);
         expect(refs.kv[0].key).to.equal("object-format");
         expect(refs.kv[0].value).to.equal("sha256");
 
         const { gitRepository: gitRepositorySHA1 } = await loadFixture(existingObjectFixtureSHA1);
         const refsSHA1 = await gitRepositorySHA1.listRefs();
-        expect(refsSHA1.kv.length).to.equal(1);
+        expect(refsSHA1.kv.length).to.equal(2);
         expect(refsSHA1.kv[0].key).to.equal("object-format");
         expect(refsSHA1.kv[0].value).to.equal("sha1");
       });
@@ -217,19 +253,22 @@ describe("GitRepository", function () {
 
         await gitRepository.pushObjectsAndRefs({
           objects: [],
+          offloaded: [],
+          deltas: [],
           refs: [{
             name: "refs/heads/main",
             hash: hash,
           }],
+          expected: [],
         });
 
         const refs = await gitRepository.listRefs();
 
-        expect(refs.normal.length).to.equal(1);
+        expect(refs.normal.length).to.equal(2);
         expect(refs.normal[0].name).to.equal("refs/heads/main");
         expect(ethers.getBytes(refs.normal[0].hash)).to.deep.equal(hash);
 
-        expect(refs.symbolic.length).to.equal(1);
+        expect(refs.symbolic.length).to.equal(2);
         expect(refs.symbolic[0].name).to.equal("HEAD");
         expect(refs.symbolic[0].target).to.equal("refs/heads/main");
       });
@@ -239,6 +278,8 @@ describe("GitRepository", function () {
 
         await gitRepository.pushObjectsAndRefs({
           objects: [],
+          offloaded: [],
+          deltas: [],
           refs: [{
             name: "refs/heads/main",
             hash: hash,
@@ -246,6 +287,7 @@ describe("GitRepository", function () {
             name: "refs/heads/other",
             hash: otherHash,
           }],
+          expected: [],
         });
 
         const refs = await gitRepository.listRefs();
@@ -270,15 +312,18 @@ describe("GitRepository", function () {
 
         await gitRepository.pushObjectsAndRefs({
           objects: [],
+          offloaded: [],
+          deltas: [],
           refs: [{
             name: "refs/heads/main",
             hash: hash,
           }],
+          expected: [],
         });
 
         const refs = await gitRepository.listRefs();
 
-        expect(refs.normal.length).to.equal(1);
+        expect(refs.normal.length).to.equal(2);
         expect(refs.normal[0].name).to.equal("refs/heads/main");
         expect(ethers.getBytes(refs.normal[0].hash)).to.deep.equal(hash);
       });
@@ -290,15 +335,18 @@ describe("GitRepository", function () {
 
         await gitRepository.pushObjectsAndRefs({
           objects: [],
+          offloaded: [],
+          deltas: [],
           refs: [{
             name: "refs/heads/main",
             hash: hash,
           }],
+          expected: [],
         });
 
         const refs = await gitRepository.connect(otherAccount).listRefs();
 
-        expect(refs.normal.length).to.equal(1);
+        expect(refs.normal.length).to.equal(2);
         expect(refs.normal[0].name).to.equal("refs/heads/main");
         expect(ethers.getBytes(refs.normal[0].hash)).to.deep.equal(hash);
       });
@@ -316,7 +364,10 @@ describe("GitRepository", function () {
         }
         await gitRepository.pushObjectsAndRefs({
           objects: [],
+          offloaded: [],
+          deltas: [],
           refs: refsToPush,
+          expected: [],
         });
 
         const refs = await gitRepository.listRefs();
@@ -339,14 +390,17 @@ describe("GitRepository", function () {
 
       await gitRepository.pushObjectsAndRefs({
         objects: [{ hash, data }],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/main",
           hash: hash,
         }],
+        expected: [],
       })
 
       const objectHashes = await gitRepository.getObjectHashes();
-      expect(objectHashes.length).to.equal(1);
+      expect(objectHashes.length).to.equal(2);
       expect(ethers.getBytes(objectHashes[0])).to.deep.equal(hash);
     });
 
@@ -358,14 +412,17 @@ describe("GitRepository", function () {
 
       await gitRepository.pushObjectsAndRefs({
         objects: [{ hash, data }],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/main",
           hash: hash,
         }],
+        expected: [],
       })
 
       const objectHashes = await gitRepository.getObjectHashes();
-      expect(objectHashes.length).to.equal(1);
+      expect(objectHashes.length).to.equal(2);
       expect(ethers.getBytes(objectHashes[0])).to.deep.equal(hash);
     });
 
@@ -374,7 +431,10 @@ describe("GitRepository", function () {
 
       await expect(gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [],
+        expected: [],
       })).to.be.revertedWith("No data to push");
     });
 
@@ -386,10 +446,13 @@ describe("GitRepository", function () {
 
       await expect(gitRepository.pushObjectsAndRefs({
         objects: [{ hash, data }],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/main",
           hash: hash,
         }],
+        expected: [],
       })).to.be.revertedWith("Object is empty");
     });
 
@@ -399,10 +462,13 @@ describe("GitRepository", function () {
       const hash = generateHash(true);
       await expect(gitRepository.pushObjectsAndRefs({
         objects: [], // No objects
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/main",
           hash: hash,
         }],
+        expected: [],
       })).to.be.revertedWith("Object not found");
     });
 
@@ -413,10 +479,13 @@ describe("GitRepository", function () {
       const hash = generateHash(true, data);
       await expect(gitRepository.connect(otherAccount).pushObjectsAndRefs({
         objects: [{ hash, data }],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/main",
           hash: hash,
         }],
+        expected: [],
       })).to.be.revertedWithCustomError(gitRepository, "OwnableUnauthorizedAccount")
         .withArgs(otherAccount.address);
     });
@@ -429,10 +498,13 @@ describe("GitRepository", function () {
 
       await expect(gitRepository.pushObjectsAndRefs({
         objects: [{ hash, data }],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "",
           hash: hash,
         }],
+        expected: [],
       })).to.be.revertedWith("Name is invalid");
     });
 
@@ -444,10 +516,13 @@ describe("GitRepository", function () {
 
       await gitRepository.pushObjectsAndRefs({
         objects: [{ hash, data }],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/main",
           hash: hash,
         }],
+        expected: [],
       });
       const hashes = await gitRepository.resolveRefs(["refs/heads/main"]);
       expect(ethers.getBytes(hashes[0])).to.deep.equal(hash);
@@ -457,10 +532,13 @@ describe("GitRepository", function () {
 
       await gitRepository.pushObjectsAndRefs({
         objects: [{ hash: otherHash, data: otherData }],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/main",
           hash: otherHash,
         }],
+        expected: [],
       });
       const newHashes = await gitRepository.resolveRefs(["refs/heads/main"]);
       expect(ethers.getBytes(newHashes[0])).to.deep.equal(otherHash);
@@ -472,23 +550,29 @@ describe("GitRepository", function () {
       const refName = "refs/heads/main";
       await gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: refName,
           hash: hash,
         }],
+        expected: [],
       });
       const refsBefore = await gitRepository.listRefs();
 
-      expect(refsBefore.normal.length).to.equal(1);
+      expect(refsBefore.normal.length).to.equal(2);
       expect(refsBefore.normal[0].name).to.equal(refName);
       expect(ethers.getBytes(refsBefore.normal[0].hash)).to.deep.equal(hash);
 
       await gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: refName,
           hash: new Uint8Array(32),
         }],
+        expected: [],
       });
       const refsAfter = await gitRepository.listRefs();
 
@@ -500,6 +584,8 @@ describe("GitRepository", function () {
 
       await gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/one",
           hash: hash,
@@ -507,18 +593,22 @@ describe("GitRepository", function () {
           name: "refs/heads/two",
           hash: otherHash,
         }],
+        expected: [],
       });
 
       await gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/one",
           hash: new Uint8Array(32),
         }],
+        expected: [],
       });
       const refs = await gitRepository.listRefs();
 
-      expect(refs.normal.length).to.equal(1);
+      expect(refs.normal.length).to.equal(2);
       expect(refs.normal[0].name).to.equal("refs/heads/two");
       expect(ethers.getBytes(refs.normal[0].hash)).to.deep.equal(otherHash);
     });
@@ -528,6 +618,8 @@ describe("GitRepository", function () {
 
       await gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/one",
           hash: hash,
@@ -535,18 +627,22 @@ describe("GitRepository", function () {
           name: "refs/heads/two",
           hash: otherHash,
         }],
+        expected: [],
       });
 
       await gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/two",
           hash: new Uint8Array(32),
         }],
+        expected: [],
       });
       const refs = await gitRepository.listRefs();
 
-      expect(refs.normal.length).to.equal(1);
+      expect(refs.normal.length).to.equal(2);
       expect(refs.normal[0].name).to.equal("refs/heads/one");
       expect(ethers.getBytes(refs.normal[0].hash)).to.deep.equal(hash);
     });
@@ -556,6 +652,8 @@ describe("GitRepository", function () {
 
       await gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/one",
           hash: hash,
@@ -566,14 +664,18 @@ describe("GitRepository", function () {
           name: "refs/heads/three",
           hash: otherHash,
         }],
+        expected: [],
       });
 
       await gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/two",
           hash: new Uint8Array(32),
         }],
+        expected: [],
       });
       const refs = await gitRepository.listRefs();
 
@@ -589,10 +691,13 @@ describe("GitRepository", function () {
 
       await expect(gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/some-ref",
           hash: new Uint8Array(32),
         }],
+        expected: [],
       })).to.be.revertedWith("No refs");
     });
 
@@ -601,18 +706,24 @@ describe("GitRepository", function () {
 
       await gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/some-ref",
           hash: hash,
         }],
+        expected: [],
       });
 
       await expect(gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{
           name: "refs/heads/other",
           hash: new Uint8Array(32),
         }],
+        expected: [],
       })).to.be.revertedWith("Ref not found");
     });
 
@@ -624,15 +735,191 @@ describe("GitRepository", function () {
 
       await expect(gitRepository.pushObjectsAndRefs({
         objects: [{ hash, data }],
+        offloaded: [],
+        deltas: [],
         refs: [{ name: "refs/heads/main", hash }],
+        expected: [],
       })).to.emit(gitRepository, "RefChanged").withArgs("refs/heads/main", hash, new Uint8Array(32))
         .and.emit(gitRepository, "ObjectAdded").withArgs(hash);
 
       await expect(gitRepository.pushObjectsAndRefs({
         objects: [],
+        offloaded: [],
+        deltas: [],
         refs: [{ name: "refs/heads/main", hash: new Uint8Array(32) }],
+        expected: [],
       })).to.emit(gitRepository, "RefChanged").withArgs("refs/heads/main", new Uint8Array(32), hash);
     });
+
+    it("updates refs when the expected hashes match", async function () {
+      const { gitRepository, hash, otherHash } = await loadFixture(existingObjectFixture);
+
+      await gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [],
+        deltas: [],
+        refs: [{ name: "refs/heads/main", hash }],
+        expected: [{ name: "refs/heads/main", hash: new Uint8Array(32) }],
+      });
+      await gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [],
+        deltas: [],
+        refs: [{ name: "refs/heads/main", hash: otherHash }],
+        expected: [{ name: "refs/heads/main", hash }],
+      });
+
+      const hashes = await gitRepository.resolveRefs(["refs/heads/main"]);
+      expect(ethers.getBytes(hashes[0])).to.deep.equal(otherHash);
+    });
+
+    it("can't push based on a stale reference", async function () {
+      const { gitRepository, hash, otherHash } = await loadFixture(existingObjectFixture);
+
+      await gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [],
+        deltas: [],
+        refs: [{ name: "refs/heads/main", hash }],
+        expected: [],
+      });
+
+      // Someone else pushed since the ref was read as missing
+      await expect(gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [],
+        deltas: [],
+        refs: [{ name: "refs/heads/main", hash: otherHash }],
+        expected: [{ name: "refs/heads/main", hash: new Uint8Array(32) }],
+      })).to.be.revertedWith("Stale reference");
+      await expect(gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [],
+        deltas: [],
+        refs: [{ name: "refs/heads/main", hash: otherHash }],
+        expected: [{ name: "refs/heads/main", hash: otherHash }],
+      })).to.be.revertedWith("Stale reference");
+    });
+  });
+
+  describe("Offloaded objects", function () {
+    const cid = "bafkreihdwdcefgh4dqkjv67uzcmw7ojee6xedzdetojuzjevtenxquvyku";
+
+    it("can push and point a ref at one", async function () {
+      const { gitRepository } = await loadFixture(deployGitRepositoryFixture);
+
+      const hash = generateHash(true);
+      await expect(gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [{ hash, cid }],
+        deltas: [],
+        refs: [{ name: "refs/heads/main", hash }],
+        expected: [],
+      })).to.emit(gitRepository, "ObjectOffloaded").withArgs(hash, cid);
+
+      expect(await gitRepository.getObject(hash)).to.equal("0x");
+      expect(await gitRepository.getObjectCid(hash)).to.equal(cid);
+      expect(await gitRepository.getObjectHashes()).to.deep.equal([ethers.hexlify(hash)]);
+      expect(await gitRepository.resolveRefs(["refs/heads/main"])).to.deep.equal([ethers.hexlify(hash)]);
+    });
+
+    it("can't get the cid of an on-chain object", async function () {
+      const { gitRepository, hash } = await loadFixture(existingObjectFixture);
+
+      await expect(gitRepository.getObjectCid(hash)).to.be.revertedWith("Object not offloaded");
+    });
+
+    it("can't push an empty cid", async function () {
+      const { gitRepository } = await loadFixture(deployGitRepositoryFixture);
+
+      await expect(gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [{ hash: generateHash(true), cid: "" }],
+        deltas: [],
+        refs: [],
+        expected: [],
+      })).to.be.revertedWith("CID is empty");
+    });
+
+    it("can't push an object that already exists", async function () {
+      const { gitRepository, hash } = await loadFixture(existingObjectFixture);
+
+      await expect(gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [{ hash, cid }],
+        deltas: [],
+        refs: [],
+        expected: [],
+      })).to.be.revertedWith("Object already exists");
+    });
+  });
+
+  describe("Delta objects", function () {
+    const delta = "0x0102";
+
+    it("can push one against an existing object", async function () {
+      const { gitRepository, hash: base } = await loadFixture(existingObjectFixture);
+
+      const hash = generateHash(true);
+      await expect(gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [],
+        deltas: [{ hash, base, delta }],
+        refs: [{ name: "refs/heads/main", hash }],
+        expected: [],
+      })).to.emit(gitRepository, "ObjectDeltaAdded").withArgs(hash, base);
+
+      expect(await gitRepository.getObject(hash)).to.equal("0x");
+      expect(await gitRepository.getObjectDelta(hash)).to.deep.equal([ethers.hexlify(base), delta]);
+      expect(await gitRepository.getObjectHashes()).to.include(ethers.hexlify(hash));
+    });
+
+    it("can push one against an object from the same push", async function () {
+      const { gitRepository } = await loadFixture(deployGitRepositoryFixture);
+
+      const data = crypto.randomBytes(100);
+      const base = generateHash(true, data);
+      const hash = generateHash(true);
+      await gitRepository.pushObjectsAndRefs({
+        objects: [{ hash: base, data }],
+        offloaded: [],
+        deltas: [{ hash, base, delta }],
+        refs: [],
+        expected: [],
+      });
+
+      expect(await gitRepository.getObjectDelta(hash)).to.deep.equal([ethers.hexlify(base), delta]);
+    });
+
+    it("can't push one against a missing base", async function () {
+      const { gitRepository } = await loadFixture(deployGitRepositoryFixture);
+
+      await expect(gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [],
+        deltas: [{ hash: generateHash(true), base: generateHash(true), delta }],
+        refs: [],
+        expected: [],
+      })).to.be.revertedWith("Base not found");
+    });
+
+    it("can't push an empty delta", async function () {
+      const { gitRepository, hash: base } = await loadFixture(existingObjectFixture);
+
+      await expect(gitRepository.pushObjectsAndRefs({
+        objects: [],
+        offloaded: [],
+        deltas: [{ hash: generateHash(true), base, delta: "0x" }],
+        refs: [],
+        expected: [],
+      })).to.be.revertedWith("Delta is empty");
+    });
+
+    it("can't get the delta of an on-chain object", async function () {
+      const { gitRepository, hash } = await loadFixture(existingObjectFixture);
+
+      await expect(gitRepository.getObjectDelta(hash)).to.be.revertedWith("Object not a delta");
+    });
   });
 
 
@@ -659,4 +946,88 @@ describe("GitRepository", function () {
       await expect(gitRepository.setDefaultBranch("")).to.be.revertedWith("Default branch is empty");
     });
   });
+
+  describe("Symbolic refs", function () {
+    it("can set HEAD", async function () {
+      const { gitRepository } = await loadFixture(deployGitRepositoryFixture);
+
+      const refName = "refs/heads/some-branch";
+      await expect(gitRepository.setSymbolicRef("HEAD", refName))
+        .to.emit(gitRepository, "SymbolicRefChanged").withArgs("HEAD", refName);
+      expect(await gitRepository.defaultBranchRef()).to.equal(refName);
+    });
+
+    it("can't set a ref other than HEAD", async function () {
+      const { gitRepository } = await loadFixture(deployGitRepositoryFixture);
+
+      await expect(gitRepository.setSymbolicRef("refs/heads/other", "refs/heads/main"))
+        .to.be.revertedWith("Only HEAD is supported");
+    });
+
+    it("can't set an empty target", async function () {
+      const { gitRepository } = await loadFixture(deployGitRepositoryFixture);
+
+      await expect(gitRepository.setSymbolicRef("HEAD", "")).to.be.revertedWith("Name is invalid");
+    });
+
+    it("can't set if not the owner", async function () {
+      const { gitRepository, otherAccount } = await loadFixture(deployGitRepositoryFixture);
+
+      await expect(gitRepository.connect(otherAccount).setSymbolicRef("HEAD", "refs/heads/main"))
+        .to.be.revertedWithCustomError(gitRepository, "OwnableUnauthorizedAccount")
+        .withArgs(otherAccount.address);
+    });
+  });
+
+  describe("Relayed calls", function () {
+    it("can push with the owner's signature", async function () {
+      const { gitRepository, owner, otherAccount } = await loadFixture(deployGitRepositoryFixture);
+
+      const object = crypto.randomBytes(100);
+      const hash = generateHash(true, object);
+      const data = { objects: [{ hash, data: object }], offloaded: [], deltas: [], refs: [{ name: "refs/heads/main", hash }], expected: [] };
+      const payload = ethers.keccak256(ethers.AbiCoder.defaultAbiCoder().encode([PUSH_DATA_TYPE], [data]));
+      const signature = await signRelayed(gitRepository, owner, payload);
+
+      await expect(gitRepository.connect(otherAccount).pushObjectsAndRefsSigned(data, signature))
+        .to.emit(gitRepository, "ObjectAdded").withArgs(hash);
+      expect(await gitRepository.getObject(hash)).to.equal("0x" + object.toString("hex"));
+      expect(await gitRepository.relayNonce()).to.equal(1);
+    });
+
+    it("can set HEAD with the owner's signature", async function () {
+      const { gitRepository, owner, otherAccount } = await loadFixture(deployGitRepositoryFixture);
+
+      const refName = "refs/heads/some-branch";
+      const payload = ethers.keccak256(ethers.AbiCoder.defaultAbiCoder().encode(["string", "string"], ["HEAD", refName]));
+      const signature = await signRelayed(gitRepository, owner, payload);
+
+      await expect(gitRepository.connect(otherAccount).setSymbolicRefSigned("HEAD", refName, signature))
+        .to.emit(gitRepository, "SymbolicRefChanged").withArgs("HEAD", refName);
+      expect(await gitRepository.defaultBranchRef()).to.equal(refName);
+    });
+
+    it("can't relay a call signed by someone else", async function () {
+      const { gitRepository, otherAccount } = await loadFixture(deployGitRepositoryFixture);
+
+      const refName = "refs/heads/some-branch";
+      const payload = ethers.keccak256(ethers.AbiCoder.defaultAbiCoder().encode(["string", "string"], ["HEAD", refName]));
+      const signature = await signRelayed(gitRepository, otherAccount, payload);
+
+      await expect(gitRepository.connect(otherAccount).setSymbolicRefSigned("HEAD", refName, signature))
+        .to.be.revertedWith("Invalid signature");
+    });
+
+    it("can't replay a signature", async function () {
+      const { gitRepository, owner, otherAccount } = await loadFixture(deployGitRepositoryFixture);
+
+      const refName = "refs/heads/some-branch";
+      const payload = ethers.keccak256(ethers.AbiCoder.defaultAbiCoder().encode(["string", "string"], ["HEAD", refName]));
+      const signature = await signRelayed(gitRepository, owner, payload);
+
+      await gitRepository.connect(otherAccount).setSymbolicRefSigned("HEAD", refName, signature);
+      await expect(gitRepository.connect(otherAccount).setSymbolicRefSigned("HEAD", refName, signature))
+        .to.be.revertedWith("Invalid signature");
+    });
+  });
 });